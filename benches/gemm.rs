@@ -0,0 +1,55 @@
+//! Compares [`nn::math::gemm`] against the naive triple loop it replaced.
+//!
+//! Run with `cargo bench --bench gemm`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nn::math::{self, Array, ColMajor, RowMajor};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+nn::index_type!(pub K);
+nn::index_type!(pub M);
+nn::index_type!(pub N);
+
+/// The loop `FullyConnectedLayer::infer` used to run, generalized to a batch.
+fn naive(m: usize, n: usize, k: usize, a: &[f32], b: &[f32], c: &mut [f32]) {
+    for row in 0..m {
+        for col in 0..n {
+            let mut sum = 0.0;
+            for p in 0..k {
+                sum += a[row * k + p] * b[col * k + p];
+            }
+            c[row * n + col] += sum;
+        }
+    }
+}
+
+fn bench_gemm(criterion: &mut Criterion) {
+    let mut rng = StdRng::from_seed([0u8; 32]);
+    let mut group = criterion.benchmark_group("gemm");
+
+    // Square problems followed by the shapes of the first layer of an MNIST MLP at a few batch sizes.
+    for [m, n, k] in [[64, 64, 64], [256, 256, 256], [512, 512, 512], [1, 128, 784], [64, 128, 784]] {
+        let a: Vec<f32> = (0..m * k).map(|_| rng.gen()).collect();
+        let b: Vec<f32> = (0..n * k).map(|_| rng.gen()).collect();
+        let mut c = vec![0.0; m * n];
+
+        let name = format!("{m}x{n}x{k}");
+        group.throughput(Throughput::Elements((2 * m * n * k) as u64));
+
+        group.bench_function(BenchmarkId::new("naive", &name), |bencher| {
+            bencher.iter(|| naive(m, n, k, black_box(&a), black_box(&b), black_box(&mut c)))
+        });
+
+        group.bench_function(BenchmarkId::new("gemm", &name), |bencher| {
+            let a = Array::<_, _, (K, M)>::new(&a[..], ColMajor([k, m]));
+            let b = Array::<_, _, (N, K)>::new(&b[..], RowMajor([n, k]));
+            let mut c = Array::<_, _, (N, M)>::new(&mut c[..], ColMajor([n, m]));
+            bencher.iter(|| math::gemm(black_box(&a), black_box(&b), black_box(&mut c)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_gemm);
+criterion_main!(benches);
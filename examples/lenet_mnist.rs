@@ -0,0 +1,83 @@
+//! Trains a LeNet-5 style convolutional network on MNIST. Only the test set images are in `data/`, so the first 9000
+//! test images are used for training and the last 1000 for evaluation.
+
+use nn::{
+    data::{one_hot, DataLoader},
+    loss::NegativeLogLikelihood,
+    metrics::ConfusionMatrix,
+    mnist,
+    nn::{Conv2d, FullyConnectedLayer, Identity, LogSoftmax, MaxPool2d, Sequential},
+    optim::Sgd,
+    train::{ProgressLogger, Trainer},
+    Result,
+};
+use rand::{distributions::Uniform, rngs::StdRng, Rng, SeedableRng};
+
+const TRAIN_COUNT: usize = 9000;
+const BATCH_SIZE: usize = 32;
+const EPOCHS: usize = 5;
+
+fn main() -> Result<()> {
+    let images = mnist::read_images_from_file("data/t10k-images-idx3-ubyte.gz")?;
+    let labels = mnist::read_labels_from_file("data/t10k-labels-idx1-ubyte.gz")?;
+    let mut pixels: Vec<f32> = images
+        .iter()
+        .flat_map(|image| image.normalized_pixels())
+        .collect();
+    let mut targets = one_hot(&labels, 10);
+    let test_pixels = pixels.split_off(TRAIN_COUNT * 28 * 28);
+    let test_targets = targets.split_off(TRAIN_COUNT * 10);
+
+    let mut rng = StdRng::from_seed([0u8; 32]);
+    let weights = Uniform::new(-0.1, 0.1);
+    // Padding by two gives the first convolution the 32x32 inputs of LeNet-5.
+    let model = Sequential::new(vec![
+        Box::new(
+            Conv2d::new([1, 28, 28], 6, [5, 5], (&mut rng).sample_iter(weights))
+                .with_padding([2, 2]),
+        ),
+        Box::new(MaxPool2d::new([6, 28, 28], [2, 2])),
+        Box::new(Conv2d::new(
+            [6, 14, 14],
+            16,
+            [5, 5],
+            (&mut rng).sample_iter(weights),
+        )),
+        Box::new(MaxPool2d::new([16, 10, 10], [2, 2])),
+        Box::new(FullyConnectedLayer::new(
+            16 * 5 * 5,
+            120,
+            (&mut rng).sample_iter(weights),
+        )),
+        Box::new(FullyConnectedLayer::new(
+            120,
+            84,
+            (&mut rng).sample_iter(weights),
+        )),
+        Box::new(FullyConnectedLayer::with_activation(
+            84,
+            10,
+            (&mut rng).sample_iter(weights),
+            Identity,
+        )),
+        Box::new(LogSoftmax::new(10)),
+    ]);
+    print!("{}", model.summary());
+
+    let mut train = DataLoader::new(pixels, 28 * 28, targets, 10, BATCH_SIZE).with_shuffle(0);
+    let test = DataLoader::new(test_pixels.clone(), 28 * 28, test_targets, 10, 1000);
+    let mut trainer = Trainer::new(model, NegativeLogLikelihood, Sgd::new(0.05)).with_accuracy();
+    trainer.fit(
+        &mut train,
+        Some(&test),
+        EPOCHS,
+        &mut [&mut ProgressLogger::new()],
+    )?;
+
+    let mut model = trainer.into_model();
+    let matrix =
+        ConfusionMatrix::from_predictions(10, &model.predict(&test_pixels), &labels[TRAIN_COUNT..]);
+    print!("\n{matrix}\n{}", matrix.report());
+
+    Ok(())
+}
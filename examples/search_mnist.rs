@@ -0,0 +1,109 @@
+//! Searches for the hyperparameters of a fully connected MNIST classifier and writes the leaderboard to
+//! `leaderboard.csv`. Only the test set images are in `data/`, so the first 9000 test images are used for training and
+//! the last 1000 for validation.
+//!
+//! Run with `cargo run --release --example search_mnist -- [grid|random|halving|hyperband]`, Hyperband by default.
+
+use nn::{
+    data::{one_hot, DataLoader},
+    loss::NegativeLogLikelihood,
+    mnist,
+    nn::{Activation, LogSoftmax, Sequential},
+    optim::Sgd,
+    parallel::DataParallel,
+    search::{Search, SearchSpace, Trial},
+    train::Trainer,
+    Result,
+};
+use rand::{distributions::Uniform, rngs::StdRng, Rng, SeedableRng};
+use std::num::NonZeroUsize;
+
+const TRAIN_COUNT: usize = 9000;
+const SHARD_SIZE: usize = 16;
+const MAX_EPOCHS: usize = 9;
+const ETA: usize = 3;
+const SEED: u64 = 0;
+
+struct Data {
+    train_inputs: Vec<f32>,
+    train_targets: Vec<f32>,
+    validation: DataLoader,
+}
+
+impl Data {
+    /// Trains a model with the hyperparameters of the trial and returns its validation loss.
+    fn objective(&self, trial: &Trial, threads: NonZeroUsize) -> Result<f32> {
+        let hyperparameters = &trial.hyperparameters;
+        let mut rng = StdRng::seed_from_u64(trial.seed);
+        let weights = Uniform::new(-0.1, 0.1);
+        let mut layers = hyperparameters.layers(28 * 28, 10, (&mut rng).sample_iter(weights));
+        layers.push(Box::new(LogSoftmax::new(10)));
+        let model = Sequential::new(layers);
+
+        let parallel = DataParallel::new(&model, threads, NonZeroUsize::new(SHARD_SIZE).unwrap())
+            .with_seed(trial.seed);
+        let mut trainer = Trainer::new(
+            model,
+            NegativeLogLikelihood,
+            Sgd::new(hyperparameters.learning_rate),
+        )
+        .with_parallelism(parallel);
+        let mut train = DataLoader::new(
+            self.train_inputs.clone(),
+            28 * 28,
+            self.train_targets.clone(),
+            10,
+            hyperparameters.batch_size,
+        )
+        .with_shuffle(trial.seed);
+        trainer.fit(&mut train, None, trial.epochs, &mut [])?;
+
+        let loss = trainer.evaluate(&self.validation);
+        println!(
+            "trial {:3}, {} epochs: {loss:.4} with {hyperparameters}",
+            trial.id, trial.epochs
+        );
+        Ok(loss)
+    }
+}
+
+fn main() -> Result<()> {
+    let images = mnist::read_images_from_file("data/t10k-images-idx3-ubyte.gz")?;
+    let labels = mnist::read_labels_from_file("data/t10k-labels-idx1-ubyte.gz")?;
+    let mut pixels: Vec<f32> = images
+        .iter()
+        .flat_map(|image| image.normalized_pixels())
+        .collect();
+    let mut targets = one_hot(&labels, 10);
+    let validation_pixels = pixels.split_off(TRAIN_COUNT * 28 * 28);
+    let validation_targets = targets.split_off(TRAIN_COUNT * 10);
+    let data = Data {
+        train_inputs: pixels,
+        train_targets: targets,
+        validation: DataLoader::new(validation_pixels, 28 * 28, validation_targets, 10, 1000),
+    };
+
+    let space = SearchSpace {
+        learning_rates: vec![0.01, 0.03, 0.1, 0.3],
+        hidden_layers: vec![vec![32], vec![128], vec![128, 64]],
+        activations: vec![Activation::ReLU, Activation::Tanh, Activation::Sigmoid],
+        batch_sizes: vec![32, 128],
+    };
+
+    // Run a trial per core, each on one thread.
+    let threads = std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+    let search = Search::new(SEED).with_threads(threads);
+    let objective = |trial: &Trial| data.objective(trial, NonZeroUsize::MIN);
+    let strategy = std::env::args().nth(1);
+    let leaderboard = match strategy.as_deref().unwrap_or("hyperband") {
+        "grid" => search.grid(&space, MAX_EPOCHS / ETA, objective)?,
+        "random" => search.random(&space, 12, MAX_EPOCHS / ETA, objective)?,
+        "halving" => search.successive_halving(&space, 27, 1, MAX_EPOCHS, ETA, objective)?,
+        "hyperband" => search.hyperband(&space, MAX_EPOCHS, ETA, objective)?,
+        other => return Err(format!("unknown search strategy {other:?}").into()),
+    };
+
+    print!("\n{leaderboard}");
+    leaderboard.save("leaderboard.csv")?;
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! Trains a small vision transformer on MNIST. Only the test set images are in `data/`, so the first 9000 test images
+//! are used for training and the last 1000 for evaluation.
+
+use nn::{
+    data::one_hot,
+    loss::NegativeLogLikelihood,
+    metrics::{self, ConfusionMatrix},
+    mnist,
+    nn::{
+        FullyConnectedLayer, Identity, LogSoftmax, PatchEmbedding, PositionalEncoding, Sequential,
+        TokenMean, TransformerEncoderBlock,
+    },
+    optim::Sgd,
+    parallel::DataParallel,
+    Result,
+};
+use rand::{distributions::Uniform, rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::num::NonZeroUsize;
+
+const PATCH_SIZE: usize = 7;
+const TOKENS: usize = (28 / PATCH_SIZE) * (28 / PATCH_SIZE);
+const DIM: usize = 32;
+const HEADS: usize = 4;
+const HIDDEN: usize = 64;
+const TRAIN_COUNT: usize = 9000;
+const BATCH_SIZE: usize = 64;
+const SHARD_SIZE: usize = 8;
+const EPOCHS: usize = 5;
+
+fn main() -> Result<()> {
+    let images = mnist::read_images_from_file("data/t10k-images-idx3-ubyte.gz")?;
+    let labels = mnist::read_labels_from_file("data/t10k-labels-idx1-ubyte.gz")?;
+    let pixels: Vec<f32> = images
+        .iter()
+        .flat_map(|image| image.normalized_pixels())
+        .collect();
+    let targets = one_hot(&labels, 10);
+    let (train_pixels, test_pixels) = pixels.split_at(TRAIN_COUNT * 28 * 28);
+
+    let mut rng = StdRng::from_seed([0u8; 32]);
+    let weights = Uniform::new(-0.1, 0.1);
+    let mut model = Sequential::new(vec![
+        Box::new(PatchEmbedding::new(
+            [1, 28, 28],
+            PATCH_SIZE,
+            DIM,
+            (&mut rng).sample_iter(weights),
+        )),
+        Box::new(PositionalEncoding::learned(
+            TOKENS,
+            DIM,
+            (&mut rng).sample_iter(weights),
+        )),
+        Box::new(TransformerEncoderBlock::new(
+            TOKENS,
+            DIM,
+            HEADS,
+            HIDDEN,
+            (&mut rng).sample_iter(weights),
+        )),
+        Box::new(TokenMean::new(TOKENS, DIM)),
+        Box::new(FullyConnectedLayer::with_activation(
+            DIM,
+            10,
+            (&mut rng).sample_iter(weights),
+            Identity,
+        )),
+        Box::new(LogSoftmax::new(10)),
+    ]);
+    print!("{}", model.summary());
+
+    let mut parallel =
+        DataParallel::with_available_parallelism(&model, NonZeroUsize::new(SHARD_SIZE).unwrap());
+    let mut optimizer = Sgd::new(0.5);
+    let mut gradients = vec![0.0; model.parameter_count()];
+    let mut order: Vec<usize> = (0..TRAIN_COUNT).collect();
+
+    for epoch in 0..EPOCHS {
+        order.shuffle(&mut rng);
+        let mut total_loss = 0.0;
+        for batch in order.chunks(BATCH_SIZE) {
+            let inputs: Vec<f32> = batch
+                .iter()
+                .flat_map(|&i| &train_pixels[i * 28 * 28..][..28 * 28])
+                .copied()
+                .collect();
+            let batch_targets: Vec<f32> = batch
+                .iter()
+                .flat_map(|&i| &targets[i * 10..][..10])
+                .copied()
+                .collect();
+
+            total_loss += parallel.gradients(
+                &mut model,
+                &inputs,
+                &batch_targets,
+                &NegativeLogLikelihood,
+                &mut gradients,
+            ) * batch.len() as f32;
+            model.update(&mut optimizer, &gradients);
+        }
+
+        let probabilities = model.predict_proba(test_pixels);
+        println!(
+            "epoch: {epoch}, loss: {:.4}, test accuracy: {:.3}, top-3 accuracy: {:.3}",
+            total_loss / TRAIN_COUNT as f32,
+            metrics::top_k_accuracy(&probabilities, &labels[TRAIN_COUNT..], 1),
+            metrics::top_k_accuracy(&probabilities, &labels[TRAIN_COUNT..], 3),
+        );
+    }
+
+    let matrix =
+        ConfusionMatrix::from_predictions(10, &model.predict(test_pixels), &labels[TRAIN_COUNT..]);
+    print!("\n{matrix}\n{}", matrix.report());
+
+    Ok(())
+}
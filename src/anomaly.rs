@@ -0,0 +1,186 @@
+//! Finding where values stop being finite.
+//!
+//! With anomaly detection on, see [`Sequential::set_anomaly_detection`](crate::nn::Sequential), a model checks the
+//! outputs of every layer on the way forward, and the gradients of the loss with respect to the outputs, the inputs
+//! and the parameters of every layer on the way back, and records the first [`Anomaly`] it finds. Checking costs a
+//! pass over every value, so it is off by default. [`Trainer::with_anomaly_detection`](crate::train::Trainer) stops
+//! training at the first anomaly with a [`TrainingAnomaly`].
+
+use std::{error::Error, fmt, path::PathBuf};
+
+/// Which values of a layer were not finite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Values {
+    /// The outputs of the forward pass.
+    Outputs,
+    /// The gradient of the loss with respect to the outputs of the last layer, as computed by the loss.
+    LossGradients,
+    /// The gradient of the loss with respect to the inputs of the layer.
+    InputGradients,
+    /// The gradient of the loss with respect to the parameters of the layer.
+    ParameterGradients,
+}
+
+impl fmt::Display for Values {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Values::Outputs => "outputs",
+            Values::LossGradients => "loss gradients",
+            Values::InputGradients => "input gradients",
+            Values::ParameterGradients => "parameter gradients",
+        })
+    }
+}
+
+/// The first non-finite value found in a forward and backward pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    /// The index of the layer in the model.
+    pub layer: usize,
+    pub name: String,
+    pub values: Values,
+    /// The index in the batch of the first sample with a non-finite value. Parameter gradients are summed over the
+    /// batch, so they have none.
+    pub sample: Option<usize>,
+    /// The first non-finite value: NaN or an infinity.
+    pub value: f32,
+}
+
+impl Anomaly {
+    /// Looks for a non-finite value in `values`, which hold `per_sample` values for every sample of a batch, or are not
+    /// split by sample if `per_sample` is `None`.
+    pub(crate) fn find(
+        layer: usize,
+        name: impl FnOnce() -> String,
+        kind: Values,
+        values: &[f32],
+        per_sample: Option<usize>,
+    ) -> Option<Self> {
+        let index = values.iter().position(|value| !value.is_finite())?;
+        Some(Self {
+            layer,
+            name: name(),
+            values: kind,
+            sample: per_sample.map(|count| index / count.max(1)),
+            value: values[index],
+        })
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in the {} of layer {} ({})",
+            self.value, self.values, self.layer, self.name
+        )?;
+        if let Some(sample) = self.sample {
+            write!(f, " for sample {sample} of the batch")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Anomaly {}
+
+/// The error with which [`Trainer::fit`](crate::train::Trainer::fit) stops at an anomaly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingAnomaly {
+    pub anomaly: Anomaly,
+    /// The step, epoch and batch as in [`BatchStats`](crate::train::BatchStats).
+    pub step: usize,
+    pub epoch: usize,
+    pub batch: usize,
+    /// The directory the batch and the parameters were written to, if the trainer dumps them.
+    pub dump: Option<PathBuf>,
+}
+
+impl fmt::Display for TrainingAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at step {} (epoch {}, batch {})",
+            self.anomaly, self.step, self.epoch, self.batch
+        )?;
+        if let Some(dump) = &self.dump {
+            write!(f, ", dumped to {}", dump.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for TrainingAnomaly {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        loss::MeanSquaredError,
+        nn::{FullyConnectedLayer, Identity, Sequential},
+    };
+
+    fn model() -> Sequential {
+        Sequential::new(vec![
+            // ReLU turns NaN into zero, so the first layer is linear to let it through.
+            Box::new(FullyConnectedLayer::with_activation(
+                1,
+                2,
+                [1.0, 1.0, 0.0, 0.0],
+                Identity,
+            )),
+            Box::new(FullyConnectedLayer::with_activation(
+                2,
+                1,
+                [1.0, 1.0, 0.0],
+                Identity,
+            )),
+        ])
+    }
+
+    #[test]
+    fn sequential() {
+        let mut model = model();
+        let mut gradients = vec![0.0; model.parameter_count()];
+        model.gradients(
+            &[1.0, f32::NAN],
+            &[0.0, 0.0],
+            &MeanSquaredError,
+            &mut gradients,
+        );
+        assert_eq!(model.take_anomaly(), None, "detection is off by default");
+
+        model.set_anomaly_detection(true);
+        gradients.fill(0.0);
+        model.gradients(
+            &[1.0, 2.0, f32::NAN],
+            &[0.0; 3],
+            &MeanSquaredError,
+            &mut gradients,
+        );
+        let anomaly = model.take_anomaly().unwrap();
+        assert_eq!(
+            (anomaly.layer, anomaly.values, anomaly.sample),
+            (0, Values::Outputs, Some(2))
+        );
+        assert!(anomaly.value.is_nan());
+        assert_eq!(
+            anomaly.to_string(),
+            "NaN in the outputs of layer 0 (FullyConnectedLayer<Identity>) for sample 2 of the batch"
+        );
+        assert_eq!(model.take_anomaly(), None);
+
+        // Finite outputs with a target that is not.
+        gradients.fill(0.0);
+        model.gradients(
+            &[1.0, 2.0],
+            &[0.0, f32::INFINITY],
+            &MeanSquaredError,
+            &mut gradients,
+        );
+        let anomaly = model.take_anomaly().unwrap();
+        assert_eq!(
+            (anomaly.layer, anomaly.values, anomaly.sample),
+            (1, Values::LossGradients, Some(1))
+        );
+    }
+}
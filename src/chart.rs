@@ -0,0 +1,259 @@
+//! Line charts drawn with characters, for watching loss curves in a terminal.
+
+use crate::{
+    nn::Sequential,
+    train::{Callback, EpochStats},
+    Result,
+};
+use std::{fmt, ops::ControlFlow};
+
+const MARKERS: [char; 5] = ['*', '+', 'o', 'x', '#'];
+
+/// A chart of one or more series of values over their index, such as the loss of every epoch. Every series is drawn
+/// with its own marker, and points are connected by straight lines of that marker. Values that are not finite, or
+/// not positive on a logarithmic scale, are left out and break the line.
+#[derive(Debug, Clone)]
+pub struct Chart {
+    width: usize,
+    height: usize,
+    log_scale: bool,
+    series: Vec<(String, Vec<f32>)>,
+}
+
+impl Chart {
+    /// Creates a chart whose plot area is `width` columns by `height` rows, not counting the axes and labels.
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width >= 2 && height >= 2, "chart is too small");
+        Self {
+            width,
+            height,
+            log_scale: false,
+            series: Vec::new(),
+        }
+    }
+
+    /// Uses a logarithmic vertical axis, which suits losses that fall over orders of magnitude.
+    pub fn with_log_scale(mut self) -> Self {
+        self.log_scale = true;
+        self
+    }
+
+    pub fn with_series<S: Into<String>>(mut self, name: S, values: &[f32]) -> Self {
+        self.add_series(name, values);
+        self
+    }
+
+    pub fn add_series<S: Into<String>>(&mut self, name: S, values: &[f32]) {
+        assert!(self.series.len() < MARKERS.len(), "too many series");
+        self.series.push((name.into(), values.to_vec()));
+    }
+
+    /// The value as drawn, or `None` if it cannot be drawn.
+    fn scaled(&self, value: f32) -> Option<f32> {
+        if self.log_scale {
+            (value > 0.0 && value.is_finite()).then(|| value.log10())
+        } else {
+            value.is_finite().then_some(value)
+        }
+    }
+
+    fn unscaled(&self, value: f32) -> f32 {
+        if self.log_scale {
+            10f32.powf(value)
+        } else {
+            value
+        }
+    }
+}
+
+impl fmt::Display for Chart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scaled = self
+            .series
+            .iter()
+            .flat_map(|(_, values)| values.iter().filter_map(|&value| self.scaled(value)));
+        let (mut min, mut max) = scaled
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+                (min.min(value), max.max(value))
+            });
+        if min > max {
+            return writeln!(f, "(no data)");
+        }
+        if min == max {
+            min -= 0.5;
+            max += 0.5;
+        }
+
+        let (width, height) = (self.width, self.height);
+        let mut grid = vec![vec![' '; width]; height];
+        let len = self
+            .series
+            .iter()
+            .map(|(_, values)| values.len())
+            .max()
+            .unwrap_or(0);
+        let column = |index: usize| {
+            if len <= 1 {
+                0.0
+            } else {
+                index as f32 * (width - 1) as f32 / (len - 1) as f32
+            }
+        };
+        let row = |value: f32| (max - value) / (max - min) * (height - 1) as f32;
+
+        for ((_, values), marker) in self.series.iter().zip(MARKERS) {
+            let mut previous: Option<(f32, f32)> = None;
+            for (index, &value) in values.iter().enumerate() {
+                let Some(value) = self.scaled(value) else {
+                    previous = None;
+                    continue;
+                };
+                let point = (column(index), row(value));
+                let (start, end) = (previous.unwrap_or(point), point);
+                // Step along the segment from the previous point so that the line has no gaps.
+                let steps = (end.0 - start.0).abs().max((end.1 - start.1).abs()).ceil() as usize;
+                for step in 0..=steps {
+                    let t = if steps == 0 {
+                        1.0
+                    } else {
+                        step as f32 / steps as f32
+                    };
+                    let x = (start.0 + t * (end.0 - start.0)).round() as usize;
+                    let y = (start.1 + t * (end.1 - start.1)).round() as usize;
+                    grid[y.min(height - 1)][x.min(width - 1)] = marker;
+                }
+                previous = Some(point);
+            }
+        }
+
+        // Label the top, middle and bottom rows.
+        let labels: Vec<String> = (0..height)
+            .map(|y| {
+                if y == 0 || y == height - 1 || y == (height - 1) / 2 {
+                    let value = max - y as f32 / (height - 1) as f32 * (max - min);
+                    format_value(self.unscaled(value))
+                } else {
+                    String::new()
+                }
+            })
+            .collect();
+        let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+
+        for (label, cells) in labels.iter().zip(&grid) {
+            let tick = if label.is_empty() { '│' } else { '┤' };
+            let line: String = cells.iter().collect();
+            writeln!(f, "{label:>label_width$} {tick}{}", line.trim_end())?;
+        }
+        writeln!(f, "{:label_width$} └{}", "", "─".repeat(width))?;
+        let last = len.saturating_sub(1).to_string();
+        writeln!(f, "{:label_width$}  0{last:>1$}", "", width - 1)?;
+
+        let legend: Vec<String> = self
+            .series
+            .iter()
+            .zip(MARKERS)
+            .map(|((name, _), marker)| format!("{marker} {name}"))
+            .collect();
+        writeln!(f, "{:label_width$}  {}", "", legend.join("   "))
+    }
+}
+
+fn format_value(value: f32) -> String {
+    if value != 0.0 && !(1e-3..1e4).contains(&value.abs()) {
+        format!("{value:.2e}")
+    } else {
+        format!("{value:.4}")
+    }
+}
+
+/// A [`Callback`] that prints a chart of the training loss, and the validation loss if there is one, every few epochs
+/// on a logarithmic scale.
+#[derive(Debug, Clone)]
+pub struct LossChart {
+    epoch_interval: usize,
+    width: usize,
+    height: usize,
+    train_losses: Vec<f32>,
+    validation_losses: Vec<f32>,
+}
+
+impl LossChart {
+    /// Prints the chart after every `epochs`th epoch.
+    pub fn new(epochs: usize) -> Self {
+        assert!(epochs > 0, "epoch interval must be positive");
+        Self {
+            epoch_interval: epochs,
+            width: 60,
+            height: 12,
+            train_losses: Vec::new(),
+            validation_losses: Vec::new(),
+        }
+    }
+
+    pub fn with_size(mut self, width: usize, height: usize) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// The chart of the losses so far.
+    pub fn chart(&self) -> Chart {
+        let mut chart = Chart::new(self.width, self.height)
+            .with_log_scale()
+            .with_series("train", &self.train_losses);
+        if !self.validation_losses.is_empty() {
+            chart.add_series("validation", &self.validation_losses);
+        }
+        chart
+    }
+}
+
+impl Callback for LossChart {
+    fn on_epoch_end(&mut self, stats: &EpochStats, _model: &Sequential) -> Result<ControlFlow<()>> {
+        self.train_losses.push(stats.train_loss);
+        if let Some(loss) = stats.validation_loss {
+            self.validation_losses.push(loss);
+        }
+        if (stats.epoch + 1).is_multiple_of(self.epoch_interval) {
+            print!("{}", self.chart());
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_lines() {
+        let chart = Chart::new(5, 3)
+            .with_series("up", &[0.0, 1.0, 2.0])
+            .with_series("flat", &[1.0, f32::NAN, 1.0]);
+        let text = chart.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "2.0000 ┤    *",
+                "1.0000 ┤+ **+",
+                "0.0000 ┤**",
+                "       └─────",
+                "        0   2",
+                "        * up   + flat",
+            ]
+        );
+    }
+
+    #[test]
+    fn log_scale() {
+        let text = Chart::new(10, 3)
+            .with_log_scale()
+            .with_series("loss", &[100.0, 10.0, 0.0, 1.0])
+            .to_string();
+        assert!(text.starts_with("100.0000 ┤*"));
+        assert!(text.contains("10.0000 ┤"));
+        assert!(text.contains("1.0000 ┤"));
+        assert_eq!(Chart::new(2, 2).to_string(), "(no data)\n");
+    }
+}
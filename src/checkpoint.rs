@@ -0,0 +1,219 @@
+//! Snapshots of the parameters and state of a model, in memory or on disk.
+//!
+//! The file format is little-endian: the magic bytes `NNCK`, a `u32` version, and then the parameters and the state,
+//! each as a `u64` count followed by that many `f32` values. Since version 2 they are followed by the seeds of the run:
+//! a `u8` that is 1 if there are any, and then the `u64` master seed and a `u64` count of streams, each as a `u64`
+//! length and that many bytes of UTF-8 name followed by its `u64` seed. Version 1 files are still read.
+
+use crate::{nn::Sequential, seed::Seeds, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"NNCK";
+const VERSION: u32 = 2;
+
+/// The parameters and the state of a [`Sequential`], laid out as by [`Sequential::parameters`] and
+/// [`Sequential::state`], and optionally the seeds of the run that produced them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub parameters: Vec<f32>,
+    pub state: Vec<f32>,
+    pub seeds: Option<Seeds>,
+}
+
+impl Checkpoint {
+    pub fn of(model: &Sequential) -> Self {
+        Self {
+            parameters: model.parameters(),
+            state: model.state(),
+            seeds: None,
+        }
+    }
+
+    /// Records the master seed and the streams handed out by `seeds` so far.
+    pub fn with_seeds(mut self, seeds: &Seeds) -> Self {
+        self.seeds = Some(seeds.clone());
+        self
+    }
+
+    /// Copies the parameters and the state into `model`, which must have the architecture of the model the checkpoint
+    /// was taken of. Returns an error and leaves `model` alone if the number of parameters or state values differs.
+    pub fn restore(&self, model: &mut Sequential) -> Result<()> {
+        if self.parameters.len() != model.parameter_count()
+            || self.state.len() != model.state_count()
+        {
+            return Err(format!(
+                "the checkpoint has {} parameters and {} state values, but the model has {} and {}",
+                self.parameters.len(),
+                self.state.len(),
+                model.parameter_count(),
+                model.state_count()
+            )
+            .into());
+        }
+        model.set_parameters(&self.parameters);
+        model.set_state(&self.state);
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u32::<LittleEndian>(VERSION)?;
+        for values in [&self.parameters, &self.state] {
+            writer.write_u64::<LittleEndian>(values.len() as u64)?;
+            for &value in values {
+                writer.write_f32::<LittleEndian>(value)?;
+            }
+        }
+
+        writer.write_u8(self.seeds.is_some() as u8)?;
+        if let Some(seeds) = &self.seeds {
+            writer.write_u64::<LittleEndian>(seeds.master())?;
+            writer.write_u64::<LittleEndian>(seeds.streams().count() as u64)?;
+            for (name, seed) in seeds.streams() {
+                writer.write_u64::<LittleEndian>(name.len() as u64)?;
+                writer.write_all(name.as_bytes())?;
+                writer.write_u64::<LittleEndian>(seed)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err("not a checkpoint".into());
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(format!("unsupported checkpoint version {version}").into());
+        }
+
+        let mut read_values = || -> Result<Vec<f32>> {
+            let len = reader.read_u64::<LittleEndian>()?;
+            let bytes = read_bytes(reader, len.checked_mul(4).ok_or("checkpoint is corrupt")?)?;
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect())
+        };
+        let parameters = read_values()?;
+        let state = read_values()?;
+
+        let seeds = if version >= 2 && reader.read_u8()? == 1 {
+            let master = reader.read_u64::<LittleEndian>()?;
+            let count = reader.read_u64::<LittleEndian>()?;
+            let mut streams = Vec::new();
+            for _ in 0..count {
+                let len = reader.read_u64::<LittleEndian>()?;
+                let name = read_bytes(reader, len)?;
+                streams.push((String::from_utf8(name)?, reader.read_u64::<LittleEndian>()?));
+            }
+            Some(Seeds::with_streams(master, streams)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            parameters,
+            state,
+            seeds,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// Reads `len` bytes, growing the buffer as they arrive rather than trusting a length read from the file, so that a
+/// corrupt length fails with an error instead of a huge allocation.
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err("checkpoint ends early".into());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{BatchNorm, FullyConnectedLayer};
+
+    #[test]
+    fn round_trip() {
+        let model = || {
+            Sequential::new(vec![
+                Box::new(FullyConnectedLayer::new(2, 3, (0..).map(|i| i as f32))),
+                Box::new(BatchNorm::new_1d(3)),
+            ])
+        };
+        let mut trained = model();
+        trained.forward(&[1.0, 2.0, 3.0, 4.0]);
+        let checkpoint = Checkpoint::of(&trained);
+        assert_eq!(checkpoint.state.len(), 6);
+
+        let mut bytes = Vec::new();
+        checkpoint.write(&mut bytes).unwrap();
+        let read = Checkpoint::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, checkpoint);
+
+        let mut restored = model();
+        read.restore(&mut restored).unwrap();
+        assert_eq!(restored.parameters(), trained.parameters());
+        assert_eq!(restored.state(), trained.state());
+
+        // A checkpoint of another architecture is rejected.
+        let mut other = Sequential::new(vec![Box::new(FullyConnectedLayer::new(
+            3,
+            3,
+            (0..).map(|i| i as f32),
+        ))]);
+        assert!(read.restore(&mut other).is_err());
+        assert!(Checkpoint {
+            state: Vec::new(),
+            ..read.clone()
+        }
+        .restore(&mut restored)
+        .is_err());
+
+        assert!(Checkpoint::read(&mut &b"NOPE"[..]).is_err());
+
+        // The seeds of the run survive the round trip.
+        let mut seeds = Seeds::new(7);
+        seeds.seed(Seeds::SHUFFLE);
+        let seeded = checkpoint.clone().with_seeds(&seeds);
+        let mut bytes = Vec::new();
+        seeded.write(&mut bytes).unwrap();
+        assert_eq!(Checkpoint::read(&mut bytes.as_slice()).unwrap(), seeded);
+
+        // Version 1 files have no seeds.
+        let mut old = Vec::new();
+        checkpoint.write(&mut old).unwrap();
+        old[4..8].copy_from_slice(&1u32.to_le_bytes());
+        old.pop();
+        assert_eq!(Checkpoint::read(&mut old.as_slice()).unwrap(), checkpoint);
+        assert!(Checkpoint::read(&mut &bytes[..bytes.len() - 1]).is_err());
+
+        // A corrupt count is an error rather than an allocation of that size.
+        let mut corrupt = bytes.clone();
+        corrupt[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Checkpoint::read(&mut corrupt.as_slice()).is_err());
+        corrupt[8..16].copy_from_slice(&(u64::MAX / 8).to_le_bytes());
+        assert!(Checkpoint::read(&mut corrupt.as_slice()).is_err());
+    }
+}
@@ -1,6 +1,344 @@
-use rand::Rng;
+use crate::Result;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::fmt;
 
 pub fn sample_sine<R>(rng: &mut R) -> (f32, f32) where R: Rng + ?Sized {
     let x = rng.gen();
     (x, x.sin())
 }
+
+/// Encodes every label as a vector of `classes` values that is one at the index of the label and zero elsewhere, one
+/// label after the other.
+pub fn one_hot(labels: &[u8], classes: usize) -> Vec<f32> {
+    let mut targets = vec![0.0; labels.len() * classes];
+    for (target, &label) in targets.chunks_exact_mut(classes).zip(labels) {
+        assert!((label as usize) < classes, "label {label} out of range for {classes} classes");
+        target[label as usize] = 1.0;
+    }
+    targets
+}
+
+/// Mixes targets of `classes` values each with the uniform distribution, so that a one-hot target gets
+/// `1 - smoothing + smoothing / classes` for its label and `smoothing / classes` for the other classes.
+pub fn smooth_labels(targets: &mut [f32], classes: usize, smoothing: f32) {
+    assert_eq!(targets.len() % classes, 0);
+    assert!((0.0..=1.0).contains(&smoothing), "smoothing must be in 0..=1");
+    let uniform = smoothing / classes as f32;
+    for target in targets {
+        *target = *target * (1.0 - smoothing) + uniform;
+    }
+}
+
+/// Serves an in-memory dataset in batches of `batch_size` samples, the last of which may be smaller. Inputs and targets
+/// are stored one sample after the other.
+#[derive(Debug, Clone)]
+pub struct DataLoader {
+    inputs: Vec<f32>,
+    targets: Vec<f32>,
+    input_count: usize,
+    target_count: usize,
+    batch_size: usize,
+    order: Vec<usize>,
+    rng: Option<StdRng>,
+}
+
+impl DataLoader {
+    pub fn new(inputs: Vec<f32>, input_count: usize, targets: Vec<f32>, target_count: usize, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be positive");
+        assert!(input_count > 0, "samples need at least one input");
+        let len = inputs.len() / input_count;
+        assert_eq!(len * input_count, inputs.len());
+        assert_eq!(len * target_count, targets.len(), "every sample needs a target");
+
+        Self { inputs, targets, input_count, target_count, batch_size, order: (0..len).collect(), rng: None }
+    }
+
+    /// Makes [`DataLoader::shuffle`] put the samples in a random order drawn from a generator seeded with `seed`.
+    pub fn with_shuffle(mut self, seed: u64) -> Self {
+        self.rng = Some(StdRng::seed_from_u64(seed));
+        self
+    }
+
+    /// The number of samples.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn batch_count(&self) -> usize {
+        self.len().div_ceil(self.batch_size)
+    }
+
+    pub fn input_count(&self) -> usize {
+        self.input_count
+    }
+
+    pub fn target_count(&self) -> usize {
+        self.target_count
+    }
+
+    /// Puts the samples in a new random order if the loader shuffles, and does nothing otherwise. Call it at the start
+    /// of every epoch.
+    pub fn shuffle(&mut self) {
+        if let Some(rng) = &mut self.rng {
+            self.order.shuffle(rng);
+        }
+    }
+
+    /// A loader with the samples at `indices` in that order and the same batch size, which does not shuffle.
+    pub fn subset(&self, indices: &[usize]) -> DataLoader {
+        let (inputs, targets) = self.gather(indices);
+        DataLoader::new(inputs, self.input_count, targets, self.target_count, self.batch_size)
+    }
+
+    /// The inputs and targets of every batch, in the current order.
+    pub fn batches(&self) -> impl Iterator<Item = (Vec<f32>, Vec<f32>)> + '_ {
+        self.order.chunks(self.batch_size).map(|indices| self.gather(indices))
+    }
+
+    /// The inputs and targets of the samples at `indices`.
+    fn gather(&self, indices: &[usize]) -> (Vec<f32>, Vec<f32>) {
+        let gather = |values: &[f32], count: usize| {
+            indices.iter().flat_map(|&index| values[index * count..][..count].iter().copied()).collect()
+        };
+        (gather(&self.inputs, self.input_count), gather(&self.targets, self.target_count))
+    }
+}
+
+/// One fold of a k-fold split, as indices of samples in ascending order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+    pub train: Vec<usize>,
+    pub validation: Vec<usize>,
+}
+
+impl Fold {
+    /// The fold that validates on `validation` and trains on the other samples up to `len`.
+    fn new(len: usize, mut validation: Vec<usize>) -> Self {
+        validation.sort_unstable();
+        let mut in_validation = vec![false; len];
+        for &index in &validation {
+            in_validation[index] = true;
+        }
+        let train = (0..len).filter(|&index| !in_validation[index]).collect();
+        Self { train, validation }
+    }
+}
+
+/// Splits `len` samples into `k` folds whose validation sets differ in size by at most one. Every sample is validated
+/// on in exactly one fold and trained on in all others. Samples are assigned in order, or in a random order drawn
+/// from `seed` if given.
+pub fn k_fold(len: usize, k: usize, seed: Option<u64>) -> Vec<Fold> {
+    assert!(k >= 2 && k <= len, "need at least 2 folds and a sample per fold");
+    let mut order: Vec<usize> = (0..len).collect();
+    if let Some(seed) = seed {
+        order.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+
+    let mut start = 0;
+    (0..k)
+        .map(|fold| {
+            let end = start + len / k + (fold < len % k) as usize;
+            let validation = order[start..end].to_vec();
+            start = end;
+            Fold::new(len, validation)
+        })
+        .collect()
+}
+
+/// Splits samples into `k` folds like [`k_fold`], but so that the validation set of every fold holds about the same
+/// share of every class in `labels`. This keeps rare classes from missing in some folds. Regression targets can be
+/// stratified by their [`quantile_bins`].
+pub fn stratified_k_fold<L: Ord>(labels: &[L], k: usize, seed: Option<u64>) -> Vec<Fold> {
+    assert!(k >= 2 && k <= labels.len(), "need at least 2 folds and a sample per fold");
+    let mut order: Vec<usize> = (0..labels.len()).collect();
+    if let Some(seed) = seed {
+        order.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+    // Group the samples by class, keeping their order within each class.
+    order.sort_by(|&a, &b| labels[a].cmp(&labels[b]));
+
+    // Deal the samples to the folds in turn, continuing across classes so that the folds stay balanced in size.
+    let mut validation = vec![Vec::new(); k];
+    for (position, index) in order.into_iter().enumerate() {
+        validation[position % k].push(index);
+    }
+    validation.into_iter().map(|validation| Fold::new(labels.len(), validation)).collect()
+}
+
+/// Assigns every value to one of `bins` bins of about equal size by its rank, for stratifying continuous targets.
+pub fn quantile_bins(values: &[f32], bins: usize) -> Vec<usize> {
+    assert!(bins > 0, "need at least one bin");
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+
+    let mut assigned = vec![0; values.len()];
+    for (rank, index) in order.into_iter().enumerate() {
+        assigned[index] = rank * bins / values.len();
+    }
+    assigned
+}
+
+/// The metrics of every fold of a cross-validation, see [`cross_validate`]. Displays the mean and standard deviation of
+/// every metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossValidation {
+    /// The name of every metric and its value for every fold.
+    pub metrics: Vec<(&'static str, Vec<f32>)>,
+}
+
+impl CrossValidation {
+    pub fn values(&self, name: &str) -> Option<&[f32]> {
+        self.metrics.iter().find(|(metric, _)| *metric == name).map(|(_, values)| values.as_slice())
+    }
+
+    pub fn mean(&self, name: &str) -> Option<f32> {
+        self.values(name).map(|values| values.iter().sum::<f32>() / values.len().max(1) as f32)
+    }
+
+    /// The population standard deviation of the metric over the folds.
+    pub fn std(&self, name: &str) -> Option<f32> {
+        let mean = self.mean(name)?;
+        self.values(name).map(|values| {
+            let variance = values.iter().map(|value| (value - mean) * (value - mean)).sum::<f32>();
+            (variance / values.len().max(1) as f32).sqrt()
+        })
+    }
+}
+
+impl fmt::Display for CrossValidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.metrics.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, _) in &self.metrics {
+            let (mean, std) = (self.mean(name).unwrap(), self.std(name).unwrap());
+            writeln!(f, "{name:width$}  {mean:.6} ± {std:.6}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Calls `train_and_evaluate` with the index of every fold and loaders for its training and validation samples, and
+/// collects the named metrics it returns. Every fold must return the same metrics in the same order. The loaders
+/// keep the batch size of `data` and do not shuffle; call [`DataLoader::with_shuffle`] on the training loader to
+/// shuffle it.
+pub fn cross_validate<F>(data: &DataLoader, folds: &[Fold], mut train_and_evaluate: F) -> Result<CrossValidation>
+where
+    F: FnMut(usize, DataLoader, DataLoader) -> Result<Vec<(&'static str, f32)>>,
+{
+    let mut metrics: Vec<(&'static str, Vec<f32>)> = Vec::new();
+    for (index, fold) in folds.iter().enumerate() {
+        let results = train_and_evaluate(index, data.subset(&fold.train), data.subset(&fold.validation))?;
+        if index == 0 {
+            metrics = results.iter().map(|&(name, _)| (name, Vec::with_capacity(folds.len()))).collect();
+        }
+        if results.len() != metrics.len() || results.iter().zip(&metrics).any(|((a, _), (b, _))| a != b) {
+            return Err(format!("fold {index} returned different metrics than fold 0").into());
+        }
+        for ((_, value), (_, values)) in results.into_iter().zip(&mut metrics) {
+            values.push(value);
+        }
+    }
+    Ok(CrossValidation { metrics })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_hot_and_smoothing() {
+        let mut targets = one_hot(&[2, 0], 3);
+        assert_eq!(targets, [0.0, 0.0, 1.0, 1.0, 0.0, 0.0]);
+
+        smooth_labels(&mut targets, 3, 0.3);
+        for (target, expected) in targets.iter().zip([0.1, 0.1, 0.8, 0.8, 0.1, 0.1]) {
+            assert!((target - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn data_loader() {
+        let inputs: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        let targets: Vec<f32> = inputs.iter().map(|x| -x).collect();
+        let loader = DataLoader::new(inputs.clone(), 2, targets.clone(), 2, 2);
+        assert_eq!((loader.len(), loader.batch_count()), (5, 3));
+
+        let batches: Vec<_> = loader.batches().collect();
+        assert_eq!(batches[0].0, [0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(batches[2], (vec![8.0, 9.0], vec![-8.0, -9.0]));
+
+        // Shuffling keeps inputs and targets together and is reproducible.
+        let shuffled = || {
+            let mut loader = DataLoader::new(inputs.clone(), 2, targets.clone(), 2, 2).with_shuffle(3);
+            loader.shuffle();
+            loader.batches().collect::<Vec<_>>()
+        };
+        let batches = shuffled();
+        assert_eq!(batches, shuffled());
+        let mut seen: Vec<f32> = Vec::new();
+        for (inputs, targets) in batches {
+            assert!(inputs.iter().zip(&targets).all(|(x, t)| *t == -x));
+            seen.extend(inputs);
+        }
+        assert_ne!(seen, inputs);
+        seen.sort_by(f32::total_cmp);
+        assert_eq!(seen, inputs);
+    }
+
+    #[test]
+    fn folds() {
+        let folds = k_fold(10, 3, None);
+        assert_eq!(folds[0].validation, [0, 1, 2, 3]);
+        assert_eq!(folds[2], Fold { train: (0..7).collect(), validation: vec![7, 8, 9] });
+
+        // Every sample is validated on exactly once, shuffled or not.
+        for folds in [folds, k_fold(10, 3, Some(1)), stratified_k_fold(&[0; 10], 3, Some(1))] {
+            let mut validated: Vec<usize> = folds.iter().flat_map(|fold| fold.validation.clone()).collect();
+            validated.sort_unstable();
+            assert_eq!(validated, (0..10).collect::<Vec<_>>());
+            assert!(folds.iter().all(|fold| fold.train.len() + fold.validation.len() == 10));
+        }
+        assert_ne!(k_fold(10, 3, Some(1)), k_fold(10, 3, None));
+
+        // Two samples of class 1 among ten end up in different folds.
+        let labels = [0u8, 0, 0, 1, 0, 0, 0, 0, 1, 0];
+        let folds = stratified_k_fold(&labels, 2, Some(3));
+        for fold in &folds {
+            assert_eq!(fold.validation.len(), 5);
+            assert_eq!(fold.validation.iter().filter(|&&index| labels[index] == 1).count(), 1);
+        }
+
+        assert_eq!(quantile_bins(&[0.5, -1.0, 3.0, 2.0], 2), [0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn cross_validation() {
+        let inputs: Vec<f32> = (0..6).map(|i| i as f32).collect();
+        let data = DataLoader::new(inputs.clone(), 1, inputs, 1, 4);
+        let subset = data.subset(&[4, 1]);
+        assert_eq!(subset.batches().collect::<Vec<_>>(), [(vec![4.0, 1.0], vec![4.0, 1.0])]);
+
+        // Predicting the mean of the training targets.
+        let result = cross_validate(&data, &k_fold(6, 2, None), |_, train, validation| {
+            let (_, targets): (Vec<_>, Vec<_>) = train.batches().unzip();
+            let targets = targets.concat();
+            let mean = targets.iter().sum::<f32>() / targets.len() as f32;
+            let (_, validation): (Vec<_>, Vec<_>) = validation.batches().unzip();
+            let error = validation.concat().iter().map(|target| (target - mean).abs()).sum::<f32>() / 3.0;
+            Ok(vec![("error", error), ("mean", mean)])
+        })
+        .unwrap();
+        assert_eq!(result.values("mean"), Some([4.0, 1.0].as_slice()));
+        assert_eq!((result.mean("error"), result.std("error")), (Some(3.0), Some(0.0)));
+        assert_eq!((result.mean("mean"), result.std("mean")), (Some(2.5), Some(1.5)));
+        assert_eq!(result.to_string(), "error  3.000000 ± 0.000000\nmean   2.500000 ± 1.500000\n");
+
+        let error = cross_validate(&data, &k_fold(6, 2, None), |fold, _, _| {
+            Ok(if fold == 0 { vec![("a", 1.0)] } else { vec![("b", 1.0)] })
+        });
+        assert!(error.is_err());
+    }
+}
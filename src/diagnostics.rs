@@ -0,0 +1,152 @@
+//! Per-layer statistics of a training step, for finding out why training diverges or stalls.
+//!
+//! Exploding gradients show up as gradient norms that grow from layer to layer towards the input, and steps that are
+//! too large as update to weight ratios far above the usual `1e-3` or so. Layers whose ReLUs stopped activating show a
+//! high fraction of dead units.
+
+use crate::{nn::Sequential, optim::global_norm};
+use std::fmt;
+
+/// The statistics of one layer, see [`Diagnostics`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerDiagnostics {
+    pub name: String,
+    /// The L2 norm of the gradient of the parameters of the layer, as given to the optimizer.
+    pub gradient_norm: f32,
+    /// The L2 norm of the parameters after the step.
+    pub weight_norm: f32,
+    /// The L2 norm of the change of the parameters by the step, relative to [`LayerDiagnostics::weight_norm`].
+    pub update_ratio: f32,
+    /// The fraction of units that were inactive for every sample, see [`Layer::dead_fraction`](crate::nn::Layer).
+    pub dead_fraction: Option<f32>,
+}
+
+/// The statistics of every layer of a [`Sequential`] for one training step. Displays as a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostics {
+    pub layers: Vec<LayerDiagnostics>,
+}
+
+impl Diagnostics {
+    /// Diagnoses the step that changed the parameters of `model` from `previous_parameters` with `gradients`. Dead units
+    /// are counted on the outputs for `inputs`, which are computed in evaluation mode so that the state of the model
+    /// does not change.
+    pub fn new(
+        model: &mut Sequential,
+        inputs: &[f32],
+        previous_parameters: &[f32],
+        gradients: &[f32],
+    ) -> Self {
+        let parameters = model.parameters();
+        assert_eq!(parameters.len(), previous_parameters.len());
+        assert_eq!(parameters.len(), gradients.len());
+
+        let training = model.is_training();
+        model.eval();
+        let activations = model.activations(inputs);
+        model.set_training(training);
+
+        let mut start = 0;
+        let layers = model
+            .layers()
+            .iter()
+            .zip(&activations[1..])
+            .map(|(layer, outputs)| {
+                let range = start..start + layer.parameters().len();
+                start = range.end;
+
+                let weight_norm = global_norm(&parameters[range.clone()]);
+                let update_norm = parameters[range.clone()]
+                    .iter()
+                    .zip(&previous_parameters[range.clone()])
+                    .map(|(after, before)| (after - before) * (after - before))
+                    .sum::<f32>()
+                    .sqrt();
+                LayerDiagnostics {
+                    name: layer.name(),
+                    gradient_norm: global_norm(&gradients[range]),
+                    weight_norm,
+                    update_ratio: if weight_norm > 0.0 {
+                        update_norm / weight_norm
+                    } else {
+                        0.0
+                    },
+                    dead_fraction: layer.dead_fraction(outputs),
+                }
+            })
+            .collect();
+
+        Self { layers }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name_width = self
+            .layers
+            .iter()
+            .map(|layer| layer.name.len())
+            .chain(["Layer".len()])
+            .max()
+            .unwrap_or(0);
+
+        writeln!(
+            f,
+            "{:>2}  {:name_width$}  {:>13}  {:>11}  {:>13}  {:>4}",
+            "#", "Layer", "Gradient norm", "Weight norm", "Update/weight", "Dead"
+        )?;
+        for (index, layer) in self.layers.iter().enumerate() {
+            let dead = layer
+                .dead_fraction
+                .map_or(String::new(), |dead| format!("{:.0}%", 100.0 * dead));
+            writeln!(
+                f,
+                "{index:>2}  {:name_width$}  {:>13.3e}  {:>11.3e}  {:>13.3e}  {dead:>4}",
+                layer.name, layer.gradient_norm, layer.weight_norm, layer.update_ratio
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        nn::{Dropout, FullyConnectedLayer, Identity},
+        optim::Sgd,
+    };
+
+    #[test]
+    fn step() {
+        // The second unit of the first layer has a large negative bias, so it never activates.
+        let mut model = Sequential::new(vec![
+            Box::new(FullyConnectedLayer::new(1, 2, [1.0, 0.0, 1.0, -10.0])),
+            Box::new(Dropout::new(2, 0.5, 0)),
+            Box::new(FullyConnectedLayer::with_activation(
+                2,
+                1,
+                [1.0, 1.0, 0.0],
+                Identity,
+            )),
+        ]);
+        let previous = model.parameters();
+        let gradients = [0.0, 0.0, 3.0, 4.0, 1.0, 1.0, 1.0];
+        model.update(&mut Sgd::new(0.1), &gradients);
+
+        let diagnostics = Diagnostics::new(&mut model, &[1.0, 2.0], &previous, &gradients);
+        assert!(model.is_training());
+        let [first, dropout, last] = &diagnostics.layers[..] else {
+            panic!("expected three layers");
+        };
+        assert_eq!(first.gradient_norm, 5.0);
+        let weight_norm = (1.0f32 + 0.7 * 0.7 + 10.4 * 10.4).sqrt();
+        assert!((first.weight_norm - weight_norm).abs() < 1e-5);
+        assert!((first.update_ratio - 0.5 / weight_norm).abs() < 1e-6);
+        assert_eq!(first.dead_fraction, Some(0.5));
+        assert_eq!((dropout.weight_norm, dropout.dead_fraction), (0.0, None));
+        assert_eq!(last.dead_fraction, Some(0.0));
+
+        assert_eq!(diagnostics.to_string().lines().count(), 4);
+    }
+}
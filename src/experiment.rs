@@ -0,0 +1,751 @@
+//! Experiments described by configuration files, so that they can be versioned along with the code.
+//!
+//! An [`Experiment`] names a dataset, the layers of the model, the loss, the optimizer and its schedule, the number of
+//! epochs and the seed, and is read from TOML or JSON with the same fields:
+//!
+//! ```toml
+//! seed = 0
+//! epochs = 60
+//! batch_size = 256
+//! loss = "mean_squared_error"
+//! early_stopping = { patience = 5, min_delta = 1e-6 }
+//!
+//! [dataset]
+//! kind = "sine"
+//! train = 8192
+//! validation = 1024
+//! test = 1024
+//!
+//! [[layers]]
+//! type = "dense"
+//! units = 8
+//! activation = "relu"
+//!
+//! [[layers]]
+//! type = "dense"
+//! units = 1
+//! activation = "identity"
+//!
+//! [optimizer]
+//! kind = "sgd"
+//! learning_rate = 0.1
+//! ```
+//!
+//! The number of inputs of the first layer is given by the dataset, and the outputs of the last layer must match its
+//! targets. All randomness is drawn from streams of the seed, see [`Seeds`].
+
+use crate::{
+    chart::LossChart,
+    data::{self, quantile_bins, stratified_k_fold, CrossValidation, DataLoader},
+    logging::MetricsLogger,
+    loss::{Loss, MeanSquaredError, NegativeLogLikelihood},
+    metrics, mnist,
+    nn::{argmax, Activation, BatchNorm, Dropout, Layer, LogSoftmax, Regularization, Sequential},
+    optim::{CosineAnnealing, GradientClipping, Sgd, StepDecay},
+    seed::Seeds,
+    train::{Callback, EarlyStopping, EpochStats, ModelCheckpoint, ProgressLogger, Trainer},
+    Result,
+};
+use rand::{distributions::Uniform, Rng};
+use serde::Deserialize;
+use std::{fs, path::PathBuf};
+
+/// An experiment, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    #[serde(default)]
+    pub seed: u64,
+    pub dataset: DatasetConfig,
+    pub layers: Vec<LayerConfig>,
+    /// Weights and biases are drawn uniformly from `-initial_weights..initial_weights`.
+    #[serde(default = "default_initial_weights")]
+    pub initial_weights: f32,
+    pub loss: LossFunction,
+    pub optimizer: OptimizerConfig,
+    pub schedule: Option<ScheduleConfig>,
+    pub epochs: usize,
+    pub batch_size: usize,
+    /// Clips the global norm of the gradient to this value.
+    pub gradient_clipping: Option<f32>,
+    pub early_stopping: Option<EarlyStoppingConfig>,
+    /// Cross-validates with this many folds of the training samples instead of training one model.
+    pub folds: Option<usize>,
+    /// Saves the checkpoint of the best epoch to this file.
+    pub checkpoint: Option<PathBuf>,
+    /// Logs metrics to this `.csv` or `.jsonl` file.
+    pub log: Option<PathBuf>,
+}
+
+fn default_initial_weights() -> f32 {
+    0.5
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DatasetConfig {
+    /// Samples of `sin(x)` for `x` drawn uniformly from `0..1`.
+    Sine {
+        train: usize,
+        validation: usize,
+        test: usize,
+    },
+    /// Gzipped MNIST images and labels. The first `train` samples are trained on, and the rest are validated on.
+    Mnist {
+        images: PathBuf,
+        labels: PathBuf,
+        train: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LayerConfig {
+    /// A [`FullyConnectedLayer`](crate::nn::FullyConnectedLayer), optionally regularized.
+    Dense {
+        units: usize,
+        #[serde(default = "default_activation")]
+        activation: Activation,
+        #[serde(default)]
+        l1: f32,
+        #[serde(default)]
+        l2: f32,
+        #[serde(default)]
+        weight_decay: f32,
+        max_norm: Option<f32>,
+        #[serde(default)]
+        regularize_biases: bool,
+    },
+    Dropout {
+        probability: f32,
+    },
+    BatchNorm,
+    LogSoftmax,
+}
+
+fn default_activation() -> Activation {
+    Activation::ReLU
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LossFunction {
+    MeanSquaredError,
+    /// For classifiers that end in a [`LogSoftmax`]. Also measures the accuracy.
+    NegativeLogLikelihood,
+}
+
+impl Loss for LossFunction {
+    fn loss(&self, outputs: &[f32], targets: &[f32], gradients: &mut [f32]) -> f32 {
+        match self {
+            LossFunction::MeanSquaredError => MeanSquaredError.loss(outputs, targets, gradients),
+            LossFunction::NegativeLogLikelihood => {
+                NegativeLogLikelihood.loss(outputs, targets, gradients)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum OptimizerConfig {
+    Sgd { learning_rate: f32 },
+}
+
+/// A learning rate schedule, starting from the learning rate of the optimizer.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ScheduleConfig {
+    StepDecay {
+        factor: f32,
+        step_size: usize,
+    },
+    /// Anneals to `minimum` over all epochs.
+    CosineAnnealing {
+        minimum: f32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EarlyStoppingConfig {
+    pub patience: usize,
+    #[serde(default)]
+    pub min_delta: f32,
+}
+
+/// The samples of a dataset, split for an experiment.
+pub struct Data {
+    pub train: DataLoader,
+    pub validation: DataLoader,
+    pub test: Option<DataLoader>,
+}
+
+impl DatasetConfig {
+    pub fn input_count(&self) -> usize {
+        match self {
+            DatasetConfig::Sine { .. } => 1,
+            DatasetConfig::Mnist { .. } => 28 * 28,
+        }
+    }
+
+    pub fn target_count(&self) -> usize {
+        match self {
+            DatasetConfig::Sine { .. } => 1,
+            DatasetConfig::Mnist { .. } => 10,
+        }
+    }
+
+    /// Loads or generates the samples, drawing generated samples from the data stream of `seeds`.
+    pub fn load(&self, batch_size: usize, seeds: &mut Seeds) -> Result<Data> {
+        match self {
+            DatasetConfig::Sine {
+                train,
+                validation,
+                test,
+            } => {
+                let mut rng = seeds.rng(Seeds::DATA);
+                let mut sine = |count: usize| {
+                    let (inputs, targets) = (0..count).map(|_| data::sample_sine(&mut rng)).unzip();
+                    DataLoader::new(inputs, 1, targets, 1, batch_size)
+                };
+                Ok(Data {
+                    train: sine(*train),
+                    validation: sine(*validation),
+                    test: Some(sine(*test)),
+                })
+            }
+            DatasetConfig::Mnist {
+                images,
+                labels,
+                train,
+            } => {
+                let path = |path: &PathBuf| {
+                    path.to_str()
+                        .map(str::to_owned)
+                        .ok_or_else(|| format!("{} is not valid UTF-8", path.display()))
+                };
+                let images = mnist::read_images_from_file(&path(images)?)?;
+                let labels = mnist::read_labels_from_file(&path(labels)?)?;
+                if images.len() != labels.len() || *train >= images.len() {
+                    return Err(format!(
+                        "need more than {train} images with a label each, got {} images and {} labels",
+                        images.len(),
+                        labels.len()
+                    )
+                    .into());
+                }
+
+                let mut pixels: Vec<f32> = images
+                    .iter()
+                    .flat_map(|image| image.normalized_pixels())
+                    .collect();
+                let mut targets = data::one_hot(&labels, 10);
+                let validation_pixels = pixels.split_off(train * 28 * 28);
+                let validation_targets = targets.split_off(train * 10);
+                Ok(Data {
+                    train: DataLoader::new(pixels, 28 * 28, targets, 10, batch_size),
+                    validation: DataLoader::new(
+                        validation_pixels,
+                        28 * 28,
+                        validation_targets,
+                        10,
+                        batch_size,
+                    ),
+                    test: None,
+                })
+            }
+        }
+    }
+}
+
+impl Experiment {
+    /// Reads an experiment from a `.toml` or `.json` file.
+    pub fn load(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        if path.ends_with(".toml") {
+            Self::from_toml(&text)
+        } else if path.ends_with(".json") {
+            Self::from_json(&text)
+        } else {
+            Err(format!("{path} is neither a .toml nor a .json file").into())
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let experiment: Self = toml::from_str(text)?;
+        experiment.validate()?;
+        Ok(experiment)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        let experiment: Self = serde_json::from_str(text)?;
+        experiment.validate()?;
+        Ok(experiment)
+    }
+
+    /// Checks that every value is in range, so that a bad configuration is reported as an error instead of panicking
+    /// while the experiment is set up. Experiments read with [`Experiment::load`] are already validated.
+    pub fn validate(&self) -> Result<()> {
+        let train = match self.dataset {
+            DatasetConfig::Sine {
+                train,
+                validation,
+                test,
+            } => {
+                ensure(
+                    validation > 0 && test > 0,
+                    "the sine dataset needs validation and test samples",
+                )?;
+                train
+            }
+            DatasetConfig::Mnist { train, .. } => train,
+        };
+        ensure(train > 0, "the dataset needs training samples")?;
+        ensure(!self.layers.is_empty(), "the model has no layers")?;
+        for (index, layer) in self.layers.iter().enumerate() {
+            match *layer {
+                LayerConfig::Dense {
+                    units,
+                    l1,
+                    l2,
+                    weight_decay,
+                    max_norm,
+                    ..
+                } => {
+                    ensure(units > 0, format!("layer {index} has no units"))?;
+                    for (name, value) in [("l1", l1), ("l2", l2), ("weight_decay", weight_decay)] {
+                        ensure(
+                            value.is_finite() && value >= 0.0,
+                            format!("{name} of layer {index} must not be negative, got {value}"),
+                        )?;
+                    }
+                    if let Some(max_norm) = max_norm {
+                        ensure(
+                            max_norm.is_finite() && max_norm > 0.0,
+                            format!("max_norm of layer {index} must be positive, got {max_norm}"),
+                        )?;
+                    }
+                }
+                LayerConfig::Dropout { probability } => ensure(
+                    (0.0..1.0).contains(&probability),
+                    format!(
+                        "dropout probability of layer {index} must be in 0..1, got {probability}"
+                    ),
+                )?,
+                LayerConfig::BatchNorm | LayerConfig::LogSoftmax => {}
+            }
+        }
+        ensure(
+            self.initial_weights.is_finite() && self.initial_weights >= 0.0,
+            format!(
+                "initial_weights must not be negative, got {}",
+                self.initial_weights
+            ),
+        )?;
+
+        let OptimizerConfig::Sgd { learning_rate } = self.optimizer;
+        ensure(
+            learning_rate.is_finite() && learning_rate > 0.0,
+            format!("learning_rate must be positive, got {learning_rate}"),
+        )?;
+        match self.schedule {
+            Some(ScheduleConfig::StepDecay { factor, step_size }) => {
+                ensure(
+                    factor.is_finite() && factor > 0.0,
+                    format!("step decay factor must be positive, got {factor}"),
+                )?;
+                ensure(step_size > 0, "step_size must be at least one epoch")?;
+            }
+            Some(ScheduleConfig::CosineAnnealing { minimum }) => ensure(
+                minimum.is_finite() && minimum >= 0.0,
+                format!("cosine annealing minimum must not be negative, got {minimum}"),
+            )?,
+            None => {}
+        }
+
+        ensure(self.epochs > 0, "epochs must be at least one")?;
+        ensure(self.batch_size > 0, "batch_size must be at least one")?;
+        if let Some(max_norm) = self.gradient_clipping {
+            ensure(
+                max_norm.is_finite() && max_norm > 0.0,
+                format!("gradient_clipping must be positive, got {max_norm}"),
+            )?;
+        }
+        if let Some(config) = &self.early_stopping {
+            ensure(
+                config.min_delta.is_finite() && config.min_delta >= 0.0,
+                format!(
+                    "early stopping min_delta must not be negative, got {}",
+                    config.min_delta
+                ),
+            )?;
+        }
+        if let Some(folds) = self.folds {
+            ensure(
+                (2..=train).contains(&folds),
+                format!("folds must be between 2 and the {train} training samples, got {folds}"),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Builds the model, drawing the initial parameters from the initialization stream of `seeds`.
+    pub fn model(&self, seeds: &mut Seeds) -> Result<Sequential> {
+        let mut rng = seeds.rng(Seeds::INITIALIZATION);
+        let weights = Uniform::new_inclusive(-self.initial_weights, self.initial_weights);
+        let mut count = self.dataset.input_count();
+        let mut layers: Vec<Box<dyn Layer>> = Vec::with_capacity(self.layers.len());
+        for layer in &self.layers {
+            let layer: Box<dyn Layer> = match *layer {
+                LayerConfig::Dense {
+                    units,
+                    activation,
+                    l1,
+                    l2,
+                    weight_decay,
+                    max_norm,
+                    regularize_biases,
+                } => activation.regularized(
+                    count,
+                    units,
+                    (&mut rng).sample_iter(weights),
+                    regularization(l1, l2, weight_decay, max_norm, regularize_biases),
+                ),
+                LayerConfig::Dropout { probability } => {
+                    // The trainer reseeds dropout for every shard of every batch.
+                    Box::new(Dropout::new(count, probability, 0))
+                }
+                LayerConfig::BatchNorm => Box::new(BatchNorm::new_1d(count)),
+                LayerConfig::LogSoftmax => Box::new(LogSoftmax::new(count)),
+            };
+            count = layer.output_count();
+            layers.push(layer);
+        }
+
+        if layers.is_empty() {
+            return Err("the model has no layers".into());
+        }
+        if count != self.dataset.target_count() {
+            return Err(format!(
+                "the model has {count} outputs, but the dataset has {} targets",
+                self.dataset.target_count()
+            )
+            .into());
+        }
+        Ok(Sequential::new(layers))
+    }
+
+    /// A trainer for `model` as configured, with dropout seeded from the dropout stream of `seeds`.
+    pub fn trainer(&self, model: Sequential, seeds: &mut Seeds) -> Trainer<LossFunction, Sgd> {
+        let OptimizerConfig::Sgd { learning_rate } = self.optimizer;
+        let mut trainer = Trainer::new(model, self.loss, Sgd::new(learning_rate))
+            .with_seed(seeds.seed(Seeds::DROPOUT));
+        match self.schedule {
+            Some(ScheduleConfig::StepDecay { factor, step_size }) => {
+                trainer = trainer.with_scheduler(StepDecay::new(learning_rate, factor, step_size));
+            }
+            Some(ScheduleConfig::CosineAnnealing { minimum }) => {
+                trainer = trainer.with_scheduler(CosineAnnealing::new(
+                    learning_rate,
+                    minimum,
+                    self.epochs,
+                ));
+            }
+            None => {}
+        }
+        if let Some(max_norm) = self.gradient_clipping {
+            trainer = trainer.with_gradient_clipping(GradientClipping::GlobalNorm(max_norm));
+        }
+        if self.loss == LossFunction::NegativeLogLikelihood {
+            trainer = trainer.with_accuracy();
+        }
+        trainer
+    }
+
+    /// Trains the model, printing the progress, restores the best epoch and prints how well it does on the test set,
+    /// or on the validation set if the dataset has no test set. Returns the statistics of every epoch.
+    pub fn run(&self) -> Result<Vec<EpochStats>> {
+        self.validate()?;
+        let mut seeds = Seeds::new(self.seed);
+        let mut data = self.dataset.load(self.batch_size, &mut seeds)?;
+        data.train = data.train.with_shuffle(seeds.seed(Seeds::SHUFFLE));
+        let model = self.model(&mut seeds)?;
+        print!("{}", model.summary());
+        let mut trainer = self.trainer(model, &mut seeds);
+
+        let mut progress = ProgressLogger::new();
+        let mut chart = LossChart::new(self.epochs.div_ceil(6).max(1));
+        // Every stream of the run has been requested by now, so the checkpoints record all of them.
+        let mut checkpoint = ModelCheckpoint::new().with_seeds(&seeds);
+        if let Some(path) = &self.checkpoint {
+            checkpoint = checkpoint.with_path(path);
+        }
+        let mut early_stopping = self
+            .early_stopping
+            .as_ref()
+            .map(|config| EarlyStopping::new(config.patience).with_min_delta(config.min_delta));
+        let mut logger = self.log.as_ref().map(MetricsLogger::create).transpose()?;
+
+        let mut callbacks: Vec<&mut dyn Callback> =
+            vec![&mut progress, &mut chart, &mut checkpoint];
+        if let Some(early_stopping) = &mut early_stopping {
+            callbacks.push(early_stopping);
+        }
+        if let Some(logger) = &mut logger {
+            callbacks.push(logger);
+        }
+        let history = trainer.fit(
+            &mut data.train,
+            Some(&data.validation),
+            self.epochs,
+            &mut callbacks,
+        )?;
+
+        if let Some(best) = checkpoint.best() {
+            best.restore(trainer.model_mut())?;
+        }
+        let (name, evaluation) = match &data.test {
+            Some(test) => ("test", test),
+            None => ("validation", &data.validation),
+        };
+        let (inputs, targets): (Vec<Vec<f32>>, Vec<Vec<f32>>) = evaluation.batches().unzip();
+        let (inputs, targets) = (inputs.concat(), targets.concat());
+        let model = trainer.model_mut();
+        match self.loss {
+            LossFunction::MeanSquaredError => {
+                let outputs = model.forward_eval(&inputs);
+                println!(
+                    "{name} mse: {:.6}, r²: {:.4}",
+                    metrics::mean_squared_error(&outputs, &targets),
+                    metrics::r_squared(&outputs, &targets)
+                );
+            }
+            LossFunction::NegativeLogLikelihood => {
+                let labels = labels(&targets, model.output_count());
+                println!(
+                    "{name} accuracy: {:.4}",
+                    metrics::accuracy(&model.predict(&inputs), &labels)
+                );
+            }
+        }
+
+        Ok(history)
+    }
+
+    /// Trains a model on the training samples of every fold for all epochs and measures its loss, and its accuracy for
+    /// classifiers, on the validation samples of the fold. Folds are stratified by class, or by the quantile of the
+    /// first target for regression. Every fold starts from the same initial parameters.
+    pub fn cross_validate(&self) -> Result<CrossValidation> {
+        let k = self.folds.ok_or("the experiment has no folds")?;
+        self.validate()?;
+        let mut seeds = Seeds::new(self.seed);
+        let data = self.dataset.load(self.batch_size, &mut seeds)?;
+        let target_count = self.dataset.target_count();
+        let targets: Vec<f32> = data
+            .train
+            .batches()
+            .flat_map(|(_, targets)| targets)
+            .collect();
+        let strata = if target_count > 1 {
+            targets.chunks_exact(target_count).map(argmax).collect()
+        } else {
+            let first: Vec<f32> = targets.iter().step_by(target_count).copied().collect();
+            quantile_bins(&first, k)
+        };
+        let folds = stratified_k_fold(&strata, k, Some(seeds.seed(Seeds::FOLDS)));
+
+        data::cross_validate(&data.train, &folds, |fold, train, validation| {
+            let mut seeds = Seeds::new(self.seed);
+            let mut train = train.with_shuffle(seeds.seed(Seeds::SHUFFLE));
+            let model = self.model(&mut seeds)?;
+            let mut trainer = self.trainer(model, &mut seeds);
+            let history = trainer.fit(&mut train, Some(&validation), self.epochs, &mut [])?;
+            let last = history.last().ok_or("no epochs to cross-validate")?;
+            println!("fold {fold}: validation loss {:.6}", last.monitored_loss());
+
+            let mut metrics = vec![("validation loss", last.monitored_loss())];
+            if let Some(accuracy) = last.validation_accuracy {
+                metrics.push(("validation accuracy", accuracy));
+            }
+            Ok(metrics)
+        })
+    }
+}
+
+fn ensure(condition: bool, message: impl Into<String>) -> Result<()> {
+    if condition {
+        Ok(())
+    } else {
+        Err(message.into().into())
+    }
+}
+
+fn regularization(
+    l1: f32,
+    l2: f32,
+    weight_decay: f32,
+    max_norm: Option<f32>,
+    include_biases: bool,
+) -> Regularization {
+    let mut regularization = Regularization::new()
+        .with_l1(l1)
+        .with_l2(l2)
+        .with_weight_decay(weight_decay);
+    if let Some(max_norm) = max_norm {
+        regularization = regularization.with_max_norm(max_norm);
+    }
+    if include_biases {
+        regularization = regularization.with_biases();
+    }
+    regularization
+}
+
+/// The class of every one-hot target.
+fn labels(targets: &[f32], classes: usize) -> Vec<u8> {
+    targets
+        .chunks_exact(classes)
+        .map(|target| argmax(target) as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINE: &str = r#"
+        seed = 3
+        epochs = 4
+        batch_size = 32
+        loss = "mean_squared_error"
+        early_stopping = { patience = 10 }
+        schedule = { kind = "step_decay", factor = 0.5, step_size = 2 }
+
+        [dataset]
+        kind = "sine"
+        train = 256
+        validation = 64
+        test = 64
+
+        [[layers]]
+        type = "dense"
+        units = 8
+        l2 = 1e-4
+
+        [[layers]]
+        type = "dense"
+        units = 1
+        activation = "identity"
+
+        [optimizer]
+        kind = "sgd"
+        learning_rate = 0.2
+    "#;
+
+    #[test]
+    fn toml_and_json() {
+        let experiment = Experiment::from_toml(SINE).unwrap();
+        assert_eq!(experiment.seed, 3);
+        assert_eq!(experiment.initial_weights, 0.5);
+        assert_eq!(
+            experiment.layers[0],
+            LayerConfig::Dense {
+                units: 8,
+                activation: Activation::ReLU,
+                l1: 0.0,
+                l2: 1e-4,
+                weight_decay: 0.0,
+                max_norm: None,
+                regularize_biases: false,
+            }
+        );
+
+        let json = r#"{
+            "epochs": 1, "batch_size": 64, "loss": "negative_log_likelihood",
+            "dataset": { "kind": "mnist", "images": "images.gz", "labels": "labels.gz", "train": 100 },
+            "layers": [
+                { "type": "dense", "units": 32, "activation": "tanh" },
+                { "type": "dropout", "probability": 0.5 },
+                { "type": "dense", "units": 10, "activation": "identity" },
+                { "type": "log_softmax" }
+            ],
+            "optimizer": { "kind": "sgd", "learning_rate": 0.1 }
+        }"#;
+        let experiment = Experiment::from_json(json).unwrap();
+        let model = experiment.model(&mut Seeds::new(0)).unwrap();
+        assert_eq!(model.parameter_count(), 785 * 32 + 33 * 10);
+
+        // Mistakes are caught when reading or building.
+        assert!(Experiment::from_toml(&SINE.replace("units = 8", "units = 8\nsize = 3")).is_err());
+        assert!(Experiment::from_toml(&SINE.replace("\"identity\"", "\"softplus\"")).is_err());
+        let experiment = Experiment::from_toml(&SINE.replace("units = 1", "units = 2")).unwrap();
+        assert!(experiment.model(&mut Seeds::new(0)).is_err());
+    }
+
+    #[test]
+    fn out_of_range_values() {
+        let invalid = [
+            ("batch_size = 32", "batch_size = 0"),
+            ("epochs = 4", "epochs = 0"),
+            ("epochs = 4", "epochs = 4\nfolds = 1"),
+            ("epochs = 4", "epochs = 4\nfolds = 257"),
+            ("step_size = 2", "step_size = 0"),
+            ("factor = 0.5", "factor = -0.5"),
+            ("epochs = 4", "epochs = 4\ninitial_weights = -0.5"),
+            ("epochs = 4", "epochs = 4\ngradient_clipping = 0.0"),
+            ("patience = 10", "patience = 10, min_delta = -1.0"),
+            ("learning_rate = 0.2", "learning_rate = 0.0"),
+            ("learning_rate = 0.2", "learning_rate = nan"),
+            ("train = 256", "train = 0"),
+            ("units = 8", "units = 0"),
+            ("l2 = 1e-4", "l2 = -1e-4"),
+            ("l2 = 1e-4", "l2 = 1e-4\nmax_norm = 0.0"),
+            ("l2 = 1e-4", "l2 = 1e-4\nmax_norm = -1.0"),
+            (
+                "[[layers]]",
+                "[[layers]]\ntype = \"dropout\"\nprobability = 1.0\n\n[[layers]]",
+            ),
+        ];
+        for (from, to) in invalid {
+            let text = SINE.replacen(from, to, 1);
+            assert_ne!(text, SINE);
+            assert!(Experiment::from_toml(&text).is_err(), "{to}");
+        }
+        assert!(
+            Experiment::from_toml(&SINE.replace("epochs = 4", "epochs = 4\nfolds = 256")).is_ok()
+        );
+
+        let mut experiment = Experiment::from_toml(SINE).unwrap();
+        experiment.folds = Some(1);
+        assert!(experiment.cross_validate().is_err());
+        experiment.folds = None;
+        experiment.layers.clear();
+        assert!(experiment.run().is_err());
+        assert!(Experiment::from_json(
+            r#"{
+                "epochs": 1, "batch_size": 64, "loss": "mean_squared_error",
+                "dataset": { "kind": "sine", "train": 8, "validation": 8, "test": 8 },
+                "layers": [],
+                "optimizer": { "kind": "sgd", "learning_rate": 0.1 }
+            }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn run() {
+        let experiment = Experiment::from_toml(SINE).unwrap();
+        let history = experiment.run().unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[2].learning_rate, 0.1);
+        assert!(history[3].train_loss < history[0].train_loss);
+        // Runs are reproducible.
+        assert_eq!(experiment.run().unwrap(), history);
+
+        let mut experiment = experiment;
+        experiment.folds = Some(3);
+        let result = experiment.cross_validate().unwrap();
+        assert_eq!(result.values("validation loss").unwrap().len(), 3);
+        assert!(result.values("validation accuracy").is_none());
+    }
+}
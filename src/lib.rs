@@ -2,5 +2,18 @@ pub mod mnist;
 pub mod nn;
 pub mod data;
 pub mod math;
+pub mod loss;
+pub mod metrics;
+pub mod optim;
+pub mod parallel;
+pub mod checkpoint;
+pub mod train;
+pub mod logging;
+pub mod chart;
+pub mod diagnostics;
+pub mod anomaly;
+pub mod seed;
+pub mod search;
+pub mod experiment;
 
 pub type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
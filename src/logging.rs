@@ -0,0 +1,254 @@
+//! Structured logs of training metrics, for plotting or comparing runs with other tools.
+//!
+//! A [`MetricsLogger`] writes a record after every batch, or every few batches, and after every epoch. Batch records
+//! hold the step, epoch, loss, learning rate and gradient norm; epoch records hold the step, epoch, training loss,
+//! learning rate and the validation loss and accuracy, if measured. Both kinds share the columns of [`Record`], and
+//! the `event` column tells them apart.
+
+use crate::{
+    nn::Sequential,
+    train::{BatchStats, Callback, EpochStats},
+    Result,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    ops::ControlFlow,
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Comma separated values with a header line. Missing values are empty fields.
+    Csv,
+    /// One JSON object per line. Missing and non-finite values are `null`.
+    JsonLines,
+}
+
+/// One line of a metrics log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// `"batch"` or `"epoch"`.
+    pub event: &'static str,
+    /// The number of batches trained on so far.
+    pub step: usize,
+    pub epoch: usize,
+    pub loss: f32,
+    pub learning_rate: f32,
+    pub gradient_norm: Option<f32>,
+    pub validation_loss: Option<f32>,
+    pub validation_accuracy: Option<f32>,
+}
+
+impl Record {
+    const COLUMNS: [&'static str; 8] = [
+        "event",
+        "step",
+        "epoch",
+        "loss",
+        "learning_rate",
+        "gradient_norm",
+        "validation_loss",
+        "validation_accuracy",
+    ];
+
+    fn values(&self) -> [Option<f32>; 5] {
+        [
+            Some(self.loss),
+            Some(self.learning_rate),
+            self.gradient_norm,
+            self.validation_loss,
+            self.validation_accuracy,
+        ]
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W, format: Format) -> Result<()> {
+        match format {
+            Format::Csv => {
+                write!(writer, "{},{},{}", self.event, self.step, self.epoch)?;
+                for value in self.values() {
+                    match value {
+                        Some(value) => write!(writer, ",{value}")?,
+                        None => write!(writer, ",")?,
+                    }
+                }
+            }
+            Format::JsonLines => {
+                write!(
+                    writer,
+                    r#"{{"event":"{}","step":{},"epoch":{}"#,
+                    self.event, self.step, self.epoch
+                )?;
+                for (column, value) in Self::COLUMNS[3..].iter().zip(self.values()) {
+                    match value.filter(|value| value.is_finite()) {
+                        Some(value) => write!(writer, r#","{column}":{value}"#)?,
+                        None => write!(writer, r#","{column}":null"#)?,
+                    }
+                }
+                write!(writer, "}}")?;
+            }
+        }
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+/// A [`Callback`] that writes a [`Record`] for every batch, or every few batches, and for every epoch.
+pub struct MetricsLogger<W: Write> {
+    writer: W,
+    format: Format,
+    batch_interval: Option<usize>,
+    header_written: bool,
+    /// The step of the next batch.
+    step: usize,
+}
+
+impl<W: Write> MetricsLogger<W> {
+    pub fn new(writer: W, format: Format) -> Self {
+        Self {
+            writer,
+            format,
+            batch_interval: Some(1),
+            header_written: false,
+            step: 0,
+        }
+    }
+
+    /// Only logs every `batches`th batch of an epoch, starting with the first.
+    pub fn with_batch_interval(mut self, batches: usize) -> Self {
+        assert!(batches > 0, "batch interval must be positive");
+        self.batch_interval = Some(batches);
+        self
+    }
+
+    /// Only logs epochs.
+    pub fn epochs_only(mut self) -> Self {
+        self.batch_interval = None;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn log(&mut self, record: Record) -> Result<()> {
+        if self.format == Format::Csv && !self.header_written {
+            writeln!(self.writer, "{}", Record::COLUMNS.join(","))?;
+        }
+        self.header_written = true;
+        record.write(&mut self.writer, self.format)
+    }
+}
+
+impl MetricsLogger<BufWriter<File>> {
+    /// Creates a log file in the format given by its extension: `.csv` for CSV, or `.jsonl` for JSON Lines.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let format = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Format::Csv,
+            Some("jsonl") => Format::JsonLines,
+            _ => {
+                return Err(
+                    format!("{} is neither a .csv nor a .jsonl file", path.display()).into(),
+                )
+            }
+        };
+        Ok(Self::new(BufWriter::new(File::create(path)?), format))
+    }
+}
+
+impl<W: Write> Callback for MetricsLogger<W> {
+    fn on_batch_end(&mut self, stats: &BatchStats, _model: &Sequential) -> Result<ControlFlow<()>> {
+        self.step = stats.step + 1;
+        if self
+            .batch_interval
+            .is_some_and(|interval| stats.batch.is_multiple_of(interval))
+        {
+            self.log(Record {
+                event: "batch",
+                step: stats.step,
+                epoch: stats.epoch,
+                loss: stats.loss,
+                learning_rate: stats.learning_rate,
+                gradient_norm: Some(stats.gradient_norm),
+                validation_loss: None,
+                validation_accuracy: None,
+            })?;
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn on_epoch_end(&mut self, stats: &EpochStats, _model: &Sequential) -> Result<ControlFlow<()>> {
+        self.log(Record {
+            event: "epoch",
+            step: self.step,
+            epoch: stats.epoch,
+            loss: stats.train_loss,
+            learning_rate: stats.learning_rate,
+            gradient_norm: None,
+            validation_loss: stats.validation_loss,
+            validation_accuracy: stats.validation_accuracy,
+        })?;
+        // Flush so that the log can be followed while training runs.
+        self.writer.flush()?;
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(format: Format) -> String {
+        let model = Sequential::new(Vec::new());
+        let mut logger = MetricsLogger::new(Vec::new(), format).with_batch_interval(2);
+        for batch in 0..3 {
+            let stats = BatchStats {
+                step: 10 + batch,
+                epoch: 1,
+                batch,
+                sample_count: 4,
+                loss: 0.5,
+                learning_rate: 0.1,
+                gradient_norm: 2.0,
+                diagnostics: None,
+            };
+            assert!(logger.on_batch_end(&stats, &model).unwrap().is_continue());
+        }
+        let stats = EpochStats {
+            epoch: 1,
+            train_loss: 0.25,
+            validation_loss: Some(f32::NAN),
+            validation_accuracy: None,
+            learning_rate: 0.1,
+        };
+        assert!(logger.on_epoch_end(&stats, &model).unwrap().is_continue());
+        String::from_utf8(logger.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            log(Format::Csv),
+            "event,step,epoch,loss,learning_rate,gradient_norm,validation_loss,validation_accuracy\n\
+             batch,10,1,0.5,0.1,2,,\n\
+             batch,12,1,0.5,0.1,2,,\n\
+             epoch,13,1,0.25,0.1,,NaN,\n"
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let log = log(Format::JsonLines);
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            r#"{"event":"batch","step":10,"epoch":1,"loss":0.5,"learning_rate":0.1,"gradient_norm":2,"validation_loss":null,"validation_accuracy":null}"#
+        );
+        assert_eq!(
+            lines[2],
+            r#"{"event":"epoch","step":13,"epoch":1,"loss":0.25,"learning_rate":0.1,"gradient_norm":null,"validation_loss":null,"validation_accuracy":null}"#
+        );
+    }
+}
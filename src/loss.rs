@@ -0,0 +1,45 @@
+/// A loss function over a batch of outputs, stored one sample after the other.
+pub trait Loss: Sync {
+    /// Returns the loss summed over the samples in the batch and writes the gradient of that sum with respect to the
+    /// outputs to `gradients`.
+    fn loss(&self, outputs: &[f32], targets: &[f32], gradients: &mut [f32]) -> f32;
+}
+
+/// The squared difference between outputs and targets, summed over the outputs of a sample.
+#[derive(Debug, Clone, Copy)]
+pub struct MeanSquaredError;
+
+impl Loss for MeanSquaredError {
+    fn loss(&self, outputs: &[f32], targets: &[f32], gradients: &mut [f32]) -> f32 {
+        assert_eq!(outputs.len(), targets.len());
+        assert_eq!(outputs.len(), gradients.len());
+
+        let mut loss = 0.0;
+        for ((gradient, &output), &target) in gradients.iter_mut().zip(outputs).zip(targets) {
+            let difference = output - target;
+            loss += difference * difference;
+            *gradient = 2.0 * difference;
+        }
+        loss
+    }
+}
+
+/// The negative log-likelihood of the targets under the outputs of a [`LogSoftmax`](crate::nn::LogSoftmax) layer,
+/// summed over the outputs of a sample. Targets are class probabilities, usually one-hot, which makes this the
+/// cross-entropy between the targets and the predictions.
+#[derive(Debug, Clone, Copy)]
+pub struct NegativeLogLikelihood;
+
+impl Loss for NegativeLogLikelihood {
+    fn loss(&self, outputs: &[f32], targets: &[f32], gradients: &mut [f32]) -> f32 {
+        assert_eq!(outputs.len(), targets.len());
+        assert_eq!(outputs.len(), gradients.len());
+
+        let mut loss = 0.0;
+        for ((gradient, &output), &target) in gradients.iter_mut().zip(outputs).zip(targets) {
+            loss -= target * output;
+            *gradient = -target;
+        }
+        loss
+    }
+}
@@ -1,8 +1,13 @@
 use std::{
+    fmt,
     marker::PhantomData,
-    ops::{Deref, DerefMut, Index, IndexMut},
+    ops::{Add, Deref, DerefMut, Index, IndexMut, Mul, Sub},
 };
 
+pub mod gemm;
+
+pub use gemm::gemm;
+
 pub trait TryIndex<I>: Index<I> {
     fn get(&self, index: I) -> Option<&Self::Output>;
 }
@@ -36,7 +41,7 @@ impl<T, const N: usize, I: std::slice::SliceIndex<[T]>> TryIndex<I> for [T; N] {
 // }
 
 /// Additive identity.
-trait Zero {
+pub trait Zero {
     const ZERO: Self;
 }
 
@@ -48,68 +53,11 @@ impl Zero for usize {
     const ZERO: Self = 0;
 }
 
-// #[derive(Debug, PartialEq)]
-// struct Array<T, D> {
-//     data: Vec<T>,
-//     dims: D,
-// }
-
-// impl<T, I0, I1> std::ops::Index<(I0, I1)> for Array<T, (I0, I1)>
-// where
-//     I0: Copy + Into<usize>,
-//     I1: Copy + Into<usize>,
-// {
-//     type Output = T;
-
-//     fn index(&self, (i0, i1): (I0, I1)) -> &Self::Output {
-//         let (d0, d1) = self.dims;
-//         let (i0, i1, d0, _) = (i0.into(), i1.into(), d0.into(), d1.into());
-//         &self.data[i1 * d0 + i0]
-//     }
-// }
-
-// fn dim_range<T: Copy + Into<usize> + From<usize>>(t: T) -> impl Iterator<Item = T> {
-//     (0..t.into()).map(T::from)
-// }
-
-// impl<T, I0, I1, I2> std::ops::Mul<Array<T, (I2, I0)>> for Array<T, (I0, I1)>
-// where
-//     T: Copy + Zero + Add<Output = T> + Mul<Output = T>,
-//     I0: Copy + PartialEq + Into<usize> + From<usize>,
-//     I1: Copy + PartialEq + Into<usize> + From<usize>,
-//     I2: Copy + PartialEq + Into<usize> + From<usize>,
-// {
-//     type Output = Result<Array<T, (I2, I1)>, (Self, Array<T, (I2, I0)>)>;
-
-//     fn mul(self, rhs: Array<T, (I2, I0)>) -> Self::Output {
-//         let common_dim = self.dims.0;
-//         if common_dim != rhs.dims.1 {
-//             return Err((self, rhs));
-//         }
-
-//         let dims = (rhs.dims.0, self.dims.1);
-
-//         let mut data = Vec::with_capacity(dims.0.into() * dims.1.into());
-
-//         for i1 in dim_range(dims.1) {
-//             for i2 in dim_range(dims.0) {
-//                 let mut val = Zero::ZERO;
-//                 for i0 in dim_range(common_dim) {
-//                     val = val + self[(i0, i1)] * rhs[(i2, i0)];
-//                 }
-//                 data.push(val);
-//             }
-//         }
-
-//         Ok(Array { data, dims })
-//     }
-// }
-
 #[macro_export]
 macro_rules! index_type {
-    ($T:ident) => {
+    ($vis:vis $T:ident) => {
         #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
-        struct $T(usize);
+        $vis struct $T($vis usize);
 
         impl From<$T> for usize {
             fn from(value: $T) -> Self {
@@ -133,8 +81,9 @@ pub trait Indices {
     fn from_usize(array: Self::Array) -> Self;
 }
 
+/// An extent that is known at compile time and therefore takes up no space.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-struct Static<const N: usize>;
+pub struct Static<const N: usize>;
 
 impl<const N: usize> From<Static<N>> for usize {
     fn from(_: Static<N>) -> Self {
@@ -142,6 +91,11 @@ impl<const N: usize> From<Static<N>> for usize {
     }
 }
 
+/// Maps multidimensional indices to flat indices into the underlying storage.
+///
+/// Iterating over an indexer visits `len()` multidimensional indices. `expand` maps a position in that iteration to
+/// the multidimensional index visited at that position. For dense indexers the position is the flat index itself, so
+/// `flatten(expand(i)) == Some(i)` for every `i < len()`.
 pub trait Indexer: IntoIterator<Item = Self::Expanded> {
     type Expanded;
 
@@ -150,9 +104,17 @@ pub trait Indexer: IntoIterator<Item = Self::Expanded> {
     fn expand(&self, index: usize) -> Option<Self::Expanded>;
 
     fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of elements along each axis.
+    fn shape(&self) -> Self::Expanded;
 }
 
-pub struct Square<E>(E);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Square<E>(pub E);
 
 impl<E0> Indexer for Square<E0>
 where
@@ -187,9 +149,14 @@ where
         let e0 = self.0.into();
         e0 * e0
     }
+
+    fn shape(&self) -> Self::Expanded {
+        let e0 = self.0.into();
+        [e0, e0]
+    }
 }
 
-struct IndexerIter<X> {
+pub struct IndexerIter<X> {
     index: usize,
     indexer: X,
 }
@@ -239,7 +206,28 @@ impl<E0> IntoIterator for Square<E0> where E0: Copy + Into<usize> {
     }
 }
 
-pub struct SquareSymmetric<E>(E);
+/// The number of elements in the upper triangle of an `n` by `n` matrix, including the diagonal.
+fn triangular(n: usize) -> usize {
+    n * (n + 1) / 2
+}
+
+/// The largest `n` for which `triangular(n) <= i`.
+fn triangular_root(i: usize) -> usize {
+    ((8 * i + 1).isqrt() - 1) / 2
+}
+
+/// A symmetric square matrix that only stores the upper triangle, packed row by row.
+///
+/// ```text
+///      i0
+///    0, 1, 2,
+/// i1 -, 3, 4,
+///    -, -, 5,
+/// ```
+///
+/// Iteration visits the stored elements as `[i0, i1]` with `i0 >= i1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SquareSymmetric<E>(pub E);
 
 impl<E0> Indexer for SquareSymmetric<E0>
 where
@@ -257,37 +245,33 @@ where
         // Ensure i0 >= i1.
         let (i0, i1) = if i0 >= i1 { (i0, i1) } else { (i1, i0) };
 
-        // Compute flat index.
-        //      i0
-        //    0, 1, 2,
-        // i1 -, 3, 4,
-        //    -, -, 5,
-        // TODO: Should we index so that extending the matrix does not change the indices? So instead use
-        //      i0
-        //    0, 1, 3,
-        // i1 -, 2, 4,
-        //    -, -, 5,
-        // this can impact perf
-
         Some(i0 + i1 * (2 * e0 - 1 - i1) / 2)
     }
 
     fn expand(&self, i: usize) -> Option<Self::Expanded> {
         let e0 = self.0.into();
+        let len = self.len();
 
-        if i >= e0 * e0 {
-            return None
+        if i >= len {
+            return None;
         }
 
-        let i0 = i % e0;
-        let i1 = i / e0;
+        // Counting from the end, the rows from the bottom up form the column packed layout, so we can reuse its
+        // closed form.
+        let j = len - 1 - i;
+        let a = triangular_root(j);
+        let b = j - triangular(a);
 
-        Some([i0, i1])
+        Some([e0 - 1 - b, e0 - 1 - a])
     }
 
     fn len(&self) -> usize {
+        triangular(self.0.into())
+    }
+
+    fn shape(&self) -> Self::Expanded {
         let e0 = self.0.into();
-        e0 * e0
+        [e0, e0]
     }
 }
 
@@ -301,6 +285,339 @@ impl<E0> IntoIterator for SquareSymmetric<E0> where E0: Copy + Into<usize> {
     }
 }
 
+/// A symmetric square matrix that only stores the upper triangle, packed column by column.
+///
+/// ```text
+///      i0
+///    0, 1, 3,
+/// i1 -, 2, 4,
+///    -, -, 5,
+/// ```
+///
+/// Unlike [`SquareSymmetric`], the flat index of an element does not depend on the extent, so the matrix can be
+/// extended by appending a column without moving the existing elements. Iteration visits the stored elements as `[i0,
+/// i1]` with `i0 >= i1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SquareSymmetricColumnPacked<E>(pub E);
+
+impl<E0> Indexer for SquareSymmetricColumnPacked<E0>
+where
+    E0: Copy + Into<usize>,
+{
+    type Expanded = [usize; 2];
+
+    fn flatten(&self, [i0, i1]: Self::Expanded) -> Option<usize> {
+        let e0 = self.0.into();
+
+        if i0 >= e0 || i1 >= e0 {
+            return None;
+        }
+
+        // Ensure i0 >= i1.
+        let (i0, i1) = if i0 >= i1 { (i0, i1) } else { (i1, i0) };
+
+        Some(triangular(i0) + i1)
+    }
+
+    fn expand(&self, i: usize) -> Option<Self::Expanded> {
+        if i >= self.len() {
+            return None;
+        }
+
+        let i0 = triangular_root(i);
+        let i1 = i - triangular(i0);
+
+        Some([i0, i1])
+    }
+
+    fn len(&self) -> usize {
+        triangular(self.0.into())
+    }
+
+    fn shape(&self) -> Self::Expanded {
+        let e0 = self.0.into();
+        [e0, e0]
+    }
+}
+
+impl<E0> IntoIterator for SquareSymmetricColumnPacked<E0> where E0: Copy + Into<usize> {
+    type Item = <Self as Indexer>::Expanded;
+
+    type IntoIter = IndexerIter<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IndexerIter::new(self)
+    }
+}
+
+/// A dense indexer over any number of axes in which the last axis is contiguous.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RowMajor<E>(pub E);
+
+impl<E, const N: usize> Indexer for RowMajor<E>
+where
+    E: Copy + IntoArray<usize, Array = [usize; N]>,
+{
+    type Expanded = [usize; N];
+
+    fn flatten(&self, indices: Self::Expanded) -> Option<usize> {
+        Strided::row_major(self.shape()).flatten(indices)
+    }
+
+    fn expand(&self, index: usize) -> Option<Self::Expanded> {
+        Strided::row_major(self.shape()).expand(index)
+    }
+
+    fn len(&self) -> usize {
+        self.shape().iter().product()
+    }
+
+    fn shape(&self) -> Self::Expanded {
+        self.0.into_array()
+    }
+}
+
+impl<E, const N: usize> IntoIterator for RowMajor<E>
+where
+    E: Copy + IntoArray<usize, Array = [usize; N]>,
+{
+    type Item = <Self as Indexer>::Expanded;
+
+    type IntoIter = IndexerIter<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IndexerIter::new(self)
+    }
+}
+
+/// A dense indexer over any number of axes in which the first axis is contiguous.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ColMajor<E>(pub E);
+
+impl<E, const N: usize> Indexer for ColMajor<E>
+where
+    E: Copy + IntoArray<usize, Array = [usize; N]>,
+{
+    type Expanded = [usize; N];
+
+    fn flatten(&self, indices: Self::Expanded) -> Option<usize> {
+        Strided::col_major(self.shape()).flatten(indices)
+    }
+
+    fn expand(&self, index: usize) -> Option<Self::Expanded> {
+        let shape = self.shape();
+
+        if index >= self.len() {
+            return None;
+        }
+
+        let mut indices = [0; N];
+        let mut rest = index;
+        for (i, e) in indices.iter_mut().zip(shape) {
+            *i = rest % e;
+            rest /= e;
+        }
+
+        Some(indices)
+    }
+
+    fn len(&self) -> usize {
+        self.shape().iter().product()
+    }
+
+    fn shape(&self) -> Self::Expanded {
+        self.0.into_array()
+    }
+}
+
+impl<E, const N: usize> IntoIterator for ColMajor<E>
+where
+    E: Copy + IntoArray<usize, Array = [usize; N]>,
+{
+    type Item = <Self as Indexer>::Expanded;
+
+    type IntoIter = IndexerIter<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IndexerIter::new(self)
+    }
+}
+
+/// An indexer over `N` axes where the element at `[i0, i1, ..]` is stored at `offset + i0 * strides[0] + i1 *
+/// strides[1] + ..`.
+///
+/// Slicing, stepping and permuting axes only change the strides and the offset, so they never copy the data. Iteration
+/// visits the indices in row-major order regardless of the strides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Strided<const N: usize> {
+    shape: [usize; N],
+    strides: [usize; N],
+    offset: usize,
+}
+
+impl<const N: usize> Strided<N> {
+    pub fn new(shape: [usize; N], strides: [usize; N], offset: usize) -> Self {
+        Self {
+            shape,
+            strides,
+            offset,
+        }
+    }
+
+    pub fn row_major(shape: [usize; N]) -> Self {
+        let mut strides = [0; N];
+        let mut stride = 1;
+        for (s, e) in strides.iter_mut().zip(shape).rev() {
+            *s = stride;
+            stride *= e;
+        }
+        Self::new(shape, strides, 0)
+    }
+
+    pub fn col_major(shape: [usize; N]) -> Self {
+        let mut strides = [0; N];
+        let mut stride = 1;
+        for (s, e) in strides.iter_mut().zip(shape) {
+            *s = stride;
+            stride *= e;
+        }
+        Self::new(shape, strides, 0)
+    }
+
+    pub fn strides(&self) -> [usize; N] {
+        self.strides
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Reorders the axes so that axis `k` of the result is axis `axes[k]` of `self`. Panics if `axes` is not a
+    /// permutation of `0..N`.
+    pub fn permute(self, axes: [usize; N]) -> Self {
+        let mut seen = [false; N];
+        for &a in &axes {
+            assert!(a < N && !seen[a], "{axes:?} is not a permutation");
+            seen[a] = true;
+        }
+
+        Self::new(
+            axes.map(|a| self.shape[a]),
+            axes.map(|a| self.strides[a]),
+            self.offset,
+        )
+    }
+
+    /// Reverses the order of the axes.
+    pub fn transpose(self) -> Self {
+        let mut axes = [0; N];
+        for (k, a) in axes.iter_mut().enumerate() {
+            *a = N - 1 - k;
+        }
+        self.permute(axes)
+    }
+
+    /// Restricts `axis` to `range`. Panics if the range does not fit in the axis.
+    pub fn slice(mut self, axis: usize, range: std::ops::Range<usize>) -> Self {
+        assert!(
+            range.start <= range.end && range.end <= self.shape[axis],
+            "{range:?} out of bounds for axis {axis} of length {}",
+            self.shape[axis]
+        );
+
+        if range.start < range.end {
+            self.offset += range.start * self.strides[axis];
+        }
+        self.shape[axis] = range.end - range.start;
+        self
+    }
+
+    /// Keeps every `step`-th element along `axis`, starting with the first. Panics if `step` is zero.
+    pub fn step(mut self, axis: usize, step: usize) -> Self {
+        assert!(step > 0, "step must be positive");
+
+        self.shape[axis] = self.shape[axis].div_ceil(step);
+        self.strides[axis] *= step;
+        self
+    }
+}
+
+impl<const N: usize> Indexer for Strided<N> {
+    type Expanded = [usize; N];
+
+    fn flatten(&self, indices: Self::Expanded) -> Option<usize> {
+        let mut index = self.offset;
+        for ((i, e), s) in indices.into_iter().zip(self.shape).zip(self.strides) {
+            if i >= e {
+                return None;
+            }
+            index += i * s;
+        }
+        Some(index)
+    }
+
+    fn expand(&self, index: usize) -> Option<Self::Expanded> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let mut indices = [0; N];
+        let mut rest = index;
+        for (i, e) in indices.iter_mut().zip(self.shape).rev() {
+            *i = rest % e;
+            rest /= e;
+        }
+
+        Some(indices)
+    }
+
+    fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+
+    fn shape(&self) -> Self::Expanded {
+        self.shape
+    }
+}
+
+impl<const N: usize> IntoIterator for Strided<N> {
+    type Item = <Self as Indexer>::Expanded;
+
+    type IntoIter = IndexerIter<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IndexerIter::new(self)
+    }
+}
+
+impl<E> From<Square<E>> for Strided<2>
+where
+    E: Copy + Into<usize>,
+{
+    fn from(value: Square<E>) -> Self {
+        Self::col_major(value.shape())
+    }
+}
+
+impl<E, const N: usize> From<RowMajor<E>> for Strided<N>
+where
+    E: Copy + IntoArray<usize, Array = [usize; N]>,
+{
+    fn from(value: RowMajor<E>) -> Self {
+        Self::row_major(value.shape())
+    }
+}
+
+impl<E, const N: usize> From<ColMajor<E>> for Strided<N>
+where
+    E: Copy + IntoArray<usize, Array = [usize; N]>,
+{
+    fn from(value: ColMajor<E>) -> Self {
+        Self::col_major(value.shape())
+    }
+}
+
+#[derive(Debug)]
 pub struct Array<D, X, I> {
     data: D,
     indexer: X,
@@ -322,6 +639,138 @@ impl<D, X, I> Array<D, X, I> {
     pub fn into_inner(self) -> D {
         self.data
     }
+
+    pub fn indexer(&self) -> &X {
+        &self.indexer
+    }
+}
+
+impl<D, X, I> Array<D, X, I>
+where
+    X: Indexer,
+{
+    pub fn len(&self) -> usize {
+        self.indexer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indexer.is_empty()
+    }
+
+    pub fn shape(&self) -> X::Expanded {
+        self.indexer.shape()
+    }
+}
+
+impl<D, X, I> Array<D, X, I>
+where
+    D: Deref<Target: Index<usize>>,
+    X: Indexer,
+{
+    /// Iterates over the elements in the order in which the indexer visits them.
+    pub fn iter(&self) -> impl Iterator<Item = &<D::Target as Index<usize>>::Output> + '_ {
+        (0..self.indexer.len()).map(|i| &self.data[self.flat_index(i)])
+    }
+
+    /// Iterates over the elements along with their multidimensional indices.
+    pub fn indexed_iter(
+        &self,
+    ) -> impl Iterator<Item = (X::Expanded, &<D::Target as Index<usize>>::Output)> + '_ {
+        (0..self.indexer.len()).map(|i| {
+            let indices = self.indexer.expand(i).unwrap_or_else(|| oob());
+            let value = &self.data[self.flat_index(i)];
+            (indices, value)
+        })
+    }
+
+    fn flat_index(&self, i: usize) -> usize {
+        self.indexer
+            .expand(i)
+            .and_then(|indices| self.indexer.flatten(indices))
+            .unwrap_or_else(|| oob())
+    }
+}
+
+impl<D, X, I> Array<D, X, I>
+where
+    D: DerefMut<Target: IndexMut<usize>>,
+    X: Indexer,
+{
+    /// Applies `f` to every element in the order in which the indexer visits them.
+    pub fn map_inplace<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut <D::Target as Index<usize>>::Output),
+    {
+        for i in 0..self.indexer.len() {
+            let index = self.flat_index(i);
+            f(&mut self.data[index]);
+        }
+    }
+}
+
+impl<D, X, I> Array<D, X, I> {
+    pub fn into_strided<const N: usize>(self) -> Array<D, Strided<N>, I>
+    where
+        X: Into<Strided<N>>,
+    {
+        Array::new(self.data, self.indexer.into())
+    }
+
+    /// Borrows the data as a strided view.
+    pub fn view<const N: usize>(&self) -> Array<&D::Target, Strided<N>, I>
+    where
+        D: Deref,
+        X: Copy + Into<Strided<N>>,
+    {
+        Array::new(&*self.data, self.indexer.into())
+    }
+
+    /// Mutably borrows the data as a strided view.
+    pub fn view_mut<const N: usize>(&mut self) -> Array<&mut D::Target, Strided<N>, I>
+    where
+        D: DerefMut,
+        X: Copy + Into<Strided<N>>,
+    {
+        Array::new(&mut *self.data, self.indexer.into())
+    }
+
+    /// Reverses the order of the axes without copying the data.
+    pub fn transpose<const N: usize>(self) -> Array<D, Strided<N>, I::Transposed>
+    where
+        X: Into<Strided<N>>,
+        I: Transpose,
+    {
+        Array::new(self.data, self.indexer.into().transpose())
+    }
+
+    /// Reorders the axes without copying the data, see [`Strided::permute`]. Since the axes can end up in any order,
+    /// the result is indexed with plain `usize`s.
+    pub fn permute<const N: usize>(self, axes: [usize; N]) -> Array<D, Strided<N>, [usize; N]>
+    where
+        X: Into<Strided<N>>,
+    {
+        Array::new(self.data, self.indexer.into().permute(axes))
+    }
+
+    /// Restricts `axis` to `range` without copying the data, see [`Strided::slice`].
+    pub fn slice<const N: usize>(
+        self,
+        axis: usize,
+        range: std::ops::Range<usize>,
+    ) -> Array<D, Strided<N>, I>
+    where
+        X: Into<Strided<N>>,
+    {
+        Array::new(self.data, self.indexer.into().slice(axis, range))
+    }
+
+    /// Keeps every `step`-th element along `axis` without copying the data, see [`Strided::step`].
+    pub fn step<const N: usize>(self, axis: usize, step: usize) -> Array<D, Strided<N>, I>
+    where
+        X: Into<Strided<N>>,
+    {
+        Array::new(self.data, self.indexer.into().step(axis, step))
+    }
 }
 
 fn oob() -> ! {
@@ -331,7 +780,7 @@ fn oob() -> ! {
 impl<D, X, I> Index<I> for Array<D, X, I>
 where
     D: Deref<Target: Index<usize>>,
-    X: Indexer<I::Array>,
+    X: Indexer<Expanded = I::Array>,
     I: IntoArray<usize>,
 {
     type Output = <D::Target as Index<usize>>::Output;
@@ -344,7 +793,7 @@ where
 impl<D, X, I> IndexMut<I> for Array<D, X, I>
 where
     D: DerefMut<Target: IndexMut<usize>>,
-    X: Indexer<I::Array>,
+    X: Indexer<Expanded = I::Array>,
     I: IntoArray<usize>,
 {
     fn index_mut(&mut self, indices: I) -> &mut Self::Output {
@@ -352,24 +801,329 @@ where
     }
 }
 
-// impl<D0, X0, I, D1, X1> PartialEq<Array<D1, X1, I>> for Array<D0, X0, I>
-// where
-//     X0: Indexer<I::Array> + Copy + IntoArray<usize, Array = I::Array>,
-//     X1: Indexer<I::Array> + Copy + IntoArray<usize, Array = I::Array>,
-//     I: IntoArray<usize, Array: Eq>,
-// {
-//     fn eq(&self, other: &Array<D1, X1, I>) -> bool {
-//         self.indexer.into_array() == other.indexer.into_array()
-//         self.indexer.iter()
-//     }
-// }
+impl<D0, X0, D1, X1, I, T, U, const N: usize> PartialEq<Array<D1, X1, I>> for Array<D0, X0, I>
+where
+    D0: Deref<Target: Index<usize, Output = T>>,
+    X0: Indexer<Expanded = [usize; N]>,
+    D1: Deref<Target: Index<usize, Output = U>>,
+    X1: Indexer<Expanded = [usize; N]>,
+    T: PartialEq<U>,
+{
+    /// Arrays are equal when they have the same shape and the same elements, regardless of their layout.
+    fn eq(&self, other: &Array<D1, X1, I>) -> bool {
+        let shape = self.shape();
+        shape == other.shape()
+            && Strided::row_major(shape)
+                .into_iter()
+                .all(|indices| self.at(indices) == other.at(indices))
+    }
+}
+
+/// The shapes of the operands of an operation are incompatible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShapeMismatch {
+    pub lhs: Vec<usize>,
+    pub rhs: Vec<usize>,
+}
+
+impl ShapeMismatch {
+    fn new(lhs: &[usize], rhs: &[usize]) -> Self {
+        Self {
+            lhs: lhs.to_vec(),
+            rhs: rhs.to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for ShapeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "shape mismatch between {:?} and {:?}", self.lhs, self.rhs)
+    }
+}
+
+impl std::error::Error for ShapeMismatch {}
+
+/// An array that owns its elements and stores them with the first axis contiguous.
+pub type Owned<T, I, const N: usize> = Array<Vec<T>, ColMajor<[usize; N]>, I>;
+
+pub type Matrix<T, I0, I1> = Owned<T, (I0, I1), 2>;
+
+pub type Vector<T, I0> = Owned<T, (I0,), 1>;
+
+impl<T, I, const N: usize> Array<Vec<T>, ColMajor<[usize; N]>, I> {
+    pub fn from_fn<F>(shape: [usize; N], f: F) -> Self
+    where
+        F: FnMut([usize; N]) -> T,
+    {
+        let indexer = ColMajor(shape);
+        Self::new(indexer.into_iter().map(f).collect(), indexer)
+    }
+
+    pub fn zeros(shape: [usize; N]) -> Self
+    where
+        T: Zero,
+    {
+        Self::from_fn(shape, |_| T::ZERO)
+    }
+}
+
+impl<D, X, I, T, const N: usize> Array<D, X, I>
+where
+    D: Deref<Target: Index<usize, Output = T>>,
+    X: Indexer<Expanded = [usize; N]>,
+{
+    fn at(&self, indices: [usize; N]) -> &T {
+        &self.data[self.indexer.flatten(indices).unwrap_or_else(|| oob())]
+    }
+
+    /// Copies the elements into a new array.
+    pub fn to_owned(&self) -> Owned<T, I, N>
+    where
+        T: Copy,
+    {
+        self.map(|value| value)
+    }
+
+    pub fn map<F, U>(&self, mut f: F) -> Owned<U, I, N>
+    where
+        T: Copy,
+        F: FnMut(T) -> U,
+    {
+        Array::from_fn(self.shape(), |indices| f(*self.at(indices)))
+    }
+
+    /// Combines the elements of two arrays with the same shape.
+    pub fn zip_map<D1, X1, U, V, F>(
+        &self,
+        rhs: &Array<D1, X1, I>,
+        mut f: F,
+    ) -> Result<Owned<V, I, N>, ShapeMismatch>
+    where
+        D1: Deref<Target: Index<usize, Output = U>>,
+        X1: Indexer<Expanded = [usize; N]>,
+        T: Copy,
+        U: Copy,
+        F: FnMut(T, U) -> V,
+    {
+        let shape = self.shape();
+        if shape != rhs.shape() {
+            return Err(ShapeMismatch::new(&shape, &rhs.shape()));
+        }
+
+        Ok(Array::from_fn(shape, |indices| {
+            f(*self.at(indices), *rhs.at(indices))
+        }))
+    }
+
+    pub fn scale(&self, factor: T) -> Owned<T, I, N>
+    where
+        T: Copy + Mul<Output = T>,
+    {
+        self.map(|value| value * factor)
+    }
+
+    /// Multiplies the elements of two arrays with the same shape.
+    pub fn hadamard<D1, X1>(&self, rhs: &Array<D1, X1, I>) -> Result<Owned<T, I, N>, ShapeMismatch>
+    where
+        D1: Deref<Target: Index<usize, Output = T>>,
+        X1: Indexer<Expanded = [usize; N]>,
+        T: Copy + Mul<Output = T>,
+    {
+        self.zip_map(rhs, Mul::mul)
+    }
+}
+
+impl<D, X, I0, I1, T> Array<D, X, (I0, I1)>
+where
+    D: Deref<Target: Index<usize, Output = T>>,
+    X: Indexer<Expanded = [usize; 2]>,
+    T: Copy + Zero + Add<Output = T> + Mul<Output = T>,
+{
+    /// Computes the matrix product `out[(i2, i1)] = sum over i0 of self[(i0, i1)] * rhs[(i2, i0)]`.
+    ///
+    /// The axis that is summed over has to have the same index type in both operands:
+    ///
+    /// ```
+    /// # use nn::math::{Array, ColMajor};
+    /// nn::index_type!(pub A);
+    /// nn::index_type!(pub B);
+    /// nn::index_type!(pub C);
+    ///
+    /// let ab = Array::<_, _, (A, B)>::new(vec![1, 2, 3, 4, 5, 6], ColMajor([3, 2]));
+    /// let ca = Array::<_, _, (C, A)>::new(vec![1; 12], ColMajor([4, 3]));
+    /// let cb = ab.matmul(&ca).unwrap();
+    /// assert_eq!(cb[(C(0), B(1))], 15);
+    /// ```
+    ///
+    /// ```compile_fail
+    /// # use nn::math::{Array, ColMajor};
+    /// nn::index_type!(pub A);
+    /// nn::index_type!(pub B);
+    /// nn::index_type!(pub C);
+    ///
+    /// let ab = Array::<_, _, (A, B)>::new(vec![1, 2, 3, 4, 5, 6], ColMajor([3, 2]));
+    /// let cb = Array::<_, _, (C, B)>::new(vec![1; 8], ColMajor([4, 2]));
+    /// let _ = ab.matmul(&cb);
+    /// ```
+    pub fn matmul<D1, X1, I2>(
+        &self,
+        rhs: &Array<D1, X1, (I2, I0)>,
+    ) -> Result<Matrix<T, I2, I1>, ShapeMismatch>
+    where
+        D1: Deref<Target: Index<usize, Output = T>>,
+        X1: Indexer<Expanded = [usize; 2]>,
+    {
+        let [e0, e1] = self.shape();
+        let [e2, rhs_e0] = rhs.shape();
+        if e0 != rhs_e0 {
+            return Err(ShapeMismatch::new(&[e0, e1], &[e2, rhs_e0]));
+        }
+
+        Ok(Array::from_fn([e2, e1], |[i2, i1]| {
+            (0..e0).fold(T::ZERO, |sum, i0| {
+                sum + *self.at([i0, i1]) * *rhs.at([i2, i0])
+            })
+        }))
+    }
+
+    /// Computes the matrix-vector product `out[(i1,)] = sum over i0 of self[(i0, i1)] * rhs[(i0,)]`.
+    pub fn matvec<D1, X1>(&self, rhs: &Array<D1, X1, (I0,)>) -> Result<Vector<T, I1>, ShapeMismatch>
+    where
+        D1: Deref<Target: Index<usize, Output = T>>,
+        X1: Indexer<Expanded = [usize; 1]>,
+    {
+        let [e0, e1] = self.shape();
+        let [rhs_e0] = rhs.shape();
+        if e0 != rhs_e0 {
+            return Err(ShapeMismatch::new(&[e0, e1], &[rhs_e0]));
+        }
+
+        Ok(Array::from_fn([e1], |[i1]| {
+            (0..e0).fold(T::ZERO, |sum, i0| sum + *self.at([i0, i1]) * *rhs.at([i0]))
+        }))
+    }
+}
+
+impl<D, X, I0, T> Array<D, X, (I0,)>
+where
+    D: Deref<Target: Index<usize, Output = T>>,
+    X: Indexer<Expanded = [usize; 1]>,
+    T: Copy + Zero + Add<Output = T> + Mul<Output = T>,
+{
+    pub fn dot<D1, X1>(&self, rhs: &Array<D1, X1, (I0,)>) -> Result<T, ShapeMismatch>
+    where
+        D1: Deref<Target: Index<usize, Output = T>>,
+        X1: Indexer<Expanded = [usize; 1]>,
+    {
+        let [e0] = self.shape();
+        let [rhs_e0] = rhs.shape();
+        if e0 != rhs_e0 {
+            return Err(ShapeMismatch::new(&[e0], &[rhs_e0]));
+        }
+
+        Ok((0..e0).fold(T::ZERO, |sum, i0| sum + *self.at([i0]) * *rhs.at([i0])))
+    }
+
+    /// Computes the outer product `out[(i0, i1)] = self[(i0,)] * rhs[(i1,)]`.
+    pub fn outer<D1, X1, I1>(&self, rhs: &Array<D1, X1, (I1,)>) -> Matrix<T, I0, I1>
+    where
+        D1: Deref<Target: Index<usize, Output = T>>,
+        X1: Indexer<Expanded = [usize; 1]>,
+    {
+        let [e0] = self.shape();
+        let [e1] = rhs.shape();
+
+        Array::from_fn([e0, e1], |[i0, i1]| *self.at([i0]) * *rhs.at([i1]))
+    }
+}
 
-trait IntoArray<T> {
+impl<D0, X0, D1, X1, I0, I1, I2, T> Mul<&Array<D1, X1, (I2, I0)>> for &Array<D0, X0, (I0, I1)>
+where
+    D0: Deref<Target: Index<usize, Output = T>>,
+    X0: Indexer<Expanded = [usize; 2]>,
+    D1: Deref<Target: Index<usize, Output = T>>,
+    X1: Indexer<Expanded = [usize; 2]>,
+    T: Copy + Zero + Add<Output = T> + Mul<Output = T>,
+{
+    type Output = Result<Matrix<T, I2, I1>, ShapeMismatch>;
+
+    fn mul(self, rhs: &Array<D1, X1, (I2, I0)>) -> Self::Output {
+        self.matmul(rhs)
+    }
+}
+
+impl<D0, X0, D1, X1, I0, I1, T> Mul<&Array<D1, X1, (I0,)>> for &Array<D0, X0, (I0, I1)>
+where
+    D0: Deref<Target: Index<usize, Output = T>>,
+    X0: Indexer<Expanded = [usize; 2]>,
+    D1: Deref<Target: Index<usize, Output = T>>,
+    X1: Indexer<Expanded = [usize; 1]>,
+    T: Copy + Zero + Add<Output = T> + Mul<Output = T>,
+{
+    type Output = Result<Vector<T, I1>, ShapeMismatch>;
+
+    fn mul(self, rhs: &Array<D1, X1, (I0,)>) -> Self::Output {
+        self.matvec(rhs)
+    }
+}
+
+impl<D0, X0, D1, X1, I, T, const N: usize> Add<&Array<D1, X1, I>> for &Array<D0, X0, I>
+where
+    D0: Deref<Target: Index<usize, Output = T>>,
+    X0: Indexer<Expanded = [usize; N]>,
+    D1: Deref<Target: Index<usize, Output = T>>,
+    X1: Indexer<Expanded = [usize; N]>,
+    T: Copy + Add<Output = T>,
+{
+    type Output = Result<Owned<T, I, N>, ShapeMismatch>;
+
+    fn add(self, rhs: &Array<D1, X1, I>) -> Self::Output {
+        self.zip_map(rhs, Add::add)
+    }
+}
+
+impl<D0, X0, D1, X1, I, T, const N: usize> Sub<&Array<D1, X1, I>> for &Array<D0, X0, I>
+where
+    D0: Deref<Target: Index<usize, Output = T>>,
+    X0: Indexer<Expanded = [usize; N]>,
+    D1: Deref<Target: Index<usize, Output = T>>,
+    X1: Indexer<Expanded = [usize; N]>,
+    T: Copy + Sub<Output = T>,
+{
+    type Output = Result<Owned<T, I, N>, ShapeMismatch>;
+
+    fn sub(self, rhs: &Array<D1, X1, I>) -> Self::Output {
+        self.zip_map(rhs, Sub::sub)
+    }
+}
+
+pub trait IntoArray<T> {
     type Array;
 
     fn into_array(self) -> Self::Array;
 }
 
+/// Index types with the order of their axes reversed.
+pub trait Transpose {
+    type Transposed;
+}
+
+impl<const N: usize> Transpose for [usize; N] {
+    type Transposed = Self;
+}
+
+impl<A> Transpose for (A,) {
+    type Transposed = Self;
+}
+
+impl<A, B> Transpose for (A, B) {
+    type Transposed = (B, A);
+}
+
+impl<A, B, C> Transpose for (A, B, C) {
+    type Transposed = (C, B, A);
+}
+
 impl<const N: usize> IntoArray<usize> for [usize; N] {
     type Array = Self;
     fn into_array(self) -> Self::Array {
@@ -467,42 +1221,250 @@ mod tests {
         assert_eq!(view[(Col(3), Row(3))], 9);
     }
 
-    // index_type!(A);
-    // index_type!(B);
-    // index_type!(C);
-
-    // #[test]
-    // fn x() {
-    //     let a1 = Array {
-    //         data: vec![
-    //             1, 2, 3, //
-    //             4, 5, 6, //
-    //         ],
-    //         dims: (A(3), B(2)),
-    //     };
-
-    //     assert_eq!(a1[(A(1), B(1))], 5);
-
-    //     let a2 = Array {
-    //         data: vec![
-    //             1, 2, 3, 4, //
-    //             5, 6, 7, 8, //
-    //             9, 10, 11, 12, //
-    //         ],
-    //         dims: (C(4), A(3)),
-    //     };
-
-    //     let a3 = (a1 * a2).unwrap_or_else(|_| panic!("dimensionality mismatch"));
-
-    //     assert_eq!(
-    //         a3,
-    //         Array {
-    //             data: vec![
-    //                 38, 44, 50, 56, //
-    //                 83, 98, 113, 128, //
-    //             ],
-    //             dims: (C(4), B(2))
-    //         }
-    //     )
-    // }
+    #[test]
+    fn sym_tri_column_packed() {
+        let data = &mut [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let view = Array::<_, _, (Col, Row)>::new(data, SquareSymmetricColumnPacked(Static::<4>));
+
+        // 0, 1, 3, 6,
+        // 1, 2, 4, 7,
+        // 3, 4, 5, 8,
+        // 6, 7, 8, 9,
+
+        assert_eq!(view[(Col(0), Row(0))], 0);
+        assert_eq!(view[(Col(3), Row(0))], 6);
+        assert_eq!(view[(Col(1), Row(1))], 2);
+        assert_eq!(view[(Col(1), Row(2))], 4);
+        assert_eq!(view[(Col(3), Row(2))], 8);
+        assert_eq!(view[(Col(3), Row(3))], 9);
+
+        // Growing the matrix does not move existing elements.
+        let grown = SquareSymmetricColumnPacked(5usize);
+        for indices in SquareSymmetricColumnPacked(4usize) {
+            assert_eq!(view.indexer().flatten(indices), grown.flatten(indices));
+        }
+    }
+
+    #[test]
+    fn sym_tri_flatten_expand_inverse() {
+        fn check<X: Indexer<Expanded = [usize; 2]> + Copy>(indexer: X) {
+            let [n, _] = indexer.shape();
+            assert_eq!(indexer.len(), n * (n + 1) / 2);
+
+            // expand is a right inverse of flatten and only yields canonical indices.
+            for i in 0..indexer.len() {
+                let [i0, i1] = indexer.expand(i).unwrap();
+                assert!(i0 >= i1 && i0 < n);
+                assert_eq!(indexer.flatten([i0, i1]), Some(i));
+            }
+            assert_eq!(indexer.expand(indexer.len()), None);
+
+            // flatten is symmetric and expand is its inverse on canonical indices.
+            for i0 in 0..n {
+                for i1 in 0..n {
+                    let i = indexer.flatten([i0, i1]).unwrap();
+                    assert_eq!(indexer.flatten([i1, i0]), Some(i));
+                    assert_eq!(indexer.expand(i), Some([i0.max(i1), i0.min(i1)]));
+                }
+            }
+            assert_eq!(indexer.flatten([n, 0]), None);
+        }
+
+        for n in 0..64usize {
+            check(SquareSymmetric(n));
+            check(SquareSymmetricColumnPacked(n));
+        }
+    }
+
+    #[test]
+    fn row_major() {
+        let data = vec![
+            0, 1, 2, //
+            3, 4, 5, //
+        ];
+        let array = Array::<_, _, (Row, Col)>::new(data, RowMajor([2, 3]));
+
+        assert_eq!(array.shape(), [2, 3]);
+        assert_eq!(array[(Row(0), Col(2))], 2);
+        assert_eq!(array[(Row(1), Col(0))], 3);
+        assert_eq!(array.iter().copied().collect::<Vec<_>>(), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn col_major() {
+        let data = vec![
+            0, 1, //
+            2, 3, //
+            4, 5, //
+        ];
+        let array = Array::<_, _, (Row, Col)>::new(data, ColMajor((Static::<2>, Static::<3>)));
+
+        assert_eq!(array.shape(), [2, 3]);
+        assert_eq!(array[(Row(1), Col(0))], 1);
+        assert_eq!(array[(Row(0), Col(2))], 4);
+        assert_eq!(array.iter().copied().collect::<Vec<_>>(), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn dense_indexers_round_trip() {
+        fn check<X: Indexer<Expanded = [usize; N]> + Copy, const N: usize>(indexer: X) {
+            for i in 0..indexer.len() {
+                let indices = indexer.expand(i).unwrap();
+                assert_eq!(indexer.flatten(indices), Some(i));
+            }
+            assert_eq!(indexer.expand(indexer.len()), None);
+            assert_eq!(indexer.into_iter().count(), indexer.len());
+        }
+
+        check(Square(3usize));
+        check(RowMajor([2, 3, 4]));
+        check(ColMajor([2, 3, 4]));
+        check(RowMajor([5]));
+        check(ColMajor([0, 3]));
+    }
+
+    #[test]
+    fn transpose() {
+        let data = [
+            0, 1, 2, //
+            3, 4, 5, //
+        ];
+        let array = Array::<_, _, (Row, Col)>::new(&data, RowMajor([2, 3]));
+        let transposed: Array<_, _, (Col, Row)> = array.transpose();
+
+        assert_eq!(transposed.shape(), [3, 2]);
+        assert_eq!(transposed[(Col(2), Row(0))], 2);
+        assert_eq!(transposed[(Col(0), Row(1))], 3);
+        assert_eq!(
+            transposed.iter().copied().collect::<Vec<_>>(),
+            [0, 3, 1, 4, 2, 5]
+        );
+    }
+
+    #[test]
+    fn slice_and_step() {
+        let data: Vec<usize> = (0..24).collect();
+        let array = Array::<_, _, [usize; 3]>::new(&data, RowMajor([2, 3, 4]));
+
+        let sliced = array.slice(1, 1..3).step(2, 2);
+        assert_eq!(sliced.shape(), [2, 2, 2]);
+        assert_eq!(
+            sliced.iter().copied().collect::<Vec<_>>(),
+            [4, 6, 8, 10, 16, 18, 20, 22]
+        );
+        assert_eq!(sliced[[1, 0, 1]], 18);
+    }
+
+    #[test]
+    fn permute() {
+        let data: Vec<usize> = (0..6).collect();
+        let array = Array::<_, _, [usize; 3]>::new(&data, RowMajor([1, 2, 3]));
+        let permuted = array.permute([2, 0, 1]);
+
+        assert_eq!(permuted.shape(), [3, 1, 2]);
+        assert_eq!(permuted[[2, 0, 1]], 5);
+        assert_eq!(
+            permuted.indexed_iter().map(|(i, &v)| (i, v)).take(2).collect::<Vec<_>>(),
+            [([0, 0, 0], 0), ([0, 0, 1], 3)]
+        );
+    }
+
+    #[test]
+    fn view_mut() {
+        let mut array = Array::<_, _, (Row, Col)>::new(vec![0; 6], RowMajor([2, 3]));
+
+        let mut column = array.view_mut().slice(1, 1..2);
+        column.map_inplace(|v| *v = 7);
+        column[(Row(1), Col(0))] = 9;
+
+        assert_eq!(array.into_inner(), [0, 7, 0, 0, 9, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn strided_out_of_bounds() {
+        let data = [0; 6];
+        let array = Array::<_, _, [usize; 2]>::new(&data, RowMajor([2, 3])).slice(1, 0..2);
+        let _ = array[[0, 2]];
+    }
+
+    index_type!(A);
+    index_type!(B);
+    index_type!(C);
+
+    #[test]
+    fn matmul() {
+        let a1 = Array::<_, _, (A, B)>::new(
+            vec![
+                1, 2, 3, //
+                4, 5, 6, //
+            ],
+            ColMajor([3, 2]),
+        );
+
+        assert_eq!(a1[(A(1), B(1))], 5);
+
+        let a2 = Array::<_, _, (C, A)>::new(
+            vec![
+                1, 2, 3, 4, //
+                5, 6, 7, 8, //
+                9, 10, 11, 12, //
+            ],
+            ColMajor([4, 3]),
+        );
+
+        let a3 = (&a1 * &a2).unwrap_or_else(|_| panic!("dimensionality mismatch"));
+
+        assert_eq!(
+            a3,
+            Array::<_, _, (C, B)>::new(
+                vec![
+                    38, 44, 50, 56, //
+                    83, 98, 113, 128, //
+                ],
+                ColMajor([4, 2])
+            )
+        );
+
+        // Multiplying with a transposed view gives the same result as multiplying with a transposed copy.
+        let a2_t = a2.view().transpose().to_owned();
+        let a2_tt: Array<_, _, (C, A)> = a2_t.view().transpose();
+        assert_eq!(a1.matmul(&a2_tt), Ok(a3));
+
+        let short = Array::<_, _, (C, A)>::new(vec![0; 8], ColMajor([4, 2]));
+        assert_eq!(
+            a1.matmul(&short).err(),
+            Some(ShapeMismatch {
+                lhs: vec![3, 2],
+                rhs: vec![4, 2],
+            })
+        );
+    }
+
+    #[test]
+    fn vector_products() {
+        let m = Array::<_, _, (A, B)>::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], ColMajor([3, 2]));
+        let u = Array::<_, _, (A,)>::new(vec![1.0, 0.0, -1.0], ColMajor([3]));
+        let v = Array::<_, _, (B,)>::new(vec![2.0, 3.0], ColMajor([2]));
+
+        assert_eq!(&m * &u, Ok(Array::new(vec![-2.0, -2.0], ColMajor([2]))));
+        assert_eq!(u.dot(&u), Ok(2.0));
+        assert_eq!(
+            u.outer(&v),
+            Array::<_, _, (A, B)>::new(vec![2.0, 0.0, -2.0, 3.0, 0.0, -3.0], ColMajor([3, 2]))
+        );
+    }
+
+    #[test]
+    fn elementwise() {
+        let a = Array::<_, _, (A, B)>::new(vec![1, 2, 3, 4], RowMajor([2, 2]));
+        let b = Array::<_, _, (A, B)>::new(vec![1, 3, 2, 4], ColMajor([2, 2]));
+
+        assert_eq!(&a - &b, Ok(Matrix::zeros([2, 2])));
+        assert_eq!((&a + &b).map(|s| s.scale(2)), Ok(a.scale(4)));
+        assert_eq!(a.hadamard(&b), Ok(a.map(|x| x * x)));
+
+        let c = Array::<_, _, (A, B)>::new(vec![0; 6], RowMajor([2, 3]));
+        assert!((&a + &c).is_err());
+    }
 }
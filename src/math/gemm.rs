@@ -0,0 +1,338 @@
+//! Single precision general matrix multiplication.
+//!
+//! This follows the usual structure of high performance GEMM implementations. The operands are split into blocks that
+//! fit in the caches, every block is packed into contiguous panels, and a register blocked micro-kernel computes an `MR`
+//! by `NR` tile of the output at a time. On x86-64 the micro-kernel uses AVX2 and FMA when the CPU supports them.
+
+use super::{Array, ShapeMismatch, Strided};
+use std::ops::{Deref, DerefMut};
+
+/// Rows of the output computed by one invocation of the micro-kernel.
+const MR: usize = 6;
+
+/// Columns of the output computed by one invocation of the micro-kernel.
+const NR: usize = 16;
+
+/// Depth of the packed panels, chosen so that a panel of `b` stays in L1.
+const KC: usize = 256;
+
+/// Rows of `a` packed at a time, chosen so that the packed block stays in L2.
+const MC: usize = 16 * MR;
+
+/// Columns of `b` packed at a time, chosen so that the packed block stays in L3.
+const NC: usize = 128 * NR;
+
+/// Below this many multiply-adds, packing costs more than it saves.
+const SMALL: usize = 16 * 16 * 16;
+
+/// Computes `c[(i2, i1)] += sum over i0 of a[(i0, i1)] * b[(i2, i0)]`, the same product as [`Array::matmul`].
+///
+/// The operands can have any layout that converts into [`Strided`], so transposed and sliced views are multiplied
+/// without copying them first.
+pub fn gemm<DA, XA, DB, XB, DC, XC, I0, I1, I2>(
+    a: &Array<DA, XA, (I0, I1)>,
+    b: &Array<DB, XB, (I2, I0)>,
+    c: &mut Array<DC, XC, (I2, I1)>,
+) -> Result<(), ShapeMismatch>
+where
+    DA: Deref<Target = [f32]>,
+    XA: Copy + Into<Strided<2>>,
+    DB: Deref<Target = [f32]>,
+    XB: Copy + Into<Strided<2>>,
+    DC: DerefMut<Target = [f32]>,
+    XC: Copy + Into<Strided<2>>,
+{
+    let (a_layout, [k, m]) = Layout::new(a.indexer);
+    let (b_layout, [n, b_k]) = Layout::new(b.indexer);
+    let (c_layout, [c_n, c_m]) = Layout::new(c.indexer);
+
+    if k != b_k {
+        return Err(ShapeMismatch::new(&[k, m], &[n, b_k]));
+    }
+    if [n, m] != [c_n, c_m] {
+        return Err(ShapeMismatch::new(&[n, m], &[c_n, c_m]));
+    }
+
+    let a = Operand {
+        data: &a.data,
+        layout: a_layout,
+    };
+    let b = Operand {
+        data: &b.data,
+        layout: b_layout,
+    };
+
+    if m * n * k <= SMALL {
+        gemm_small(m, n, k, a, b, &mut c.data, c_layout);
+    } else {
+        gemm_blocked(m, n, k, a, b, &mut c.data, c_layout);
+    }
+
+    Ok(())
+}
+
+/// The position of the element in row `r` and column `c` of a row-by-column matrix, in the conventional orientation
+/// where `a` is `m` by `k`, `b` is `k` by `n` and `c` is `m` by `n`.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    offset: usize,
+    row_stride: usize,
+    col_stride: usize,
+}
+
+impl Layout {
+    /// Our arrays put the column axis first, so the strides are swapped relative to the conventional orientation.
+    fn new(indexer: impl Into<Strided<2>>) -> (Self, [usize; 2]) {
+        let strided = indexer.into();
+        let [col_stride, row_stride] = strided.strides();
+        let layout = Self {
+            offset: strided.offset(),
+            row_stride,
+            col_stride,
+        };
+        (layout, super::Indexer::shape(&strided))
+    }
+
+    fn index(&self, row: usize, col: usize) -> usize {
+        self.offset + row * self.row_stride + col * self.col_stride
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Operand<'a> {
+    data: &'a [f32],
+    layout: Layout,
+}
+
+impl Operand<'_> {
+    fn get(&self, row: usize, col: usize) -> f32 {
+        self.data[self.layout.index(row, col)]
+    }
+}
+
+fn gemm_small(m: usize, n: usize, k: usize, a: Operand, b: Operand, c: &mut [f32], c_layout: Layout) {
+    for row in 0..m {
+        for col in 0..n {
+            let sum = (0..k).fold(0.0, |sum, p| sum + a.get(row, p) * b.get(p, col));
+            c[c_layout.index(row, col)] += sum;
+        }
+    }
+}
+
+fn gemm_blocked(m: usize, n: usize, k: usize, a: Operand, b: Operand, c: &mut [f32], c_layout: Layout) {
+    let kernel = select_kernel();
+
+    let mut a_packed = vec![0.0; MC * KC];
+    let mut b_packed = vec![0.0; KC * NC];
+
+    for jc in (0..n).step_by(NC) {
+        let nc = NC.min(n - jc);
+
+        for pc in (0..k).step_by(KC) {
+            let kc = KC.min(k - pc);
+
+            pack_b(b, pc, jc, kc, nc, &mut b_packed);
+
+            for ic in (0..m).step_by(MC) {
+                let mc = MC.min(m - ic);
+
+                pack_a(a, ic, pc, mc, kc, &mut a_packed);
+
+                for jr in (0..nc).step_by(NR) {
+                    let nr = NR.min(nc - jr);
+                    let b_panel = &b_packed[jr * kc..][..NR * kc];
+
+                    for ir in (0..mc).step_by(MR) {
+                        let mr = MR.min(mc - ir);
+                        let a_panel = &a_packed[ir * kc..][..MR * kc];
+
+                        let mut tile = [[0.0; NR]; MR];
+                        kernel(kc, a_panel, b_panel, &mut tile);
+
+                        for (r, tile_row) in tile.iter().enumerate().take(mr) {
+                            for (col, value) in tile_row.iter().enumerate().take(nr) {
+                                c[c_layout.index(ic + ir + r, jc + jr + col)] += value;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Packs an `mc` by `kc` block of `a` into panels of `MR` rows. Within a panel the elements are stored column by
+/// column, and rows past the end of `a` are padded with zeros.
+fn pack_a(a: Operand, row: usize, col: usize, mc: usize, kc: usize, packed: &mut [f32]) {
+    for (ir, panel) in (0..mc).step_by(MR).zip(packed.chunks_exact_mut(MR * kc)) {
+        for (p, column) in panel.chunks_exact_mut(MR).enumerate() {
+            for (r, value) in column.iter_mut().enumerate() {
+                *value = if ir + r < mc { a.get(row + ir + r, col + p) } else { 0.0 };
+            }
+        }
+    }
+}
+
+/// Packs a `kc` by `nc` block of `b` into panels of `NR` columns. Within a panel the elements are stored row by row,
+/// and columns past the end of `b` are padded with zeros.
+fn pack_b(b: Operand, row: usize, col: usize, kc: usize, nc: usize, packed: &mut [f32]) {
+    for (jr, panel) in (0..nc).step_by(NR).zip(packed.chunks_exact_mut(NR * kc)) {
+        for (p, panel_row) in panel.chunks_exact_mut(NR).enumerate() {
+            for (c, value) in panel_row.iter_mut().enumerate() {
+                *value = if jr + c < nc { b.get(row + p, col + jr + c) } else { 0.0 };
+            }
+        }
+    }
+}
+
+/// Computes the product of a packed panel of `a` and a packed panel of `b` of depth `kc` and stores it in `tile`.
+type Kernel = fn(usize, &[f32], &[f32], &mut [[f32; NR]; MR]);
+
+fn select_kernel() -> Kernel {
+    #[cfg(target_arch = "x86_64")]
+    if avx2::is_supported() {
+        return avx2::kernel;
+    }
+
+    kernel_portable
+}
+
+/// Written so that the compiler can keep the tile in registers and vectorize the inner loop on any target.
+fn kernel_portable(kc: usize, a: &[f32], b: &[f32], tile: &mut [[f32; NR]; MR]) {
+    let mut acc = [[0.0; NR]; MR];
+
+    for (a, b) in a.chunks_exact(MR).zip(b.chunks_exact(NR)).take(kc) {
+        for (acc_row, &a) in acc.iter_mut().zip(a) {
+            for (acc, &b) in acc_row.iter_mut().zip(b) {
+                *acc += a * b;
+            }
+        }
+    }
+
+    *tile = acc;
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::{MR, NR};
+    use std::arch::x86_64::*;
+
+    pub fn is_supported() -> bool {
+        is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+    }
+
+    pub fn kernel(kc: usize, a: &[f32], b: &[f32], tile: &mut [[f32; NR]; MR]) {
+        assert!(is_supported());
+        assert!(a.len() >= kc * MR && b.len() >= kc * NR);
+
+        // SAFETY: The CPU supports the required features and the panels hold at least `kc` steps.
+        unsafe { kernel_unchecked(kc, a.as_ptr(), b.as_ptr(), tile) }
+    }
+
+    /// Keeps the `MR` by `NR` tile in 12 of the 16 ymm registers, leaving room for two rows of `b` and a broadcast
+    /// element of `a`.
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn kernel_unchecked(kc: usize, a: *const f32, b: *const f32, tile: &mut [[f32; NR]; MR]) {
+        let mut acc = [[_mm256_setzero_ps(); 2]; MR];
+
+        for p in 0..kc {
+            let b0 = _mm256_loadu_ps(b.add(p * NR));
+            let b1 = _mm256_loadu_ps(b.add(p * NR + 8));
+
+            for (r, acc) in acc.iter_mut().enumerate() {
+                let a = _mm256_broadcast_ss(&*a.add(p * MR + r));
+                acc[0] = _mm256_fmadd_ps(a, b0, acc[0]);
+                acc[1] = _mm256_fmadd_ps(a, b1, acc[1]);
+            }
+        }
+
+        for (tile_row, acc) in tile.iter_mut().zip(acc) {
+            _mm256_storeu_ps(tile_row.as_mut_ptr(), acc[0]);
+            _mm256_storeu_ps(tile_row.as_mut_ptr().add(8), acc[1]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{ColMajor, RowMajor};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    crate::index_type!(A);
+    crate::index_type!(B);
+    crate::index_type!(C);
+
+    fn random(rng: &mut StdRng, len: usize) -> Vec<f32> {
+        (0..len).map(|_| rng.gen_range(-1.0..1.0)).collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() <= 1e-4 * (1.0 + e.abs()), "{a} != {e}");
+        }
+    }
+
+    #[test]
+    fn matches_matmul() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+
+        for [m, n, k] in [[1, 1, 1], [3, 5, 7], [1, 300, 20], [MR + 1, NR + 1, KC + 1], [MC + 5, 70, 33]] {
+            let a = Array::<_, _, (A, B)>::new(random(&mut rng, m * k), ColMajor([k, m]));
+            let b = Array::<_, _, (C, A)>::new(random(&mut rng, k * n), RowMajor([n, k]));
+            let initial = random(&mut rng, m * n);
+
+            let product = a.matmul(&b).unwrap();
+            let expected = (&product + &Array::<_, _, (C, B)>::new(&initial[..], ColMajor([n, m]))).unwrap();
+
+            let mut c = Array::<_, _, (C, B)>::new(initial.clone(), ColMajor([n, m]));
+            gemm(&a, &b, &mut c).unwrap();
+
+            assert_close(&c.into_inner(), &expected.into_inner());
+        }
+    }
+
+    #[test]
+    fn strided_operands() {
+        let mut rng = StdRng::from_seed([1u8; 32]);
+        let [m, n, k] = [40, 50, 60];
+
+        // Store `a` transposed and with padding between the columns, then view it in the expected orientation.
+        let a_data = random(&mut rng, (m + 3) * k);
+        let a = Array::<_, _, (B, A)>::new(&a_data[..], RowMajor([m + 3, k]))
+            .slice(0, 0..m)
+            .transpose();
+        let b = Array::<_, _, (C, A)>::new(random(&mut rng, k * n), ColMajor([n, k]));
+
+        let expected = a.matmul(&b).unwrap();
+        let mut c = Array::<_, _, (C, B)>::new(vec![0.0; m * n], ColMajor([n, m]));
+        gemm(&a, &b, &mut c).unwrap();
+
+        assert_close(&c.into_inner(), &expected.into_inner());
+    }
+
+    #[test]
+    fn portable_kernel_matches_selected() {
+        let mut rng = StdRng::from_seed([2u8; 32]);
+        let kc = 17;
+        let a = random(&mut rng, MR * kc);
+        let b = random(&mut rng, NR * kc);
+
+        let mut expected = [[0.0; NR]; MR];
+        kernel_portable(kc, &a, &b, &mut expected);
+        let mut actual = [[0.0; NR]; MR];
+        select_kernel()(kc, &a, &b, &mut actual);
+
+        assert_close(actual.as_flattened(), expected.as_flattened());
+    }
+
+    #[test]
+    fn shape_mismatch() {
+        let a = Array::<_, _, (A, B)>::new(vec![0.0; 6], ColMajor([3, 2]));
+        let b = Array::<_, _, (C, A)>::new(vec![0.0; 8], ColMajor([4, 2]));
+        let mut c = Array::<_, _, (C, B)>::new(vec![0.0; 8], ColMajor([4, 2]));
+
+        assert!(gemm(&a, &b, &mut c).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+//! Scores for predictions: accuracy, confusion matrices and the metrics derived from them for classification, and the
+//! mean squared error and R² for regression.
+//!
+//! Labels are class indices as returned by [`read_labels`](crate::mnist::read_labels), and predictions class indices as
+//! returned by [`Sequential::predict`](crate::nn::Sequential::predict).
+
+use std::fmt;
+
+/// The fraction of predictions that equal their label.
+pub fn accuracy(predictions: &[usize], labels: &[u8]) -> f32 {
+    assert_eq!(predictions.len(), labels.len());
+    let correct = predictions
+        .iter()
+        .zip(labels)
+        .filter(|&(&prediction, &label)| prediction == label as usize)
+        .count();
+    correct as f32 / labels.len().max(1) as f32
+}
+
+/// The fraction of samples whose label is among the `k` classes with the highest scores. `scores` holds the scores of
+/// every class, one sample after the other. A label counts as among the top `k` if fewer than `k` classes score
+/// strictly higher, so ties go in favor of the label. Without samples it is zero, like [`accuracy`].
+pub fn top_k_accuracy(scores: &[f32], labels: &[u8], k: usize) -> f32 {
+    if labels.is_empty() {
+        assert!(scores.is_empty(), "scores without labels");
+        return 0.0;
+    }
+    let classes = scores.len() / labels.len().max(1);
+    assert_eq!(classes * labels.len(), scores.len());
+
+    let correct = scores
+        .chunks_exact(classes)
+        .zip(labels)
+        .filter(|&(scores, &label)| {
+            let score = scores[label as usize];
+            scores.iter().filter(|&&other| other > score).count() < k
+        })
+        .count();
+    correct as f32 / labels.len().max(1) as f32
+}
+
+/// The mean of the squared differences between outputs and targets, over all values.
+pub fn mean_squared_error(outputs: &[f32], targets: &[f32]) -> f32 {
+    assert_eq!(outputs.len(), targets.len());
+    let sum: f32 = outputs
+        .iter()
+        .zip(targets)
+        .map(|(output, target)| (output - target) * (output - target))
+        .sum();
+    sum / targets.len().max(1) as f32
+}
+
+/// The coefficient of determination: one minus the squared error of the outputs relative to that of always predicting
+/// the mean of the targets. One is a perfect fit, and zero is no better than the mean. If the targets are all equal,
+/// the mean fits them perfectly, so any fit is either perfect, which scores one, or no better, which scores zero.
+pub fn r_squared(outputs: &[f32], targets: &[f32]) -> f32 {
+    assert_eq!(outputs.len(), targets.len());
+    let mean = targets.iter().sum::<f32>() / targets.len().max(1) as f32;
+    let total: f32 = targets
+        .iter()
+        .map(|target| (target - mean) * (target - mean))
+        .sum();
+    let residual = mean_squared_error(outputs, targets) * targets.len() as f32;
+    if total == 0.0 {
+        return if residual == 0.0 { 1.0 } else { 0.0 };
+    }
+    1.0 - residual / total
+}
+
+/// Precision, recall and F1 score of a class or an average over classes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClassMetrics {
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+    /// The number of samples with the label, or of all samples for an average.
+    pub support: usize,
+}
+
+impl ClassMetrics {
+    fn new(true_positives: usize, predicted: usize, actual: usize) -> Self {
+        // Classes that are never predicted, or never occur, score zero rather than dividing by zero.
+        let ratio = |count: usize, total: usize| {
+            if total == 0 {
+                0.0
+            } else {
+                count as f32 / total as f32
+            }
+        };
+        let precision = ratio(true_positives, predicted);
+        let recall = ratio(true_positives, actual);
+        let f1 = if precision + recall == 0.0 {
+            0.0
+        } else {
+            2.0 * precision * recall / (precision + recall)
+        };
+
+        Self {
+            precision,
+            recall,
+            f1,
+            support: actual,
+        }
+    }
+}
+
+/// Counts how often every label was predicted as every class. Displays as a table with a row per label and a column
+/// per prediction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfusionMatrix {
+    classes: usize,
+    /// The count of label `l` predicted as `p` at `l * classes + p`.
+    counts: Vec<usize>,
+}
+
+impl ConfusionMatrix {
+    pub fn new(classes: usize) -> Self {
+        Self {
+            classes,
+            counts: vec![0; classes * classes],
+        }
+    }
+
+    pub fn from_predictions(classes: usize, predictions: &[usize], labels: &[u8]) -> Self {
+        let mut matrix = Self::new(classes);
+        matrix.extend(predictions, labels);
+        matrix
+    }
+
+    pub fn extend(&mut self, predictions: &[usize], labels: &[u8]) {
+        assert_eq!(predictions.len(), labels.len());
+        for (&prediction, &label) in predictions.iter().zip(labels) {
+            self.add(label as usize, prediction);
+        }
+    }
+
+    pub fn add(&mut self, label: usize, prediction: usize) {
+        assert!(
+            label < self.classes && prediction < self.classes,
+            "class out of range for {} classes",
+            self.classes
+        );
+        self.counts[label * self.classes + prediction] += 1;
+    }
+
+    pub fn classes(&self) -> usize {
+        self.classes
+    }
+
+    pub fn count(&self, label: usize, prediction: usize) -> usize {
+        self.counts[label * self.classes + prediction]
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    pub fn accuracy(&self) -> f32 {
+        let correct: usize = (0..self.classes)
+            .map(|class| self.count(class, class))
+            .sum();
+        correct as f32 / self.total().max(1) as f32
+    }
+
+    pub fn class_metrics(&self, class: usize) -> ClassMetrics {
+        let predicted = (0..self.classes)
+            .map(|label| self.count(label, class))
+            .sum();
+        let actual = (0..self.classes)
+            .map(|prediction| self.count(class, prediction))
+            .sum();
+        ClassMetrics::new(self.count(class, class), predicted, actual)
+    }
+
+    /// The unweighted mean of the metrics of every class, so rare classes count as much as common ones.
+    pub fn macro_average(&self) -> ClassMetrics {
+        let metrics: Vec<ClassMetrics> = (0..self.classes)
+            .map(|class| self.class_metrics(class))
+            .collect();
+        let mean = |metric: fn(&ClassMetrics) -> f32| {
+            metrics.iter().map(metric).sum::<f32>() / self.classes.max(1) as f32
+        };
+
+        ClassMetrics {
+            precision: mean(|m| m.precision),
+            recall: mean(|m| m.recall),
+            f1: mean(|m| m.f1),
+            support: self.total(),
+        }
+    }
+
+    /// The metrics of the counts pooled over all classes. With one label per sample every prediction that is a false
+    /// positive for one class is a false negative for another, so all three equal the accuracy.
+    pub fn micro_average(&self) -> ClassMetrics {
+        let correct = (0..self.classes)
+            .map(|class| self.count(class, class))
+            .sum();
+        ClassMetrics::new(correct, self.total(), self.total())
+    }
+
+    /// A table of the precision, recall, F1 score and support of every class, followed by the macro and micro
+    /// averages.
+    pub fn report(&self) -> Report<'_> {
+        Report(self)
+    }
+}
+
+impl fmt::Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let corner = "label\\predicted";
+        let width = self
+            .counts
+            .iter()
+            .chain([&self.classes])
+            .map(|count| count.to_string().len())
+            .max()
+            .unwrap_or(1);
+
+        write!(f, "{corner}")?;
+        for prediction in 0..self.classes {
+            write!(f, " {prediction:>width$}")?;
+        }
+        writeln!(f)?;
+        for label in 0..self.classes {
+            write!(f, "{label:<0$}", corner.len())?;
+            for prediction in 0..self.classes {
+                write!(f, " {:>width$}", self.count(label, prediction))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays the per-class metrics of a [`ConfusionMatrix`], see [`ConfusionMatrix::report`].
+pub struct Report<'a>(&'a ConfusionMatrix);
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let matrix = self.0;
+        let support_width = matrix.total().to_string().len().max("support".len());
+        let write_row = |f: &mut fmt::Formatter<'_>, name: &str, metrics: ClassMetrics| {
+            writeln!(
+                f,
+                "{name:<5}  {:>9.3}  {:>6.3}  {:>6.3}  {:>support_width$}",
+                metrics.precision, metrics.recall, metrics.f1, metrics.support
+            )
+        };
+
+        writeln!(
+            f,
+            "class  precision  recall      F1  {:>support_width$}",
+            "support"
+        )?;
+        for class in 0..matrix.classes {
+            write_row(f, &class.to_string(), matrix.class_metrics(class))?;
+        }
+        write_row(f, "macro", matrix.macro_average())?;
+        write_row(f, "micro", matrix.micro_average())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn classification() {
+        let labels = [0, 0, 0, 1, 1, 2];
+        let predictions = [0, 0, 1, 1, 2, 2];
+        assert!(close(accuracy(&predictions, &labels), 4.0 / 6.0));
+
+        let matrix = ConfusionMatrix::from_predictions(3, &predictions, &labels);
+        assert_eq!(
+            (matrix.count(0, 1), matrix.count(1, 2), matrix.total()),
+            (1, 1, 6)
+        );
+        assert!(close(matrix.accuracy(), 4.0 / 6.0));
+
+        let zero = matrix.class_metrics(0);
+        assert_eq!((zero.precision, zero.support), (1.0, 3));
+        assert!(close(zero.recall, 2.0 / 3.0));
+        assert!(close(zero.f1, 0.8));
+        let two = matrix.class_metrics(2);
+        assert_eq!((two.precision, two.recall), (0.5, 1.0));
+
+        let macro_average = matrix.macro_average();
+        assert!(close(macro_average.precision, (1.0 + 0.5 + 0.5) / 3.0));
+        assert!(close(macro_average.recall, (2.0 / 3.0 + 0.5 + 1.0) / 3.0));
+        let micro = matrix.micro_average();
+        assert!(close(micro.precision, matrix.accuracy()) && close(micro.f1, matrix.accuracy()));
+
+        // A class that never occurs and is never predicted scores zero.
+        let empty = ConfusionMatrix::from_predictions(4, &predictions, &labels).class_metrics(3);
+        assert_eq!((empty.precision, empty.recall, empty.f1), (0.0, 0.0, 0.0));
+
+        let table = matrix.to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "label\\predicted 0 1 2");
+        assert_eq!(lines[1], "0               2 1 0");
+        assert_eq!(matrix.report().to_string().lines().count(), 1 + 3 + 2);
+    }
+
+    #[test]
+    fn top_k() {
+        let scores = [
+            0.1, 0.7, 0.2, // label 2 is second
+            0.5, 0.3, 0.2, // label 0 is first
+            0.4, 0.4, 0.2, // label 1 ties for first
+        ];
+        let labels = [2, 0, 1];
+        assert!(close(top_k_accuracy(&scores, &labels, 1), 2.0 / 3.0));
+        assert!(close(top_k_accuracy(&scores, &labels, 2), 1.0));
+        assert_eq!(top_k_accuracy(&[], &[], 1), 0.0);
+    }
+
+    #[test]
+    fn regression() {
+        let targets = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(mean_squared_error(&targets, &targets), 0.0);
+        assert_eq!(r_squared(&targets, &targets), 1.0);
+
+        assert!(close(
+            mean_squared_error(&[1.0, 2.0, 3.0, 6.0], &targets),
+            1.0
+        ));
+        assert!(close(r_squared(&[2.5; 4], &targets), 0.0));
+        assert!(close(
+            r_squared(&[1.0, 2.0, 3.0, 6.0], &targets),
+            1.0 - 4.0 / 5.0
+        ));
+
+        // Constant targets leave nothing to explain.
+        assert_eq!(r_squared(&[2.0; 3], &[2.0; 3]), 1.0);
+        assert_eq!(r_squared(&[1.0, 2.0, 3.0], &[2.0; 3]), 0.0);
+    }
+}
@@ -54,6 +54,14 @@ pub struct Image {
     pub height: u32,
 }
 
+impl Image {
+    /// The pixels scaled to `0.0..=1.0`, row by row. This is the layout of a single channel sample for
+    /// [`crate::nn::Conv2d`].
+    pub fn normalized_pixels(&self) -> impl Iterator<Item = f32> + '_ {
+        self.pixels.iter().map(|&pixel| f32::from(pixel) / 255.0)
+    }
+}
+
 impl fmt::Debug for Image {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_char('\n')?;
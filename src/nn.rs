@@ -1,20 +1,214 @@
+use crate::{
+    anomaly::{Anomaly, Values},
+    index_type,
+    loss::Loss,
+    math::{self, Array, ColMajor, Strided},
+    optim::Optimizer,
+};
+use serde::{Deserialize, Deserializer};
+use std::{fmt, ops::Range, str::FromStr};
+
+pub mod conv;
+pub mod dropout;
+pub mod embedding;
+pub mod graph;
+pub mod norm;
+pub mod pool;
+pub mod recurrent;
+pub mod regularization;
+pub mod softmax;
+pub mod summary;
+pub mod transformer;
+
+pub use conv::Conv2d;
+pub use dropout::Dropout;
+pub use embedding::Embedding;
+pub use graph::{Graph, NodeId};
+pub use norm::{BatchNorm, GroupNorm, LayerNorm};
+pub use pool::{AvgPool2d, GlobalAvgPool, MaxPool2d};
+pub use recurrent::{Gru, GruCell, Lstm, LstmCell, Recurrent, Rnn, RnnCell};
+pub use regularization::Regularization;
+pub use softmax::{argmax, LogSoftmax, Softmax};
+pub use summary::{LayerSummary, Summary};
+pub use transformer::{
+    MultiHeadAttention, PatchEmbedding, PositionalEncoding, TokenMean, TransformerEncoderBlock,
+};
+
 pub trait ActivationFunction {
     fn activate(&self, value: f32) -> f32;
+
+    /// The derivative of the activation function, expressed in terms of its output.
+    fn derivative(&self, activated: f32) -> f32;
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct ReLU;
 
 impl ActivationFunction for ReLU {
     fn activate(&self, value: f32) -> f32 {
         f32::max(0.0, value)
     }
+
+    fn derivative(&self, activated: f32) -> f32 {
+        if activated > 0.0 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Passes values through unchanged, for output layers that predict unbounded values.
+#[derive(Debug, Clone, Copy)]
+pub struct Identity;
+
+impl ActivationFunction for Identity {
+    fn activate(&self, value: f32) -> f32 {
+        value
+    }
+
+    fn derivative(&self, _activated: f32) -> f32 {
+        1.0
+    }
+}
+
+/// The logistic function, which squashes values into `(0, 1)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Sigmoid;
+
+impl ActivationFunction for Sigmoid {
+    fn activate(&self, value: f32) -> f32 {
+        1.0 / (1.0 + (-value).exp())
+    }
+
+    fn derivative(&self, activated: f32) -> f32 {
+        activated * (1.0 - activated)
+    }
+}
+
+/// The hyperbolic tangent, which squashes values into `(-1, 1)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Tanh;
+
+impl ActivationFunction for Tanh {
+    fn activate(&self, value: f32) -> f32 {
+        value.tanh()
+    }
+
+    fn derivative(&self, activated: f32) -> f32 {
+        1.0 - activated * activated
+    }
+}
+
+/// An activation function chosen at run time, for building layers from a description such as a hyperparameter
+/// search. Displays and parses as its lowercase name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    ReLU,
+    Sigmoid,
+    Tanh,
+    Identity,
 }
 
+impl Activation {
+    pub const ALL: [Activation; 4] = [
+        Activation::ReLU,
+        Activation::Sigmoid,
+        Activation::Tanh,
+        Activation::Identity,
+    ];
+
+    /// A [`FullyConnectedLayer`] with this activation function.
+    pub fn fully_connected<I: IntoIterator<Item = f32>>(
+        self,
+        input_count: usize,
+        output_count: usize,
+        initializer: I,
+    ) -> Box<dyn Layer> {
+        self.regularized(
+            input_count,
+            output_count,
+            initializer,
+            Regularization::default(),
+        )
+    }
+
+    /// A [`FullyConnectedLayer`] with this activation function and `regularization`.
+    pub fn regularized<I: IntoIterator<Item = f32>>(
+        self,
+        input_count: usize,
+        output_count: usize,
+        initializer: I,
+        regularization: Regularization,
+    ) -> Box<dyn Layer> {
+        match self {
+            Activation::ReLU => Box::new(
+                FullyConnectedLayer::with_activation(input_count, output_count, initializer, ReLU)
+                    .with_regularization(regularization),
+            ),
+            Activation::Sigmoid => Box::new(
+                FullyConnectedLayer::with_activation(
+                    input_count,
+                    output_count,
+                    initializer,
+                    Sigmoid,
+                )
+                .with_regularization(regularization),
+            ),
+            Activation::Tanh => Box::new(
+                FullyConnectedLayer::with_activation(input_count, output_count, initializer, Tanh)
+                    .with_regularization(regularization),
+            ),
+            Activation::Identity => Box::new(
+                FullyConnectedLayer::with_activation(
+                    input_count,
+                    output_count,
+                    initializer,
+                    Identity,
+                )
+                .with_regularization(regularization),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Activation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Activation::ReLU => "relu",
+            Activation::Sigmoid => "sigmoid",
+            Activation::Tanh => "tanh",
+            Activation::Identity => "identity",
+        })
+    }
+}
+
+impl FromStr for Activation {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, String> {
+        Activation::ALL
+            .into_iter()
+            .find(|activation| activation.to_string() == name)
+            .ok_or_else(|| format!("unknown activation function {name:?}"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Activation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct FullyConnectedLayer<A = ReLU> {
     input_count: usize,
     output_count: usize,
     weights_and_biases: Vec<f32>,
     activation_function: A,
+    regularization: Regularization,
 }
 
 impl FullyConnectedLayer<ReLU> {
@@ -23,16 +217,198 @@ impl FullyConnectedLayer<ReLU> {
         output_count: usize,
         initializer: I,
     ) -> Self {
+        Self::with_activation(input_count, output_count, initializer, ReLU)
+    }
+}
+
+impl<A> FullyConnectedLayer<A> {
+    pub fn with_activation<I: IntoIterator<Item = f32>>(
+        input_count: usize,
+        output_count: usize,
+        initializer: I,
+        activation_function: A,
+    ) -> Self {
+        assert!(output_count > 0, "a layer needs at least one output");
+        let weights_and_biases: Vec<f32> = initializer
+            .into_iter()
+            .take((input_count + 1) * output_count)
+            .collect();
+        assert_eq!(
+            weights_and_biases.len(),
+            (input_count + 1) * output_count,
+            "initializer ran out of values"
+        );
+
         Self {
             input_count,
             output_count,
-            weights_and_biases: initializer
-                .into_iter()
-                .take((input_count + 1) * output_count)
-                .collect(),
-            activation_function: ReLU,
+            weights_and_biases,
+            activation_function,
+            regularization: Regularization::default(),
+        }
+    }
+
+    pub fn with_regularization(mut self, regularization: Regularization) -> Self {
+        self.regularization = regularization;
+        self
+    }
+
+    pub fn regularization(&self) -> &Regularization {
+        &self.regularization
+    }
+}
+
+index_type!(Input);
+index_type!(Output);
+index_type!(Sample);
+
+/// The weights without the biases of a layer in the layout of [`FullyConnectedLayer`], indexed by output and then input.
+fn affine_weights(
+    weights_and_biases: &[f32],
+    input_count: usize,
+) -> Array<&[f32], Strided<2>, (Output, Input)> {
+    let output_count = weights_and_biases.len() / (input_count + 1);
+    Array::new(
+        weights_and_biases,
+        Strided::new([output_count, input_count], [input_count + 1, 1], 0),
+    )
+}
+
+/// Computes `outputs = weights * inputs + biases` for a batch of samples, with the weights and biases in the layout of
+/// [`FullyConnectedLayer`]: a row of weights followed by a bias for every output.
+pub(crate) fn affine_forward(
+    weights_and_biases: &[f32],
+    input_count: usize,
+    inputs: &[f32],
+    outputs: &mut [f32],
+) {
+    let output_count = weights_and_biases.len() / (input_count + 1);
+    let batch_size = outputs.len() / output_count;
+    assert_eq!((input_count + 1) * output_count, weights_and_biases.len());
+    assert_eq!(input_count * batch_size, inputs.len());
+    assert_eq!(output_count * batch_size, outputs.len());
+
+    for sample_outputs in outputs.chunks_exact_mut(output_count) {
+        for (output, bias) in sample_outputs.iter_mut().zip(
+            weights_and_biases[input_count..]
+                .iter()
+                .step_by(input_count + 1),
+        ) {
+            *output = *bias;
+        }
+    }
+
+    let inputs = Array::<_, _, (Input, Sample)>::new(inputs, ColMajor([input_count, batch_size]));
+    let mut outputs =
+        Array::<_, _, (Output, Sample)>::new(outputs, ColMajor([output_count, batch_size]));
+    math::gemm(
+        &inputs,
+        &affine_weights(weights_and_biases, input_count),
+        &mut outputs,
+    )
+    .expect("shapes are consistent");
+}
+
+/// The backward pass of [`affine_forward`]. Adds the gradient with respect to the weights and biases to
+/// `parameter_gradients` and writes the gradient with respect to the inputs to `input_gradients`.
+pub(crate) fn affine_backward(
+    weights_and_biases: &[f32],
+    input_count: usize,
+    inputs: &[f32],
+    output_gradients: &[f32],
+    input_gradients: &mut [f32],
+    parameter_gradients: &mut [f32],
+) {
+    let output_count = weights_and_biases.len() / (input_count + 1);
+    let batch_size = output_gradients.len() / output_count;
+    assert_eq!(input_count * batch_size, inputs.len());
+    assert_eq!(input_count * batch_size, input_gradients.len());
+    assert_eq!(output_count * batch_size, output_gradients.len());
+    assert_eq!(weights_and_biases.len(), parameter_gradients.len());
+
+    for sample_gradients in output_gradients.chunks_exact(output_count) {
+        for (bias_gradient, gradient) in parameter_gradients[input_count..]
+            .iter_mut()
+            .step_by(input_count + 1)
+            .zip(sample_gradients)
+        {
+            *bias_gradient += gradient;
         }
     }
+
+    let output_gradients = Array::<_, _, (Output, Sample)>::new(
+        output_gradients,
+        ColMajor([output_count, batch_size]),
+    );
+    let inputs = Array::<_, _, (Input, Sample)>::new(inputs, ColMajor([input_count, batch_size]));
+
+    let mut weight_gradients = Array::<_, _, (Input, Output)>::new(
+        parameter_gradients,
+        Strided::new([input_count, output_count], [1, input_count + 1], 0),
+    );
+    math::gemm(
+        &output_gradients.view().transpose(),
+        &inputs,
+        &mut weight_gradients,
+    )
+    .expect("shapes are consistent");
+
+    input_gradients.fill(0.0);
+    let mut input_gradients = Array::<_, _, (Input, Sample)>::new(
+        input_gradients,
+        ColMajor([input_count, batch_size]),
+    );
+    math::gemm(
+        &output_gradients,
+        &affine_weights(weights_and_biases, input_count).transpose(),
+        &mut input_gradients,
+    )
+    .expect("shapes are consistent");
+}
+
+/// The forward pass of a [`FullyConnectedLayer`] with the given weights and biases, for layers that keep fully
+/// connected sublayers in their own parameters, like [`TransformerEncoderBlock`].
+pub(crate) fn fully_connected_forward<A: ActivationFunction>(
+    weights_and_biases: &[f32],
+    input_count: usize,
+    activation_function: &A,
+    inputs: &[f32],
+    outputs: &mut [f32],
+) {
+    affine_forward(weights_and_biases, input_count, inputs, outputs);
+
+    for output in outputs {
+        *output = activation_function.activate(*output);
+    }
+}
+
+/// The backward pass of [`fully_connected_forward`], as [`Layer::backward`] of a [`FullyConnectedLayer`]. Overwrites
+/// `output_gradients` with the gradient with respect to the pre-activations.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn fully_connected_backward<A: ActivationFunction>(
+    weights_and_biases: &[f32],
+    input_count: usize,
+    activation_function: &A,
+    inputs: &[f32],
+    outputs: &[f32],
+    output_gradients: &mut [f32],
+    input_gradients: &mut [f32],
+    parameter_gradients: &mut [f32],
+) {
+    assert_eq!(outputs.len(), output_gradients.len());
+
+    for (gradient, &output) in output_gradients.iter_mut().zip(outputs) {
+        *gradient *= activation_function.derivative(output);
+    }
+
+    affine_backward(
+        weights_and_biases,
+        input_count,
+        inputs,
+        output_gradients,
+        input_gradients,
+        parameter_gradients,
+    );
 }
 
 impl<A> FullyConnectedLayer<A>
@@ -43,25 +419,656 @@ where
         assert_eq!(self.input_count, inputs.len());
         assert_eq!(self.output_count, outputs.len());
 
-        #[allow(clippy::needless_range_loop)]
-        for output_idx in 0..self.output_count {
-            let mut sum = 0.0;
-            #[allow(clippy::needless_range_loop)]
-            for input_idx in 0..self.input_count {
-                sum += inputs[input_idx]
-                    * self.weights_and_biases[(self.input_count + 1) * output_idx + input_idx];
+        self.infer_batch(inputs, outputs);
+    }
+
+    /// Computes the outputs for a batch of samples. Both the inputs and the outputs are stored one sample after the
+    /// other.
+    pub fn infer_batch(&self, inputs: &[f32], outputs: &mut [f32]) {
+        fully_connected_forward(
+            &self.weights_and_biases,
+            self.input_count,
+            &self.activation_function,
+            inputs,
+            outputs,
+        );
+    }
+}
+
+/// A layer that processes a batch of samples at a time. Inputs, outputs and their gradients are stored one sample after
+/// the other.
+pub trait Layer: Send {
+    fn input_count(&self) -> usize;
+
+    fn output_count(&self) -> usize;
+
+    fn parameters(&self) -> &[f32];
+
+    fn parameters_mut(&mut self) -> &mut [f32];
+
+    /// Values that the layer updates itself rather than through gradients, like the running statistics of
+    /// [`BatchNorm`].
+    fn state(&self) -> &[f32] {
+        &[]
+    }
+
+    fn state_mut(&mut self) -> &mut [f32] {
+        &mut []
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]);
+
+    /// Given the `inputs` and `outputs` of the last call to `forward` and the gradient of the loss with respect to the
+    /// outputs, adds the gradient with respect to the parameters to `parameter_gradients` and writes the gradient with
+    /// respect to the inputs to `input_gradients`. The layer may overwrite `output_gradients` in the process.
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    );
+
+    fn clone_layer(&self) -> Box<dyn Layer>;
+
+    /// The kind of layer, as shown by [`Summary`]. Defaults to the type name without module paths.
+    fn name(&self) -> String {
+        summary::short_type_name::<Self>()
+    }
+
+    /// The shape of an input sample. Defaults to a flat vector of [`Layer::input_count`] values.
+    fn input_shape(&self) -> Vec<usize> {
+        vec![self.input_count()]
+    }
+
+    /// The shape of an output sample. Defaults to a flat vector of [`Layer::output_count`] values.
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.output_count()]
+    }
+
+    /// An estimate of the floating point operations of the forward pass per sample, counting a multiply-add as two.
+    /// Defaults to one per output, which suits elementwise layers.
+    fn flops(&self) -> usize {
+        self.output_count()
+    }
+
+    /// The fraction of output units whose gradient is zero for every sample of a batch with these `outputs`, like ReLUs
+    /// that never activate, which no longer learn. Layers without such units return `None`.
+    fn dead_fraction(&self, _outputs: &[f32]) -> Option<f32> {
+        None
+    }
+
+    /// Adds the gradient of the regularization penalty of the parameters to `parameter_gradients` and returns the
+    /// penalty. Unlike [`Layer::backward`], which runs once per shard, this is called once per batch, after the
+    /// gradients of the shards have been averaged.
+    fn penalty(&self, _parameter_gradients: &mut [f32]) -> f32 {
+        0.0
+    }
+
+    /// Constrains the parameters after every optimizer step, for example by decaying them.
+    fn constrain(&mut self, _learning_rate: f32) {}
+
+    /// Whether the outputs of every sample are already probabilities that sum to one, like those of [`Softmax`].
+    fn outputs_probabilities(&self) -> bool {
+        false
+    }
+
+    /// Switches between training and evaluation behaviour. Only layers that behave differently during training, like
+    /// [`Dropout`], need to implement this.
+    fn set_training(&mut self, _training: bool) {}
+
+    /// Reseeds the random number generator of layers that use one.
+    fn set_seed(&mut self, _seed: u64) {}
+
+    /// For layers whose parameter gradients are mostly zero, like [`Embedding`], returns the rows of parameters that
+    /// the backward passes since the last call added gradients to, and forgets them. [`Sequential::update`] and
+    /// [`DataParallel`](crate::parallel::DataParallel) then only visit those rows. Defaults to `None`, which stands
+    /// for all parameters and is always correct; sparse layers also return it when they have not recorded any rows.
+    fn take_touched_rows(&mut self) -> Option<TouchedRows> {
+        None
+    }
+}
+
+/// The rows of the parameters of a layer with sparse gradients that received gradients, see
+/// [`Layer::take_touched_rows`]. Every row holds `row_len` parameters, and `rows` is sorted without duplicates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TouchedRows {
+    pub row_len: usize,
+    pub rows: Vec<usize>,
+}
+
+impl TouchedRows {
+    /// The rows touched in either.
+    pub fn union(mut self, other: &TouchedRows) -> Self {
+        assert_eq!(self.row_len, other.row_len, "rows differ in length");
+        self.rows.extend_from_slice(&other.rows);
+        self.rows.sort_unstable();
+        self.rows.dedup();
+        self
+    }
+
+    /// The range of the parameters of every row.
+    pub fn ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.rows
+            .iter()
+            .map(|row| row * self.row_len..(row + 1) * self.row_len)
+    }
+}
+
+/// Derives an independent seed for stream `index` from `seed`, using the SplitMix64 finalizer so that nearby inputs
+/// give unrelated outputs.
+pub(crate) fn derive_seed(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Switches every layer between training and evaluation behaviour, for [`Sequential`] and [`Graph`].
+pub(crate) fn set_training<'a>(
+    layers: impl Iterator<Item = &'a mut Box<dyn Layer>>,
+    training: bool,
+) {
+    for layer in layers {
+        layer.set_training(training);
+    }
+}
+
+/// Reseeds every layer with its own seed derived from `seed` and the index of the layer.
+pub(crate) fn set_seed<'a>(layers: impl Iterator<Item = &'a mut Box<dyn Layer>>, seed: u64) {
+    for (index, layer) in layers.enumerate() {
+        layer.set_seed(derive_seed(seed, index as u64));
+    }
+}
+
+/// Adds the gradients of the penalties of every layer to `gradients`, which holds the gradients of the parameters of
+/// all layers one layer after the other, and returns the sum of the penalties.
+pub(crate) fn regularize<'a>(
+    layers: impl Iterator<Item = &'a dyn Layer>,
+    gradients: &mut [f32],
+) -> f32 {
+    let mut start = 0;
+    let mut penalty = 0.0;
+    for layer in layers {
+        let end = start + layer.parameters().len();
+        penalty += layer.penalty(&mut gradients[start..end]);
+        start = end;
+    }
+    penalty
+}
+
+/// Steps the optimizer for every layer, with the index of the layer as its parameter group, and then lets the layer
+/// constrain its parameters. Layers with sparse gradients only have the rows stepped that they touched, together with
+/// the rows in `touched` that copies of them touched, like the replicas of a
+/// [`DataParallel`](crate::parallel::DataParallel).
+pub(crate) fn update<'a, O>(
+    layers: impl Iterator<Item = &'a mut Box<dyn Layer>>,
+    optimizer: &mut O,
+    gradients: &[f32],
+    touched: Vec<Option<TouchedRows>>,
+) where
+    O: Optimizer + ?Sized,
+{
+    let mut touched = touched.into_iter();
+    let mut start = 0;
+    for (group, layer) in layers.enumerate() {
+        let rows = match (layer.take_touched_rows(), touched.next().flatten()) {
+            (Some(rows), Some(other)) => Some(rows.union(&other)),
+            (rows, other) => rows.or(other),
+        };
+        let parameters = layer.parameters_mut();
+        let end = start + parameters.len();
+        let gradients = &gradients[start..end];
+        match rows {
+            Some(rows) => optimizer.step_rows(group, parameters, gradients, &rows),
+            None => optimizer.step(group, parameters, gradients),
+        }
+        layer.constrain(optimizer.learning_rate());
+        start = end;
+    }
+}
+
+impl<A> Layer for FullyConnectedLayer<A>
+where
+    A: ActivationFunction + Clone + Send + 'static,
+{
+    fn input_count(&self) -> usize {
+        self.input_count
+    }
+
+    fn output_count(&self) -> usize {
+        self.output_count
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.weights_and_biases
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.weights_and_biases
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        self.infer_batch(inputs, outputs);
+    }
+
+    fn flops(&self) -> usize {
+        // A multiply-add per weight, then the bias and the activation.
+        (2 * self.input_count + 2) * self.output_count
+    }
+
+    fn penalty(&self, parameter_gradients: &mut [f32]) -> f32 {
+        self.regularization.penalty(
+            self.input_count,
+            &self.weights_and_biases,
+            parameter_gradients,
+        )
+    }
+
+    fn constrain(&mut self, learning_rate: f32) {
+        self.regularization.constrain(
+            self.input_count,
+            &mut self.weights_and_biases,
+            learning_rate,
+        );
+    }
+
+    fn dead_fraction(&self, outputs: &[f32]) -> Option<f32> {
+        let dead = (0..self.output_count)
+            .filter(|&unit| {
+                outputs
+                    .iter()
+                    .skip(unit)
+                    .step_by(self.output_count)
+                    .all(|&output| self.activation_function.derivative(output) == 0.0)
+            })
+            .count();
+        Some(dead as f32 / self.output_count as f32)
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        fully_connected_backward(
+            &self.weights_and_biases,
+            self.input_count,
+            &self.activation_function,
+            inputs,
+            outputs,
+            output_gradients,
+            input_gradients,
+            parameter_gradients,
+        );
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// A model that feeds the outputs of every layer into the next.
+pub struct Sequential {
+    layers: Vec<Box<dyn Layer>>,
+    training: bool,
+    anomaly_detection: bool,
+    anomaly: Option<Anomaly>,
+    /// Rows that copies of sparse layers touched, for the next update.
+    touched_rows: Vec<Option<TouchedRows>>,
+}
+
+impl Clone for Sequential {
+    fn clone(&self) -> Self {
+        Self {
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| layer.clone_layer())
+                .collect(),
+            training: self.training,
+            anomaly_detection: self.anomaly_detection,
+            anomaly: self.anomaly.clone(),
+            touched_rows: self.touched_rows.clone(),
+        }
+    }
+}
+
+impl Sequential {
+    pub fn new(layers: Vec<Box<dyn Layer>>) -> Self {
+        for pair in layers.windows(2) {
+            assert_eq!(
+                pair[0].output_count(),
+                pair[1].input_count(),
+                "consecutive layers must have matching sizes"
+            );
+        }
+
+        Self {
+            layers,
+            training: true,
+            anomaly_detection: false,
+            anomaly: None,
+            touched_rows: Vec::new(),
+        }
+    }
+
+    pub fn layers(&self) -> &[Box<dyn Layer>] {
+        &self.layers
+    }
+
+    pub fn input_count(&self) -> usize {
+        self.layers.first().map_or(0, |layer| layer.input_count())
+    }
+
+    pub fn output_count(&self) -> usize {
+        self.layers.last().map_or(0, |layer| layer.output_count())
+    }
+
+    /// The number of parameters of all layers together. Gradients for the model are laid out as the parameters of
+    /// each layer, one layer after the other.
+    pub fn parameter_count(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|layer| layer.parameters().len())
+            .sum()
+    }
+
+    /// The parameters of all layers, one layer after the other.
+    pub fn parameters(&self) -> Vec<f32> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.parameters().iter().copied())
+            .collect()
+    }
+
+    pub fn set_parameters(&mut self, parameters: &[f32]) {
+        assert_eq!(self.parameter_count(), parameters.len());
+        let mut parameters = parameters;
+        for layer in &mut self.layers {
+            let (head, tail) = parameters.split_at(layer.parameters().len());
+            layer.parameters_mut().copy_from_slice(head);
+            parameters = tail;
+        }
+    }
+
+    /// Copies the parameters, the state, the mode and whether anomalies are detected from `other`, which must have the
+    /// same architecture.
+    pub fn copy_parameters_from(&mut self, other: &Sequential) {
+        for (layer, other) in self.layers.iter_mut().zip(&other.layers) {
+            layer.parameters_mut().copy_from_slice(other.parameters());
+            layer.state_mut().copy_from_slice(other.state());
+        }
+        self.set_training(other.training);
+        self.anomaly_detection = other.anomaly_detection;
+    }
+
+    /// The number of state values of all layers together, laid out like the parameters.
+    pub fn state_count(&self) -> usize {
+        self.layers.iter().map(|layer| layer.state().len()).sum()
+    }
+
+    pub fn state(&self) -> Vec<f32> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.state().iter().copied())
+            .collect()
+    }
+
+    pub fn set_state(&mut self, state: &[f32]) {
+        assert_eq!(self.state_count(), state.len());
+        let mut state = state;
+        for layer in &mut self.layers {
+            let (head, tail) = state.split_at(layer.state().len());
+            layer.state_mut().copy_from_slice(head);
+            state = tail;
+        }
+    }
+
+    /// Describes every layer, for printing or comparing with other models.
+    pub fn summary(&self) -> Summary {
+        Summary {
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| LayerSummary::new(&**layer))
+                .collect(),
+        }
+    }
+
+    /// Models start out in training mode.
+    pub fn is_training(&self) -> bool {
+        self.training
+    }
+
+    pub fn set_training(&mut self, training: bool) {
+        self.training = training;
+        set_training(self.layers.iter_mut(), training);
+    }
+
+    pub fn train(&mut self) {
+        self.set_training(true);
+    }
+
+    pub fn eval(&mut self) {
+        self.set_training(false);
+    }
+
+    /// Checks every value of forward and backward passes and records the first that is not finite, see
+    /// [`anomaly`](crate::anomaly).
+    pub fn set_anomaly_detection(&mut self, enabled: bool) {
+        self.anomaly_detection = enabled;
+    }
+
+    pub fn detects_anomalies(&self) -> bool {
+        self.anomaly_detection
+    }
+
+    /// Returns the first anomaly found since the last call, and forgets it.
+    pub fn take_anomaly(&mut self) -> Option<Anomaly> {
+        self.anomaly.take()
+    }
+
+    /// Remembers `anomaly` unless an earlier one is still unread.
+    pub(crate) fn record_anomaly(&mut self, anomaly: Option<Anomaly>) {
+        if self.anomaly.is_none() {
+            self.anomaly = anomaly;
+        }
+    }
+
+    /// Reseeds every layer with its own seed derived from `seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        set_seed(self.layers.iter_mut(), seed);
+    }
+
+    /// Takes the rows that every layer with sparse gradients touched since the last call, see
+    /// [`Layer::take_touched_rows`].
+    pub(crate) fn take_touched_rows(&mut self) -> Vec<Option<TouchedRows>> {
+        self.layers
+            .iter_mut()
+            .map(|layer| layer.take_touched_rows())
+            .collect()
+    }
+
+    /// Makes the next [`Sequential::update`] also step `touched`, the rows that copies of the layers touched while
+    /// computing the gradients.
+    pub(crate) fn touch_rows(&mut self, touched: Vec<Option<TouchedRows>>) {
+        self.touched_rows = touched;
+    }
+
+    pub fn forward(&mut self, inputs: &[f32]) -> Vec<f32> {
+        let mut activations = self.activations(inputs);
+        activations.pop().unwrap_or_else(|| inputs.to_vec())
+    }
+
+    /// Runs the batch through the model in evaluation mode and turns the outputs of every sample into class
+    /// probabilities. The outputs of a model that ends in a [`Softmax`] are returned as they are; any other outputs
+    /// are taken to be logits or log-probabilities, as those of a model that ends in a [`LogSoftmax`].
+    pub fn predict_proba(&mut self, inputs: &[f32]) -> Vec<f32> {
+        let outputs = self.forward_eval(inputs);
+        if self
+            .layers
+            .last()
+            .is_some_and(|layer| layer.outputs_probabilities())
+        {
+            return outputs;
+        }
+
+        let mut probabilities = vec![0.0; outputs.len()];
+        for (outputs, probabilities) in outputs
+            .chunks_exact(self.output_count())
+            .zip(probabilities.chunks_exact_mut(self.output_count()))
+        {
+            softmax::softmax(outputs, probabilities);
+        }
+        probabilities
+    }
+
+    /// Runs the batch through the model in evaluation mode and returns the most likely class of every sample.
+    pub fn predict(&mut self, inputs: &[f32]) -> Vec<usize> {
+        self.forward_eval(inputs)
+            .chunks_exact(self.output_count())
+            .map(argmax)
+            .collect()
+    }
+
+    /// Runs the batch through the model in evaluation mode, and restores the mode afterwards.
+    pub(crate) fn forward_eval(&mut self, inputs: &[f32]) -> Vec<f32> {
+        let training = self.training;
+        self.eval();
+        let outputs = self.forward(inputs);
+        self.set_training(training);
+        outputs
+    }
+
+    /// Runs the batch through the model and returns the inputs of every layer followed by the outputs of the last.
+    pub(crate) fn activations(&mut self, inputs: &[f32]) -> Vec<Vec<f32>> {
+        let batch_size = inputs.len() / self.input_count().max(1);
+
+        let mut activations = Vec::with_capacity(self.layers.len() + 1);
+        activations.push(inputs.to_vec());
+
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            let mut outputs = vec![0.0; layer.output_count() * batch_size];
+            layer.forward(activations.last().unwrap(), &mut outputs);
+            if self.anomaly_detection && self.anomaly.is_none() {
+                self.anomaly = Anomaly::find(
+                    index,
+                    || layer.name(),
+                    Values::Outputs,
+                    &outputs,
+                    Some(layer.output_count()),
+                );
             }
+            activations.push(outputs);
+        }
+
+        activations
+    }
+
+    /// Adds the gradient of the loss summed over the batch to `gradients` and returns the summed loss. The
+    /// regularization penalties of the layers are not included, see [`Sequential::regularize`].
+    pub fn gradients<L>(
+        &mut self,
+        inputs: &[f32],
+        targets: &[f32],
+        loss: &L,
+        gradients: &mut [f32],
+    ) -> f32
+    where
+        L: Loss + ?Sized,
+    {
+        assert_eq!(self.parameter_count(), gradients.len());
+
+        let activations = self.activations(inputs);
+        let outputs = activations.last().unwrap();
 
-            let bias =
-                self.weights_and_biases[(self.input_count + 1) * output_idx + self.input_count];
+        let mut output_gradients = vec![0.0; outputs.len()];
+        let loss = loss.loss(outputs, targets, &mut output_gradients);
+        if self.anomaly_detection {
+            if let Some(layer) = self.layers.last() {
+                let anomaly = Anomaly::find(
+                    self.layers.len() - 1,
+                    || layer.name(),
+                    Values::LossGradients,
+                    &output_gradients,
+                    Some(layer.output_count()),
+                );
+                self.record_anomaly(anomaly);
+            }
+        }
 
-            outputs[output_idx] = self.activation_function.activate(sum + bias);
+        let mut end = gradients.len();
+        for (index, (layer, io)) in self
+            .layers
+            .iter_mut()
+            .zip(activations.windows(2))
+            .enumerate()
+            .rev()
+        {
+            let start = end - layer.parameters().len();
+            let mut input_gradients = vec![0.0; io[0].len()];
+            layer.backward(
+                &io[0],
+                &io[1],
+                &mut output_gradients,
+                &mut input_gradients,
+                &mut gradients[start..end],
+            );
+            if self.anomaly_detection && self.anomaly.is_none() {
+                // Gradients accumulate into `gradients`, so earlier batches may already have made them non-finite.
+                self.anomaly = Anomaly::find(
+                    index,
+                    || layer.name(),
+                    Values::ParameterGradients,
+                    &gradients[start..end],
+                    None,
+                )
+                .or_else(|| {
+                    Anomaly::find(
+                        index,
+                        || layer.name(),
+                        Values::InputGradients,
+                        &input_gradients,
+                        Some(layer.input_count()),
+                    )
+                });
+            }
+            output_gradients = input_gradients;
+            end = start;
         }
+
+        loss
+    }
+
+    /// Adds the gradients of the regularization penalties of all layers to `gradients` and returns the sum of the
+    /// penalties. Since the penalties belong to the batch rather than to its samples, call this once the gradient of
+    /// [`Sequential::gradients`] has been averaged over the batch.
+    /// [`DataParallel::gradients`](crate::parallel::DataParallel::gradients) does this for every batch.
+    pub fn regularize(&self, gradients: &mut [f32]) -> f32 {
+        assert_eq!(self.parameter_count(), gradients.len());
+        regularize(self.layers.iter().map(|layer| &**layer), gradients)
+    }
+
+    /// Lets the optimizer update the parameters of every layer, and then lets every layer constrain its parameters.
+    /// The parameters of layer `i` are passed as group `i`. Layers with sparse gradients, like [`Embedding`], only have
+    /// the rows updated that received gradients since the last update, see [`Optimizer::step_rows`].
+    pub fn update<O>(&mut self, optimizer: &mut O, gradients: &[f32])
+    where
+        O: Optimizer + ?Sized,
+    {
+        assert_eq!(self.parameter_count(), gradients.len());
+        let touched = std::mem::take(&mut self.touched_rows);
+        update(self.layers.iter_mut(), optimizer, gradients, touched);
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     #[test]
@@ -73,4 +1080,177 @@ mod tests {
         layer.infer(&inputs, &mut outputs);
         assert_eq!(outputs, [48.0, 88.0, 128.0]);
     }
+
+    #[test]
+    fn infer_batch() {
+        let layer = FullyConnectedLayer::new(2, 2, [1.0, -1.0, 0.5, 2.0, 1.0, -10.0]);
+        let inputs = [1.0, 2.0, 3.0, 1.0, 6.0, 1.0];
+        let mut outputs = [0.0; 6];
+        layer.infer_batch(&inputs, &mut outputs);
+        assert_eq!(outputs, [0.0, 0.0, 2.5, 0.0, 5.5, 3.0]);
+    }
+
+    /// A model whose gradients [`assert_finite_differences`] can check, layer by layer.
+    pub(crate) trait Layers {
+        fn layer_mut(&mut self, index: usize) -> Option<&mut Box<dyn Layer>>;
+    }
+
+    impl Layers for Sequential {
+        fn layer_mut(&mut self, index: usize) -> Option<&mut Box<dyn Layer>> {
+            self.layers.get_mut(index)
+        }
+    }
+
+    /// Compares the gradients that `loss` adds to its second argument with central finite differences of the loss it
+    /// returns, for every parameter of every layer of `model`.
+    pub(crate) fn assert_finite_differences<M: Layers>(
+        model: &mut M,
+        mut loss: impl FnMut(&mut M, &mut [f32]) -> f32,
+    ) {
+        let mut parameter_counts = Vec::new();
+        while let Some(layer) = model.layer_mut(parameter_counts.len()) {
+            parameter_counts.push(layer.parameters().len());
+        }
+        let parameter_count = parameter_counts.iter().sum();
+
+        let mut gradients = vec![0.0; parameter_count];
+        loss(model, &mut gradients);
+
+        let epsilon = 1e-2;
+        let mut index = 0;
+        for (layer, &count) in parameter_counts.iter().enumerate() {
+            for parameter in 0..count {
+                let mut loss_at = |model: &mut M, value: f32| {
+                    model.layer_mut(layer).unwrap().parameters_mut()[parameter] = value;
+                    loss(model, &mut vec![0.0; parameter_count])
+                };
+                let original = model.layer_mut(layer).unwrap().parameters()[parameter];
+                let above = loss_at(model, original + epsilon);
+                let below = loss_at(model, original - epsilon);
+                model.layer_mut(layer).unwrap().parameters_mut()[parameter] = original;
+
+                let numerical = (above - below) / (2.0 * epsilon);
+                assert!(
+                    (numerical - gradients[index]).abs() < 1e-2 * (1.0 + numerical.abs()),
+                    "layer {layer} parameter {parameter}: {numerical} != {}",
+                    gradients[index]
+                );
+                index += 1;
+            }
+        }
+        assert_eq!(index, parameter_count);
+    }
+
+    /// Compares the gradients computed by `model` with central finite differences of the loss.
+    pub(crate) fn assert_gradients_match(model: &mut Sequential, inputs: &[f32], targets: &[f32]) {
+        use crate::loss::MeanSquaredError;
+
+        assert_finite_differences(model, |model, gradients| {
+            model.gradients(inputs, targets, &MeanSquaredError, gradients)
+        });
+    }
+
+    #[test]
+    fn activations() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let mut model = Sequential::new(vec![
+            Activation::Sigmoid.fully_connected(2, 3, uniform(&mut rng, 3 * 3)),
+            Activation::Tanh.fully_connected(3, 2, uniform(&mut rng, 4 * 2)),
+        ]);
+        assert_gradients_match(&mut model, &[0.5, -1.0, 2.0, 0.25], &[0.1, 0.2, -0.3, 0.4]);
+
+        for activation in Activation::ALL {
+            assert_eq!(activation.to_string().parse(), Ok(activation));
+        }
+        assert!("softplus".parse::<Activation>().is_err());
+    }
+
+    #[test]
+    fn classifier() {
+        use crate::{loss::NegativeLogLikelihood, optim::Sgd};
+        use rand::{rngs::StdRng, SeedableRng};
+
+        // Points in the square belong to class 1 above the diagonal and to class 0 below it.
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let inputs = uniform(&mut rng, 2 * 64);
+        let labels: Vec<usize> = inputs.chunks_exact(2).map(|p| (p[1] > p[0]) as usize).collect();
+        let targets: Vec<f32> = labels
+            .iter()
+            .flat_map(|&label| if label == 0 { [1.0, 0.0] } else { [0.0, 1.0] })
+            .collect();
+
+        let mut model = Sequential::new(vec![
+            Box::new(FullyConnectedLayer::with_activation(
+                2,
+                2,
+                uniform(&mut rng, 3 * 2),
+                Identity,
+            )),
+            Box::new(LogSoftmax::new(2)),
+        ]);
+        let mut optimizer = Sgd::new(0.1);
+        let mut gradients = vec![0.0; model.parameter_count()];
+        for _ in 0..100 {
+            gradients.fill(0.0);
+            model.gradients(&inputs, &targets, &NegativeLogLikelihood, &mut gradients);
+            model.update(&mut optimizer, &gradients);
+        }
+
+        let probabilities = model.predict_proba(&inputs);
+        for sample in probabilities.chunks_exact(2) {
+            assert!((sample.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        }
+        let correct = model
+            .predict(&inputs)
+            .iter()
+            .zip(&labels)
+            .filter(|(prediction, label)| prediction == label)
+            .count();
+        assert!(correct >= 60, "{correct} of 64 correct");
+
+        // A model that ends in a softmax already outputs the probabilities.
+        let mut softmax = Sequential::new(vec![
+            model.layers[0].clone_layer(),
+            Box::new(Softmax::new(2)),
+        ]);
+        for (a, b) in softmax.predict_proba(&inputs).iter().zip(&probabilities) {
+            assert!((a - b).abs() < 1e-6, "{a} != {b}");
+        }
+    }
+
+    /// Samples values uniformly from `-1..1`.
+    pub(crate) fn uniform(rng: &mut impl rand::Rng, count: usize) -> Vec<f32> {
+        use rand::distributions::Uniform;
+
+        (0..count)
+            .map(|_| rng.sample(Uniform::new(-1.0, 1.0)))
+            .collect()
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let mut model = Sequential::new(vec![
+            Box::new(FullyConnectedLayer::with_activation(
+                3,
+                4,
+                uniform(&mut rng, 16),
+                Identity,
+            )),
+            Box::new(FullyConnectedLayer::with_activation(
+                4,
+                2,
+                uniform(&mut rng, 10),
+                Identity,
+            )),
+        ]);
+        let inputs = uniform(&mut rng, 3 * 5);
+        let targets = uniform(&mut rng, 2 * 5);
+
+        assert_gradients_match(&mut model, &inputs, &targets);
+    }
 }
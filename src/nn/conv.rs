@@ -0,0 +1,464 @@
+use super::{ActivationFunction, Layer, ReLU};
+use crate::{
+    index_type,
+    math::{self, Array, ColMajor, RowMajor, Strided},
+};
+
+index_type!(Patch);
+index_type!(Position);
+index_type!(Channel);
+
+/// A two dimensional convolution over samples stored as channels, then rows, then columns.
+///
+/// Like [`super::FullyConnectedLayer`], every output channel has a row of weights followed by a bias. The weights in
+/// a row are stored by input channel, then kernel row, then kernel column. The convolution is computed by copying the
+/// patches of the input into the columns of a matrix (im2col) and multiplying that with the weights.
+#[derive(Debug, Clone)]
+pub struct Conv2d<A = ReLU> {
+    input_shape: [usize; 3],
+    output_channels: usize,
+    kernel_size: [usize; 2],
+    stride: [usize; 2],
+    padding: [usize; 2],
+    dilation: [usize; 2],
+    weights_and_biases: Vec<f32>,
+    activation_function: A,
+}
+
+impl Conv2d<ReLU> {
+    /// Creates a convolution with a stride and dilation of one and no padding. `input_shape` is the number of
+    /// channels, rows and columns of a sample.
+    pub fn new<I: IntoIterator<Item = f32>>(
+        input_shape: [usize; 3],
+        output_channels: usize,
+        kernel_size: [usize; 2],
+        initializer: I,
+    ) -> Self {
+        Self::with_activation(input_shape, output_channels, kernel_size, initializer, ReLU)
+    }
+}
+
+impl<A> Conv2d<A> {
+    pub fn with_activation<I: IntoIterator<Item = f32>>(
+        input_shape: [usize; 3],
+        output_channels: usize,
+        kernel_size: [usize; 2],
+        initializer: I,
+        activation_function: A,
+    ) -> Self {
+        let [channels, _, _] = input_shape;
+        let [kernel_rows, kernel_cols] = kernel_size;
+        let parameter_count = (channels * kernel_rows * kernel_cols + 1) * output_channels;
+
+        let weights_and_biases: Vec<f32> = initializer.into_iter().take(parameter_count).collect();
+        assert_eq!(
+            weights_and_biases.len(),
+            parameter_count,
+            "initializer ran out of values"
+        );
+
+        let conv = Self {
+            input_shape,
+            output_channels,
+            kernel_size,
+            stride: [1, 1],
+            padding: [0, 0],
+            dilation: [1, 1],
+            weights_and_biases,
+            activation_function,
+        };
+        conv.assert_valid();
+        conv
+    }
+
+    pub fn with_stride(mut self, stride: [usize; 2]) -> Self {
+        self.stride = stride;
+        self.assert_valid();
+        self
+    }
+
+    /// Pads every side of the input with the given number of rows and columns of zeros.
+    pub fn with_padding(mut self, padding: [usize; 2]) -> Self {
+        self.padding = padding;
+        self.assert_valid();
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: [usize; 2]) -> Self {
+        self.dilation = dilation;
+        self.assert_valid();
+        self
+    }
+
+    fn assert_valid(&self) {
+        assert!(
+            self.output_channels > 0,
+            "a layer needs at least one output"
+        );
+        assert!(
+            self.kernel_size.iter().all(|&k| k > 0),
+            "kernel size must be positive"
+        );
+        assert!(
+            self.stride.iter().all(|&s| s > 0),
+            "stride must be positive"
+        );
+        assert!(
+            self.dilation.iter().all(|&d| d > 0),
+            "dilation must be positive"
+        );
+    }
+
+    pub fn input_shape(&self) -> [usize; 3] {
+        self.input_shape
+    }
+
+    /// The number of channels, rows and columns of an output sample. Panics if the dilated kernel does not fit in the
+    /// padded input, which is only checked here so that padding can be set after the constructor.
+    pub fn output_shape(&self) -> [usize; 3] {
+        let size = |axis: usize| {
+            (self.input_shape[axis + 1] + 2 * self.padding[axis])
+                .checked_sub(self.extent(axis))
+                .expect("kernel does not fit in the padded input")
+                / self.stride[axis]
+                + 1
+        };
+        [self.output_channels, size(0), size(1)]
+    }
+
+    /// The number of input rows or columns covered by the dilated kernel.
+    fn extent(&self, axis: usize) -> usize {
+        self.dilation[axis] * (self.kernel_size[axis] - 1) + 1
+    }
+
+    fn patch_len(&self) -> usize {
+        let [channels, _, _] = self.input_shape;
+        channels * self.kernel_size[0] * self.kernel_size[1]
+    }
+
+    fn position_count(&self) -> usize {
+        let [_, rows, cols] = self.output_shape();
+        rows * cols
+    }
+
+    /// Calls `f` with the index into the patch matrix and the index into the input sample for every patch element that
+    /// falls inside the input. Patch elements that fall in the padding are skipped.
+    fn for_each_patch_element<F>(&self, mut f: F)
+    where
+        F: FnMut(usize, usize),
+    {
+        let [channels, input_rows, input_cols] = self.input_shape;
+        let [_, output_rows, output_cols] = self.output_shape();
+        let [kernel_rows, kernel_cols] = self.kernel_size;
+        let positions = output_rows * output_cols;
+
+        for channel in 0..channels {
+            for kernel_row in 0..kernel_rows {
+                for kernel_col in 0..kernel_cols {
+                    let patch = (channel * kernel_rows + kernel_row) * kernel_cols + kernel_col;
+
+                    for output_row in 0..output_rows {
+                        let Some(input_row) = (output_row * self.stride[0]
+                            + kernel_row * self.dilation[0])
+                            .checked_sub(self.padding[0])
+                            .filter(|&row| row < input_rows)
+                        else {
+                            continue;
+                        };
+
+                        for output_col in 0..output_cols {
+                            let Some(input_col) = (output_col * self.stride[1]
+                                + kernel_col * self.dilation[1])
+                                .checked_sub(self.padding[1])
+                                .filter(|&col| col < input_cols)
+                            else {
+                                continue;
+                            };
+
+                            f(
+                                patch * positions + output_row * output_cols + output_col,
+                                (channel * input_rows + input_row) * input_cols + input_col,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copies the patches of one sample into a matrix with a row per patch element and a column per output position.
+    fn im2col(&self, input: &[f32], columns: &mut [f32]) {
+        columns.fill(0.0);
+        self.for_each_patch_element(|column_index, input_index| {
+            columns[column_index] = input[input_index];
+        });
+    }
+
+    /// Adds the gradients of the patch matrix back onto the elements of the sample they were copied from.
+    fn col2im(&self, columns: &[f32], input: &mut [f32]) {
+        input.fill(0.0);
+        self.for_each_patch_element(|column_index, input_index| {
+            input[input_index] += columns[column_index];
+        });
+    }
+
+    /// The weights without the biases, indexed by patch element and then output channel.
+    fn weights(&self) -> Array<&[f32], Strided<2>, (Patch, Channel)> {
+        let patch_len = self.patch_len();
+        Array::new(
+            &self.weights_and_biases[..],
+            Strided::new([patch_len, self.output_channels], [1, patch_len + 1], 0),
+        )
+    }
+
+    fn biases(&self) -> impl Iterator<Item = f32> + '_ {
+        self.weights_and_biases[self.patch_len()..]
+            .iter()
+            .step_by(self.patch_len() + 1)
+            .copied()
+    }
+}
+
+impl<A> Layer for Conv2d<A>
+where
+    A: ActivationFunction + Clone + Send + 'static,
+{
+    fn input_count(&self) -> usize {
+        self.input_shape.iter().product()
+    }
+
+    fn output_count(&self) -> usize {
+        self.output_shape().iter().product()
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        self.input_shape.to_vec()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        Conv2d::output_shape(self).to_vec()
+    }
+
+    fn flops(&self) -> usize {
+        // A multiply-add per weight of the patch, then the bias and the activation.
+        (2 * self.patch_len() + 2) * self.output_count()
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.weights_and_biases
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.weights_and_biases
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        let input_count = self.input_count();
+        let output_count = self.output_count();
+        let batch_size = outputs.len() / output_count;
+        assert_eq!(input_count * batch_size, inputs.len());
+        assert_eq!(output_count * batch_size, outputs.len());
+
+        let patch_len = self.patch_len();
+        let positions = self.position_count();
+        let mut columns = vec![0.0; patch_len * positions];
+
+        for (input, output) in inputs
+            .chunks_exact(input_count)
+            .zip(outputs.chunks_exact_mut(output_count))
+        {
+            self.im2col(input, &mut columns);
+
+            for (channel_outputs, bias) in output.chunks_exact_mut(positions).zip(self.biases()) {
+                channel_outputs.fill(bias);
+            }
+
+            let columns = Array::<_, _, (Position, Patch)>::new(
+                &columns[..],
+                ColMajor([positions, patch_len]),
+            );
+            let mut output = Array::<_, _, (Position, Channel)>::new(
+                &mut *output,
+                ColMajor([positions, self.output_channels]),
+            );
+            math::gemm(&self.weights(), &columns, &mut output).expect("shapes are consistent");
+        }
+
+        for output in outputs {
+            *output = self.activation_function.activate(*output);
+        }
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        let input_count = self.input_count();
+        let output_count = self.output_count();
+        let batch_size = outputs.len() / output_count;
+        assert_eq!(input_count * batch_size, inputs.len());
+        assert_eq!(input_count * batch_size, input_gradients.len());
+        assert_eq!(output_count * batch_size, output_gradients.len());
+        assert_eq!(self.weights_and_biases.len(), parameter_gradients.len());
+
+        for (gradient, &output) in output_gradients.iter_mut().zip(outputs) {
+            *gradient *= self.activation_function.derivative(output);
+        }
+
+        let patch_len = self.patch_len();
+        let positions = self.position_count();
+        let mut columns = vec![0.0; patch_len * positions];
+        let mut column_gradients = vec![0.0; patch_len * positions];
+
+        for ((input, output_gradients), input_gradients) in inputs
+            .chunks_exact(input_count)
+            .zip(output_gradients.chunks_exact(output_count))
+            .zip(input_gradients.chunks_exact_mut(input_count))
+        {
+            for (channel_gradients, bias_gradient) in output_gradients.chunks_exact(positions).zip(
+                parameter_gradients[patch_len..]
+                    .iter_mut()
+                    .step_by(patch_len + 1),
+            ) {
+                *bias_gradient += channel_gradients.iter().sum::<f32>();
+            }
+
+            let output_gradients = Array::<_, _, (Position, Channel)>::new(
+                output_gradients,
+                ColMajor([positions, self.output_channels]),
+            );
+
+            self.im2col(input, &mut columns);
+            let columns = Array::<_, _, (Patch, Position)>::new(
+                &columns[..],
+                RowMajor([patch_len, positions]),
+            );
+            let mut weight_gradients = Array::<_, _, (Patch, Channel)>::new(
+                &mut *parameter_gradients,
+                Strided::new([patch_len, self.output_channels], [1, patch_len + 1], 0),
+            );
+            math::gemm(&output_gradients, &columns, &mut weight_gradients)
+                .expect("shapes are consistent");
+
+            column_gradients.fill(0.0);
+            let mut column_gradients_array = Array::<_, _, (Position, Patch)>::new(
+                &mut column_gradients[..],
+                ColMajor([positions, patch_len]),
+            );
+            math::gemm(
+                &self.weights().transpose(),
+                &output_gradients,
+                &mut column_gradients_array,
+            )
+            .expect("shapes are consistent");
+
+            self.col2im(&column_gradients, input_gradients);
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{
+        tests::{assert_gradients_match, uniform},
+        FullyConnectedLayer, Identity, Sequential,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn forward() {
+        // A 2x2 kernel that sums its window, with a bias of one.
+        let mut conv = Conv2d::with_activation([1, 3, 3], 1, [2, 2], [1.0; 5], Identity);
+        let inputs = [
+            1.0, 2.0, 3.0, //
+            4.0, 5.0, 6.0, //
+            7.0, 8.0, 9.0, //
+        ];
+
+        let mut outputs = [0.0; 4];
+        conv.forward(&inputs, &mut outputs);
+        assert_eq!(outputs, [13.0, 17.0, 25.0, 29.0]);
+
+        let mut conv = conv.with_padding([1, 1]).with_stride([2, 2]);
+        assert_eq!(conv.output_shape(), [1, 2, 2]);
+        conv.forward(&inputs, &mut outputs);
+        assert_eq!(outputs, [2.0, 6.0, 12.0, 29.0]);
+
+        let mut conv = conv
+            .with_padding([0, 0])
+            .with_stride([1, 1])
+            .with_dilation([2, 2]);
+        let mut output = [0.0; 1];
+        conv.forward(&inputs, &mut output);
+        assert_eq!(output, [21.0]);
+    }
+
+    #[test]
+    fn output_shape() {
+        let conv = Conv2d::new([1, 28, 28], 6, [5, 5], std::iter::repeat(0.0));
+        assert_eq!(conv.output_shape(), [6, 24, 24]);
+
+        let conv = conv.with_padding([2, 2]);
+        assert_eq!(conv.output_shape(), [6, 28, 28]);
+
+        let conv = conv.with_stride([2, 2]).with_dilation([2, 1]);
+        assert_eq!(conv.output_shape(), [6, 12, 14]);
+
+        // The kernel only has to fit once the input is padded.
+        let conv = Conv2d::new([1, 2, 2], 1, [3, 3], std::iter::repeat(0.0)).with_padding([1, 1]);
+        assert_eq!(conv.output_shape(), [1, 2, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "kernel size must be positive")]
+    fn empty_kernel() {
+        Conv2d::new([1, 4, 4], 1, [0, 3], std::iter::repeat(0.0));
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+
+        // The leading fully connected layer checks the gradient with respect to the inputs of the convolution.
+        let conv = Conv2d::with_activation(
+            [2, 5, 4],
+            3,
+            [3, 2],
+            uniform(&mut rng, (2 * 3 * 2 + 1) * 3),
+            Identity,
+        )
+        .with_padding([1, 2])
+        .with_stride([2, 1])
+        .with_dilation([1, 2]);
+        let conv_outputs = conv.output_count();
+
+        let mut model = Sequential::new(vec![
+            Box::new(FullyConnectedLayer::with_activation(
+                3,
+                40,
+                uniform(&mut rng, 4 * 40),
+                Identity,
+            )),
+            Box::new(conv),
+            Box::new(FullyConnectedLayer::with_activation(
+                conv_outputs,
+                2,
+                uniform(&mut rng, (conv_outputs + 1) * 2),
+                Identity,
+            )),
+        ]);
+        let inputs = uniform(&mut rng, 3 * 2);
+        let targets = uniform(&mut rng, 2 * 2);
+
+        assert_gradients_match(&mut model, &inputs, &targets);
+    }
+}
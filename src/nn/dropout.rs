@@ -0,0 +1,163 @@
+use super::Layer;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Inverted dropout. During training every element is zeroed with probability `probability` and the remaining
+/// elements are scaled by `1 / (1 - probability)`, so that nothing needs to change during evaluation, where the layer
+/// passes its inputs through unchanged.
+#[derive(Debug, Clone)]
+pub struct Dropout {
+    count: usize,
+    probability: f32,
+    training: bool,
+    rng: StdRng,
+    mask: Vec<f32>,
+}
+
+impl Dropout {
+    pub fn new(count: usize, probability: f32, seed: u64) -> Self {
+        assert!(
+            (0.0..1.0).contains(&probability),
+            "probability must be in 0..1"
+        );
+
+        Self {
+            count,
+            probability,
+            training: true,
+            rng: StdRng::seed_from_u64(seed),
+            mask: Vec::new(),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.training && self.probability > 0.0
+    }
+}
+
+impl Layer for Dropout {
+    fn input_count(&self) -> usize {
+        self.count
+    }
+
+    fn output_count(&self) -> usize {
+        self.count
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &[]
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut []
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        assert_eq!(inputs.len(), outputs.len());
+
+        if !self.is_active() {
+            outputs.copy_from_slice(inputs);
+            return;
+        }
+
+        let scale = 1.0 / (1.0 - self.probability);
+        self.mask.clear();
+        self.mask.extend((0..inputs.len()).map(|_| {
+            if self.rng.gen::<f32>() < self.probability {
+                0.0
+            } else {
+                scale
+            }
+        }));
+
+        for ((output, input), mask) in outputs.iter_mut().zip(inputs).zip(&self.mask) {
+            *output = input * mask;
+        }
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        _parameter_gradients: &mut [f32],
+    ) {
+        assert_eq!(inputs.len(), input_gradients.len());
+        assert_eq!(outputs.len(), output_gradients.len());
+
+        if !self.is_active() {
+            input_gradients.copy_from_slice(output_gradients);
+            return;
+        }
+
+        assert_eq!(self.mask.len(), outputs.len(), "backward without forward");
+        for ((input_gradient, output_gradient), mask) in input_gradients
+            .iter_mut()
+            .zip(output_gradients.iter())
+            .zip(&self.mask)
+        {
+            *input_gradient = output_gradient * mask;
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn train_and_eval() {
+        let mut dropout = Dropout::new(1000, 0.25, 0);
+        let inputs = [2.0; 1000];
+        let mut outputs = [0.0; 1000];
+
+        dropout.forward(&inputs, &mut outputs);
+        let dropped = outputs.iter().filter(|&&output| output == 0.0).count();
+        assert!((200..300).contains(&dropped), "{dropped} dropped");
+        assert!(outputs
+            .iter()
+            .all(|&output| output == 0.0 || output == 2.0 / 0.75));
+
+        let mut input_gradients = [0.0; 1000];
+        dropout.backward(
+            &inputs,
+            &outputs,
+            &mut [1.0; 1000],
+            &mut input_gradients,
+            &mut [],
+        );
+        for (output, gradient) in outputs.iter().zip(input_gradients) {
+            assert_eq!(*output == 0.0, gradient == 0.0);
+        }
+
+        dropout.set_training(false);
+        dropout.forward(&inputs, &mut outputs);
+        assert_eq!(outputs, inputs);
+    }
+
+    #[test]
+    fn seeded() {
+        let run = |seed| {
+            let mut dropout = Dropout::new(64, 0.5, 0);
+            dropout.set_seed(seed);
+            let mut outputs = [0.0; 64];
+            dropout.forward(&[1.0; 64], &mut outputs);
+            outputs
+        };
+
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+}
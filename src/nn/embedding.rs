@@ -0,0 +1,221 @@
+use super::{Layer, TouchedRows};
+
+/// Looks up a learned vector for every input, which holds the index of a category. Indices are passed as `f32` like
+/// all other inputs, which represents every index below 2^24 exactly.
+///
+/// The gradients are sparse: the backward pass only adds to the gradients of the rows of the table that occur in the
+/// batch and records those rows, see [`Layer::take_touched_rows`].
+/// [`DataParallel`](crate::parallel::DataParallel) only sums those rows across shards, and [`Sequential::update`]
+/// only steps those rows, so with [`Sgd`](crate::optim::Sgd) a step costs about the same however many categories
+/// there are. The gradient buffer itself still holds the whole table. Inputs have no gradient; their input gradients
+/// are zero.
+///
+/// [`Sequential::update`]: super::Sequential::update
+#[derive(Debug, Clone)]
+pub struct Embedding {
+    categories: usize,
+    dimension: usize,
+    len: usize,
+    table: Vec<f32>,
+    /// The rows that backward passes added gradients to since they were last taken, possibly more than once.
+    touched: Vec<usize>,
+}
+
+impl Embedding {
+    /// Creates a table with a row of `dimension` values for each of `categories` categories, taking the values from
+    /// `initializer` row by row. Every sample holds a single index.
+    pub fn new<I: IntoIterator<Item = f32>>(
+        categories: usize,
+        dimension: usize,
+        initializer: I,
+    ) -> Self {
+        assert!(dimension > 0, "embeddings need at least one dimension");
+        let table: Vec<f32> = initializer
+            .into_iter()
+            .take(categories * dimension)
+            .collect();
+        assert_eq!(
+            table.len(),
+            categories * dimension,
+            "initializer ran out of values"
+        );
+
+        Self {
+            categories,
+            dimension,
+            len: 1,
+            table,
+            touched: Vec::new(),
+        }
+    }
+
+    /// Treats every sample as a sequence of `len` indices into the same table, and outputs their vectors one after the
+    /// other.
+    pub fn with_sequence_len(mut self, len: usize) -> Self {
+        self.len = len;
+        self
+    }
+
+    fn row(&self, input: f32) -> usize {
+        let index = input as usize;
+        assert!(
+            index as f32 == input && index < self.categories,
+            "{input} is not a category below {}",
+            self.categories
+        );
+        index
+    }
+}
+
+impl Layer for Embedding {
+    fn input_count(&self) -> usize {
+        self.len
+    }
+
+    fn output_count(&self) -> usize {
+        self.len * self.dimension
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.len, self.dimension]
+    }
+
+    fn flops(&self) -> usize {
+        // A lookup does no arithmetic.
+        0
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.table
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.table
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        assert_eq!(inputs.len() * self.dimension, outputs.len());
+        for (&input, output) in inputs.iter().zip(outputs.chunks_exact_mut(self.dimension)) {
+            let row = self.row(input);
+            output.copy_from_slice(&self.table[row * self.dimension..][..self.dimension]);
+        }
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        _outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        assert_eq!(inputs.len() * self.dimension, output_gradients.len());
+        for (&input, output_gradients) in inputs
+            .iter()
+            .zip(output_gradients.chunks_exact(self.dimension))
+        {
+            let row = self.row(input);
+            for (gradient, output_gradient) in parameter_gradients[row * self.dimension..]
+                .iter_mut()
+                .zip(output_gradients)
+            {
+                *gradient += output_gradient;
+            }
+            self.touched.push(row);
+        }
+        // Keep the record from growing without bound if nobody takes it.
+        if self.touched.len() > self.categories {
+            self.touched.sort_unstable();
+            self.touched.dedup();
+        }
+        input_gradients.fill(0.0);
+    }
+
+    fn take_touched_rows(&mut self) -> Option<TouchedRows> {
+        if self.touched.is_empty() {
+            return None;
+        }
+        let mut rows = std::mem::take(&mut self.touched);
+        rows.sort_unstable();
+        rows.dedup();
+        Some(TouchedRows {
+            row_len: self.dimension,
+            rows,
+        })
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        loss::MeanSquaredError,
+        nn::{
+            tests::{assert_gradients_match, uniform},
+            FullyConnectedLayer, Identity, Sequential,
+        },
+        optim::Sgd,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn lookup() {
+        let mut embedding =
+            Embedding::new(3, 2, [0.0, 1.0, 10.0, 11.0, 20.0, 21.0]).with_sequence_len(2);
+        let mut outputs = vec![0.0; 8];
+        embedding.forward(&[2.0, 0.0, 1.0, 1.0], &mut outputs);
+        assert_eq!(outputs, [20.0, 21.0, 0.0, 1.0, 10.0, 11.0, 10.0, 11.0]);
+
+        // Only the rows that occur in the batch receive gradients.
+        let mut parameter_gradients = vec![0.0; 6];
+        embedding.backward(
+            &[2.0, 2.0],
+            &outputs[..4],
+            &mut [1.0, 2.0, 3.0, 4.0],
+            &mut [0.0; 2],
+            &mut parameter_gradients,
+        );
+        assert_eq!(parameter_gradients, [0.0, 0.0, 0.0, 0.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn sparse_update() {
+        let table = [0.0, 1.0, 10.0, 11.0, 20.0, 21.0];
+        let mut model = Sequential::new(vec![Box::new(Embedding::new(3, 2, table))]);
+        let mut gradients = vec![0.0; 6];
+        model.gradients(&[2.0], &[0.0, 0.0], &MeanSquaredError, &mut gradients);
+
+        // Only the touched row is stepped, even where the gradient of another row is not zero.
+        gradients[0] = 1.0;
+        model.update(&mut Sgd::new(1.0), &gradients);
+        let parameters = model.parameters();
+        assert_eq!(parameters[..4], table[..4]);
+        assert_ne!(parameters[4..], table[4..]);
+
+        // The touched rows are forgotten after the update, so without new ones all rows are stepped.
+        model.update(&mut Sgd::new(1.0), &gradients);
+        assert_eq!(model.parameters()[0], -1.0);
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let mut model = Sequential::new(vec![
+            Box::new(Embedding::new(5, 3, uniform(&mut rng, 5 * 3)).with_sequence_len(2)),
+            Box::new(FullyConnectedLayer::with_activation(
+                6,
+                2,
+                uniform(&mut rng, 7 * 2),
+                Identity,
+            )),
+        ]);
+        let inputs = [4.0, 0.0, 1.0, 4.0, 3.0, 3.0];
+        let targets = uniform(&mut rng, 2 * 3);
+
+        assert_gradients_match(&mut model, &inputs, &targets);
+    }
+}
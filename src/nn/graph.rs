@@ -0,0 +1,560 @@
+//! Models whose layers form a directed acyclic graph.
+//!
+//! A [`Graph`] is built node by node. Every node takes the outputs of nodes that already exist, so the order in which
+//! nodes are added is a topological order, and that is the order in which they are evaluated. Before evaluating, the
+//! graph works out when the value of every node is last needed, and assigns values to buffers so that a buffer is
+//! handed to a later node as soon as its value is dead.
+
+use super::{Layer, LayerSummary, Summary};
+use crate::{loss::Loss, optim::Optimizer};
+
+/// Refers to a node of a [`Graph`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+enum Operation {
+    Input,
+    Layer(Box<dyn Layer>),
+    Add,
+    Concat,
+}
+
+struct Node {
+    operation: Operation,
+    inputs: Vec<NodeId>,
+    /// The number of values per sample.
+    count: usize,
+}
+
+/// A model whose layers take the outputs of any earlier layers, added or concatenated, and which may have several
+/// inputs and outputs. Inputs, outputs and targets are passed per input or output, each a batch stored one sample after
+/// the other.
+pub struct Graph {
+    nodes: Vec<Node>,
+    inputs: Vec<NodeId>,
+    outputs: Vec<NodeId>,
+    training: bool,
+}
+
+impl Clone for Graph {
+    fn clone(&self) -> Self {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| Node {
+                operation: match &node.operation {
+                    Operation::Input => Operation::Input,
+                    Operation::Layer(layer) => Operation::Layer(layer.clone_layer()),
+                    Operation::Add => Operation::Add,
+                    Operation::Concat => Operation::Concat,
+                },
+                inputs: node.inputs.clone(),
+                count: node.count,
+            })
+            .collect();
+
+        Self {
+            nodes,
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            training: self.training,
+        }
+    }
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The buffer of every node.
+struct Plan {
+    slots: Vec<usize>,
+    slot_count: usize,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            training: true,
+        }
+    }
+
+    fn push(&mut self, operation: Operation, inputs: Vec<NodeId>, count: usize) -> NodeId {
+        assert!(
+            inputs.iter().all(|input| input.0 < self.nodes.len()),
+            "node from another graph"
+        );
+        self.nodes.push(Node {
+            operation,
+            inputs,
+            count,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Adds an input of `count` values per sample. Inputs are passed in the order in which they are added.
+    pub fn input(&mut self, count: usize) -> NodeId {
+        let id = self.push(Operation::Input, Vec::new(), count);
+        self.inputs.push(id);
+        id
+    }
+
+    /// Adds a layer that takes the outputs of `input`.
+    pub fn layer(&mut self, layer: Box<dyn Layer>, input: NodeId) -> NodeId {
+        assert_eq!(
+            self.nodes[input.0].count,
+            layer.input_count(),
+            "the layer must take the outputs of its input"
+        );
+        let count = layer.output_count();
+        self.push(Operation::Layer(layer), vec![input], count)
+    }
+
+    /// Adds the sum of nodes with the same number of values, as in a residual connection.
+    pub fn add(&mut self, inputs: &[NodeId]) -> NodeId {
+        let count = self.nodes[inputs.first().expect("nothing to add").0].count;
+        assert!(
+            inputs
+                .iter()
+                .all(|input| self.nodes[input.0].count == count),
+            "added nodes must have the same number of values"
+        );
+        self.push(Operation::Add, inputs.to_vec(), count)
+    }
+
+    /// Adds the concatenation of the values of every sample of the given nodes.
+    pub fn concat(&mut self, inputs: &[NodeId]) -> NodeId {
+        let count = inputs.iter().map(|input| self.nodes[input.0].count).sum();
+        self.push(Operation::Concat, inputs.to_vec(), count)
+    }
+
+    /// Marks a node as an output. Outputs are returned in the order in which they are marked.
+    pub fn output(&mut self, node: NodeId) {
+        self.outputs.push(node);
+    }
+
+    /// The number of values per sample of every input.
+    pub fn input_counts(&self) -> Vec<usize> {
+        self.inputs
+            .iter()
+            .map(|input| self.nodes[input.0].count)
+            .collect()
+    }
+
+    /// The number of values per sample of every output.
+    pub fn output_counts(&self) -> Vec<usize> {
+        self.outputs
+            .iter()
+            .map(|output| self.nodes[output.0].count)
+            .collect()
+    }
+
+    fn layers_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Layer>> {
+        self.nodes
+            .iter_mut()
+            .filter_map(|node| match &mut node.operation {
+                Operation::Layer(layer) => Some(layer),
+                _ => None,
+            })
+    }
+
+    pub fn layers(&self) -> impl Iterator<Item = &dyn Layer> {
+        self.nodes.iter().filter_map(|node| match &node.operation {
+            Operation::Layer(layer) => Some(&**layer),
+            _ => None,
+        })
+    }
+
+    /// The number of parameters of all layers together. Gradients are laid out as the parameters of each layer, in the
+    /// order in which the layers were added.
+    pub fn parameter_count(&self) -> usize {
+        self.layers().map(|layer| layer.parameters().len()).sum()
+    }
+
+    /// Describes every node but the inputs, in the order in which they were added, for printing or comparing with
+    /// other models. Additions and concatenations are listed with flat shapes.
+    pub fn summary(&self) -> Summary {
+        let layers = self
+            .nodes
+            .iter()
+            .filter_map(|node| {
+                let (name, flops) = match &node.operation {
+                    Operation::Input => return None,
+                    Operation::Layer(layer) => return Some(LayerSummary::new(&**layer)),
+                    Operation::Add => ("Add", (node.inputs.len() - 1) * node.count),
+                    Operation::Concat => ("Concat", 0),
+                };
+                Some(LayerSummary {
+                    name: name.to_string(),
+                    input_shape: vec![node.count],
+                    output_shape: vec![node.count],
+                    parameter_count: 0,
+                    flops,
+                    activation_bytes: node.count * std::mem::size_of::<f32>(),
+                })
+            })
+            .collect();
+
+        Summary { layers }
+    }
+
+    /// Models start out in training mode.
+    pub fn is_training(&self) -> bool {
+        self.training
+    }
+
+    pub fn set_training(&mut self, training: bool) {
+        self.training = training;
+        super::set_training(self.layers_mut(), training);
+    }
+
+    pub fn train(&mut self) {
+        self.set_training(true);
+    }
+
+    pub fn eval(&mut self) {
+        self.set_training(false);
+    }
+
+    /// Reseeds every layer with its own seed derived from `seed`, with the layers in the order in which they were
+    /// added.
+    pub fn set_seed(&mut self, seed: u64) {
+        super::set_seed(self.layers_mut(), seed);
+    }
+
+    /// Assigns buffers to nodes. A buffer is reused once the value of its node has been used for the last time. When
+    /// `backward` is set, the inputs and outputs of layers stay alive for the backward pass.
+    fn plan(&self, backward: bool) -> Plan {
+        let end = self.nodes.len();
+        let mut last_use: Vec<usize> = (0..end).collect();
+        for (index, node) in self.nodes.iter().enumerate() {
+            for input in &node.inputs {
+                last_use[input.0] = last_use[input.0].max(index);
+            }
+            if backward && matches!(node.operation, Operation::Layer(_)) {
+                last_use[index] = end;
+                last_use[node.inputs[0].0] = end;
+            }
+        }
+        for output in &self.outputs {
+            last_use[output.0] = end;
+        }
+
+        let mut free = Vec::new();
+        let mut slots = Vec::with_capacity(end);
+        let mut slot_count = 0;
+        for (index, node) in self.nodes.iter().enumerate() {
+            slots.push(free.pop().unwrap_or_else(|| {
+                slot_count += 1;
+                slot_count - 1
+            }));
+
+            let mut inputs = node.inputs.clone();
+            inputs.sort_by_key(|input| input.0);
+            inputs.dedup();
+            for input in inputs {
+                if last_use[input.0] == index {
+                    free.push(slots[input.0]);
+                }
+            }
+            if last_use[index] == index {
+                free.push(slots[index]);
+            }
+        }
+
+        Plan { slots, slot_count }
+    }
+
+    /// Evaluates every node and returns the buffers, which hold the values of the nodes that are still alive.
+    fn evaluate(&mut self, inputs: &[&[f32]], plan: &Plan) -> Vec<Vec<f32>> {
+        assert_eq!(self.inputs.len(), inputs.len());
+        let batch_size = match self.inputs.first() {
+            Some(input) => inputs[0].len() / self.nodes[input.0].count.max(1),
+            None => 0,
+        };
+
+        let counts: Vec<usize> = self.nodes.iter().map(|node| node.count).collect();
+        let mut buffers = vec![Vec::new(); plan.slot_count];
+        let mut next_input = 0;
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            let mut values = std::mem::take(&mut buffers[plan.slots[index]]);
+            values.clear();
+            values.resize(node.count * batch_size, 0.0);
+            let input = |i: usize| &buffers[plan.slots[node.inputs[i].0]];
+
+            match &mut node.operation {
+                Operation::Input => {
+                    values.copy_from_slice(inputs[next_input]);
+                    next_input += 1;
+                }
+                Operation::Layer(layer) => layer.forward(input(0), &mut values),
+                Operation::Add => {
+                    for i in 0..node.inputs.len() {
+                        for (value, input) in values.iter_mut().zip(input(i)) {
+                            *value += input;
+                        }
+                    }
+                }
+                Operation::Concat => {
+                    let mut offset = 0;
+                    for (i, input_node) in node.inputs.iter().enumerate() {
+                        let count = counts[input_node.0];
+                        for (sample, values) in values.chunks_exact_mut(node.count).enumerate() {
+                            values[offset..offset + count]
+                                .copy_from_slice(&input(i)[sample * count..][..count]);
+                        }
+                        offset += count;
+                    }
+                }
+            }
+
+            buffers[plan.slots[index]] = values;
+        }
+
+        buffers
+    }
+
+    /// Runs a batch through the model and returns the values of every output.
+    pub fn forward(&mut self, inputs: &[&[f32]]) -> Vec<Vec<f32>> {
+        let plan = self.plan(false);
+        let buffers = self.evaluate(inputs, &plan);
+        self.outputs
+            .iter()
+            .map(|output| buffers[plan.slots[output.0]].clone())
+            .collect()
+    }
+
+    /// Computes the loss of every output with respect to its targets and adds the gradient of their sum with respect to
+    /// the parameters to `gradients`. Returns the summed loss. The regularization penalties of the layers are not
+    /// included, see [`Graph::regularize`].
+    pub fn gradients<L>(
+        &mut self,
+        inputs: &[&[f32]],
+        targets: &[&[f32]],
+        loss: &L,
+        gradients: &mut [f32],
+    ) -> f32
+    where
+        L: Loss + ?Sized,
+    {
+        assert_eq!(self.outputs.len(), targets.len());
+        assert_eq!(self.parameter_count(), gradients.len());
+
+        let plan = self.plan(true);
+        let buffers = self.evaluate(inputs, &plan);
+        let value = |node: NodeId| &buffers[plan.slots[node.0]];
+
+        fn accumulate(gradient: &mut Option<Vec<f32>>, values: &[f32]) {
+            match gradient {
+                Some(gradient) => {
+                    for (gradient, value) in gradient.iter_mut().zip(values) {
+                        *gradient += value;
+                    }
+                }
+                None => *gradient = Some(values.to_vec()),
+            }
+        }
+
+        let mut node_gradients: Vec<Option<Vec<f32>>> = vec![None; self.nodes.len()];
+        let mut total_loss = 0.0;
+        for (output, targets) in self.outputs.iter().zip(targets) {
+            let outputs = value(*output);
+            let mut output_gradients = vec![0.0; outputs.len()];
+            total_loss += loss.loss(outputs, targets, &mut output_gradients);
+            accumulate(&mut node_gradients[output.0], &output_gradients);
+        }
+
+        let counts: Vec<usize> = self.nodes.iter().map(|node| node.count).collect();
+        let mut end = gradients.len();
+        for (index, node) in self.nodes.iter_mut().enumerate().rev() {
+            let parameter_count = match &node.operation {
+                Operation::Layer(layer) => layer.parameters().len(),
+                _ => 0,
+            };
+            let start = end - parameter_count;
+            let parameter_gradients = &mut gradients[start..end];
+            end = start;
+
+            // Nodes that do not lead to an output have no gradient.
+            let Some(mut output_gradients) = node_gradients[index].take() else {
+                continue;
+            };
+
+            match &mut node.operation {
+                Operation::Input => {}
+                Operation::Layer(layer) => {
+                    let input = node.inputs[0];
+                    let mut input_gradients = vec![0.0; value(input).len()];
+                    layer.backward(
+                        value(input),
+                        value(NodeId(index)),
+                        &mut output_gradients,
+                        &mut input_gradients,
+                        parameter_gradients,
+                    );
+                    accumulate(&mut node_gradients[input.0], &input_gradients);
+                }
+                Operation::Add => {
+                    for input in &node.inputs {
+                        accumulate(&mut node_gradients[input.0], &output_gradients);
+                    }
+                }
+                Operation::Concat => {
+                    let mut offset = 0;
+                    for input in &node.inputs {
+                        let count = counts[input.0];
+                        let input_gradients: Vec<f32> = output_gradients
+                            .chunks_exact(node.count)
+                            .flat_map(|sample| &sample[offset..offset + count])
+                            .copied()
+                            .collect();
+                        accumulate(&mut node_gradients[input.0], &input_gradients);
+                        offset += count;
+                    }
+                }
+            }
+        }
+
+        total_loss
+    }
+
+    /// Adds the gradients of the regularization penalties of all layers to `gradients` and returns the sum of the
+    /// penalties. Call this once the gradient of [`Graph::gradients`] has been averaged over the batch, as for
+    /// [`Sequential::regularize`](super::Sequential::regularize).
+    pub fn regularize(&self, gradients: &mut [f32]) -> f32 {
+        assert_eq!(self.parameter_count(), gradients.len());
+        super::regularize(self.layers(), gradients)
+    }
+
+    /// Updates the parameters as [`Sequential::update`](super::Sequential::update) does, with the layers in the order
+    /// in which they were added.
+    pub fn update<O>(&mut self, optimizer: &mut O, gradients: &[f32])
+    where
+        O: Optimizer + ?Sized,
+    {
+        assert_eq!(self.parameter_count(), gradients.len());
+        super::update(self.layers_mut(), optimizer, gradients, Vec::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        loss::MeanSquaredError,
+        nn::{
+            tests::{assert_finite_differences, uniform, Layers},
+            FullyConnectedLayer, Identity, LayerNorm, Sequential,
+        },
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    impl Layers for Graph {
+        fn layer_mut(&mut self, index: usize) -> Option<&mut Box<dyn Layer>> {
+            self.layers_mut().nth(index)
+        }
+    }
+
+    fn linear(rng: &mut StdRng, inputs: usize, outputs: usize) -> Box<dyn Layer> {
+        Box::new(FullyConnectedLayer::with_activation(
+            inputs,
+            outputs,
+            uniform(rng, (inputs + 1) * outputs),
+            Identity,
+        ))
+    }
+
+    #[test]
+    fn regularization() {
+        use crate::{nn::Regularization, optim::Sgd};
+
+        let mut graph = Graph::new();
+        let x = graph.input(1);
+        let decayed = graph.layer(
+            Box::new(
+                FullyConnectedLayer::with_activation(1, 1, [2.0, 0.0], Identity)
+                    .with_regularization(Regularization::new().with_l2(0.5)),
+            ),
+            x,
+        );
+        let constrained = graph.layer(
+            Box::new(
+                FullyConnectedLayer::with_activation(1, 1, [3.0, 1.0], Identity)
+                    .with_regularization(Regularization::new().with_max_norm(1.0)),
+            ),
+            decayed,
+        );
+        graph.output(constrained);
+
+        // Only the first layer has a penalty, `0.5 * 2²` with the gradient `2 * 0.5 * 2`.
+        let mut gradients = [0.0; 4];
+        assert_eq!(graph.regularize(&mut gradients), 2.0);
+        assert_eq!(gradients, [2.0, 0.0, 0.0, 0.0]);
+
+        // Only the second layer is constrained, to a weight of norm one.
+        graph.update(&mut Sgd::new(0.0), &gradients);
+        let parameters: Vec<f32> = graph
+            .layers()
+            .flat_map(|layer| layer.parameters().to_vec())
+            .collect();
+        assert_eq!(parameters, [2.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn chain_matches_sequential() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let layers: Vec<_> = (0..4).map(|_| linear(&mut rng, 3, 3)).collect();
+        let mut sequential =
+            Sequential::new(layers.iter().map(|layer| layer.clone_layer()).collect());
+
+        let mut graph = Graph::new();
+        let mut node = graph.input(3);
+        for layer in layers {
+            node = graph.layer(layer, node);
+        }
+        graph.output(node);
+
+        // A chain only needs two buffers for inference, but keeps every value for the backward pass.
+        assert_eq!(graph.plan(false).slot_count, 2);
+        assert_eq!(graph.plan(true).slot_count, 5);
+
+        let inputs = uniform(&mut rng, 3 * 2);
+        assert_eq!(graph.forward(&[&inputs]), [sequential.forward(&inputs)]);
+        assert_eq!(graph.summary(), sequential.summary());
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = StdRng::from_seed([1u8; 32]);
+
+        // Two inputs, a residual block and two outputs.
+        let mut graph = Graph::new();
+        let x = graph.input(2);
+        let y = graph.input(1);
+        let hidden = graph.layer(linear(&mut rng, 2, 4), x);
+        let normalized = graph.layer(Box::new(LayerNorm::new(4)), hidden);
+        let transformed = graph.layer(linear(&mut rng, 4, 4), normalized);
+        let residual = graph.add(&[hidden, transformed]);
+        let joined = graph.concat(&[residual, y, x]);
+        let first = graph.layer(linear(&mut rng, 7, 2), joined);
+        let second = graph.layer(linear(&mut rng, 4, 1), residual);
+        graph.output(first);
+        graph.output(second);
+
+        let x = uniform(&mut rng, 2 * 3);
+        let y = uniform(&mut rng, 3);
+        let first_targets = uniform(&mut rng, 2 * 3);
+        let second_targets = uniform(&mut rng, 3);
+        let inputs: [&[f32]; 2] = [&x, &y];
+        let targets: [&[f32]; 2] = [&first_targets, &second_targets];
+
+        assert_finite_differences(&mut graph, |graph, gradients| {
+            graph.gradients(&inputs, &targets, &MeanSquaredError, gradients)
+        });
+    }
+}
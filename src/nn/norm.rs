@@ -0,0 +1,540 @@
+use super::Layer;
+
+const EPSILON: f32 = 1e-5;
+
+/// Batch normalization. Every channel is normalized with the mean and variance over the batch, and over the rows and
+/// columns for [`BatchNorm::new_2d`], followed by a learnable scale and shift per channel.
+///
+/// During training the layer keeps exponential moving averages of the batch statistics, which it uses instead of the
+/// batch statistics during evaluation. The parameters are the scales of all channels followed by the shifts, and the
+/// state is the running means followed by the running variances.
+///
+/// Under [`DataParallel`](crate::parallel::DataParallel), and so under [`Trainer`](crate::train::Trainer), the
+/// "batch" is a shard: every shard is normalized with its own statistics, and the running statistics become the mean
+/// of those of the shards. Training therefore depends on the shard size rather than on the batch size, much like
+/// ghost batch normalization, and shards of very few samples give noisy statistics.
+#[derive(Debug, Clone)]
+pub struct BatchNorm {
+    channels: usize,
+    channel_len: usize,
+    momentum: f32,
+    training: bool,
+    scales_and_shifts: Vec<f32>,
+    running_means_and_variances: Vec<f32>,
+    /// The mean and inverse standard deviation of every channel used by the last forward pass.
+    statistics: Vec<(f32, f32)>,
+}
+
+impl BatchNorm {
+    /// Normalizes each of `features` inputs over the batch.
+    pub fn new_1d(features: usize) -> Self {
+        Self::new(features, 1)
+    }
+
+    /// Normalizes every channel of samples stored as channels, then rows, then columns over the batch and the rows
+    /// and columns.
+    pub fn new_2d([channels, rows, cols]: [usize; 3]) -> Self {
+        Self::new(channels, rows * cols)
+    }
+
+    fn new(channels: usize, channel_len: usize) -> Self {
+        Self {
+            channels,
+            channel_len,
+            momentum: 0.1,
+            training: true,
+            scales_and_shifts: [vec![1.0; channels], vec![0.0; channels]].concat(),
+            running_means_and_variances: [vec![0.0; channels], vec![1.0; channels]].concat(),
+            statistics: Vec::new(),
+        }
+    }
+
+    /// Sets the weight of the current batch in the running statistics, between zero and one.
+    pub fn with_momentum(mut self, momentum: f32) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&momentum),
+            "momentum must be between 0 and 1"
+        );
+        self.momentum = momentum;
+        self
+    }
+
+    /// The indices of the elements of `channel` in every sample of the batch.
+    fn channel_indices(
+        &self,
+        batch_size: usize,
+        channel: usize,
+    ) -> impl Iterator<Item = usize> + Clone {
+        let sample_len = self.channels * self.channel_len;
+        let start = channel * self.channel_len;
+        let channel_len = self.channel_len;
+        (0..batch_size).flat_map(move |sample| {
+            let offset = sample * sample_len + start;
+            offset..offset + channel_len
+        })
+    }
+}
+
+impl Layer for BatchNorm {
+    fn input_count(&self) -> usize {
+        self.channels * self.channel_len
+    }
+
+    fn output_count(&self) -> usize {
+        self.channels * self.channel_len
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.scales_and_shifts
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.scales_and_shifts
+    }
+
+    fn state(&self) -> &[f32] {
+        &self.running_means_and_variances
+    }
+
+    fn state_mut(&mut self) -> &mut [f32] {
+        &mut self.running_means_and_variances
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        assert_eq!(inputs.len(), outputs.len());
+        let batch_size = inputs.len() / self.input_count();
+        let (scales, shifts) = self.scales_and_shifts.split_at(self.channels);
+
+        self.statistics.clear();
+        for channel in 0..self.channels {
+            let indices = self.channel_indices(batch_size, channel);
+
+            let (mean, variance) = if self.training {
+                let n = (batch_size * self.channel_len) as f32;
+                let mean = indices.clone().map(|i| inputs[i]).sum::<f32>() / n;
+                let variance = indices
+                    .clone()
+                    .map(|i| (inputs[i] - mean).powi(2))
+                    .sum::<f32>()
+                    / n;
+
+                // The running variance estimates the population variance, so it uses the unbiased estimate. An empty
+                // batch has no statistics to learn from.
+                if batch_size > 0 {
+                    let (running_means, running_variances) =
+                        self.running_means_and_variances.split_at_mut(self.channels);
+                    let unbiased = if n > 1.0 {
+                        variance * n / (n - 1.0)
+                    } else {
+                        variance
+                    };
+                    running_means[channel] += self.momentum * (mean - running_means[channel]);
+                    running_variances[channel] +=
+                        self.momentum * (unbiased - running_variances[channel]);
+                }
+
+                (mean, variance)
+            } else {
+                (
+                    self.running_means_and_variances[channel],
+                    self.running_means_and_variances[self.channels + channel],
+                )
+            };
+
+            let inverse_std = 1.0 / (variance + EPSILON).sqrt();
+            self.statistics.push((mean, inverse_std));
+
+            for i in indices {
+                outputs[i] = (inputs[i] - mean) * inverse_std * scales[channel] + shifts[channel];
+            }
+        }
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        assert_eq!(inputs.len(), input_gradients.len());
+        assert_eq!(outputs.len(), output_gradients.len());
+        assert_eq!(
+            self.statistics.len(),
+            self.channels,
+            "backward without forward"
+        );
+        let batch_size = inputs.len() / self.input_count();
+        let n = (batch_size * self.channel_len) as f32;
+        let (scale_gradients, shift_gradients) = parameter_gradients.split_at_mut(self.channels);
+
+        for (channel, &(mean, inverse_std)) in self.statistics.iter().enumerate() {
+            let indices = self.channel_indices(batch_size, channel);
+            let scale = self.scales_and_shifts[channel];
+
+            let mut gradient_sum = 0.0;
+            let mut weighted_gradient_sum = 0.0;
+            for i in indices.clone() {
+                let normalized = (inputs[i] - mean) * inverse_std;
+                gradient_sum += output_gradients[i];
+                weighted_gradient_sum += output_gradients[i] * normalized;
+            }
+            scale_gradients[channel] += weighted_gradient_sum;
+            shift_gradients[channel] += gradient_sum;
+
+            for i in indices {
+                input_gradients[i] = if self.training {
+                    // The batch statistics depend on the inputs too.
+                    let normalized = (inputs[i] - mean) * inverse_std;
+                    scale * inverse_std / n
+                        * (n * output_gradients[i]
+                            - gradient_sum
+                            - normalized * weighted_gradient_sum)
+                } else {
+                    scale * inverse_std * output_gradients[i]
+                };
+            }
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+}
+
+/// Normalizes contiguous groups of elements within a sample, followed by a learnable scale and shift. Element `i` of
+/// a sample uses the scale and shift with index `(i / affine_stride) % affine_count`. The scales and shifts are passed
+/// in, so that layers made of several parts can keep all their parameters together.
+#[derive(Debug, Clone)]
+pub(crate) struct GroupedNorm {
+    sample_len: usize,
+    group_len: usize,
+    affine_stride: usize,
+    affine_count: usize,
+}
+
+impl GroupedNorm {
+    fn new(sample_len: usize, group_len: usize, affine_stride: usize, affine_count: usize) -> Self {
+        assert_eq!(sample_len % group_len, 0, "groups must divide the sample");
+
+        Self {
+            sample_len,
+            group_len,
+            affine_stride,
+            affine_count,
+        }
+    }
+
+    /// Layer normalization of every row of `features` values in a sample of `len` rows.
+    pub(crate) fn rows(len: usize, features: usize) -> Self {
+        Self::new(len * features, features, 1, features)
+    }
+
+    /// All scales followed by all shifts, which start out as one and zero.
+    pub(crate) fn initial_parameters(&self) -> Vec<f32> {
+        [vec![1.0; self.affine_count], vec![0.0; self.affine_count]].concat()
+    }
+
+    fn affine_index(&self, i: usize) -> usize {
+        (i % self.sample_len / self.affine_stride) % self.affine_count
+    }
+
+    /// The mean and inverse standard deviation of a group.
+    fn statistics(&self, group: &[f32]) -> (f32, f32) {
+        let n = group.len() as f32;
+        let mean = group.iter().sum::<f32>() / n;
+        let variance = group.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n;
+        (mean, 1.0 / (variance + EPSILON).sqrt())
+    }
+
+    pub(crate) fn forward(&self, scales_and_shifts: &[f32], inputs: &[f32], outputs: &mut [f32]) {
+        assert_eq!(inputs.len(), outputs.len());
+        let (scales, shifts) = scales_and_shifts.split_at(self.affine_count);
+
+        for (start, (group, outputs)) in (0..inputs.len()).step_by(self.group_len).zip(
+            inputs
+                .chunks_exact(self.group_len)
+                .zip(outputs.chunks_exact_mut(self.group_len)),
+        ) {
+            let (mean, inverse_std) = self.statistics(group);
+            for (offset, (input, output)) in group.iter().zip(outputs).enumerate() {
+                let a = self.affine_index(start + offset);
+                *output = (input - mean) * inverse_std * scales[a] + shifts[a];
+            }
+        }
+    }
+
+    pub(crate) fn backward(
+        &self,
+        scales_and_shifts: &[f32],
+        inputs: &[f32],
+        output_gradients: &[f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        assert_eq!(inputs.len(), input_gradients.len());
+        assert_eq!(inputs.len(), output_gradients.len());
+        let n = self.group_len as f32;
+        let (scales, _) = scales_and_shifts.split_at(self.affine_count);
+        let (scale_gradients, shift_gradients) =
+            parameter_gradients.split_at_mut(self.affine_count);
+
+        for (start, group) in (0..inputs.len())
+            .step_by(self.group_len)
+            .zip(inputs.chunks_exact(self.group_len))
+        {
+            let (mean, inverse_std) = self.statistics(group);
+            let range = start..start + self.group_len;
+
+            // Gradients with respect to the normalized values, which only differ from the output gradients by the
+            // scale.
+            let mut gradient_sum = 0.0;
+            let mut weighted_gradient_sum = 0.0;
+            for i in range.clone() {
+                let a = self.affine_index(i);
+                let normalized = (inputs[i] - mean) * inverse_std;
+                scale_gradients[a] += output_gradients[i] * normalized;
+                shift_gradients[a] += output_gradients[i];
+
+                let gradient = output_gradients[i] * scales[a];
+                gradient_sum += gradient;
+                weighted_gradient_sum += gradient * normalized;
+            }
+
+            for i in range {
+                let a = self.affine_index(i);
+                let normalized = (inputs[i] - mean) * inverse_std;
+                input_gradients[i] = inverse_std / n
+                    * (n * output_gradients[i] * scales[a]
+                        - gradient_sum
+                        - normalized * weighted_gradient_sum);
+            }
+        }
+    }
+}
+
+/// Implements [`Layer`] for a struct with a [`GroupedNorm`] `norm` and its `scales_and_shifts`.
+macro_rules! grouped_norm_layer {
+    ($T:ident) => {
+        impl Layer for $T {
+            fn input_count(&self) -> usize {
+                self.norm.sample_len
+            }
+
+            fn output_count(&self) -> usize {
+                self.norm.sample_len
+            }
+
+            fn parameters(&self) -> &[f32] {
+                &self.scales_and_shifts
+            }
+
+            fn parameters_mut(&mut self) -> &mut [f32] {
+                &mut self.scales_and_shifts
+            }
+
+            fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+                self.norm.forward(&self.scales_and_shifts, inputs, outputs);
+            }
+
+            fn backward(
+                &mut self,
+                inputs: &[f32],
+                _outputs: &[f32],
+                output_gradients: &mut [f32],
+                input_gradients: &mut [f32],
+                parameter_gradients: &mut [f32],
+            ) {
+                self.norm.backward(
+                    &self.scales_and_shifts,
+                    inputs,
+                    output_gradients,
+                    input_gradients,
+                    parameter_gradients,
+                );
+            }
+
+            fn clone_layer(&self) -> Box<dyn Layer> {
+                Box::new(self.clone())
+            }
+        }
+    };
+}
+
+/// Layer normalization. Every sample is normalized over its features, followed by a learnable scale and shift per
+/// feature. The layer behaves the same during training and evaluation.
+#[derive(Debug, Clone)]
+pub struct LayerNorm {
+    norm: GroupedNorm,
+    scales_and_shifts: Vec<f32>,
+}
+
+impl LayerNorm {
+    pub fn new(features: usize) -> Self {
+        let norm = GroupedNorm::rows(1, features);
+        Self {
+            scales_and_shifts: norm.initial_parameters(),
+            norm,
+        }
+    }
+
+    /// Treats every sample as a sequence of `len` rows of features and normalizes every row separately, as in a
+    /// transformer.
+    pub fn with_sequence_len(mut self, len: usize) -> Self {
+        self.norm = GroupedNorm::rows(len, self.norm.affine_count);
+        self
+    }
+}
+
+grouped_norm_layer!(LayerNorm);
+
+/// Group normalization. The channels of samples stored as channels, then rows, then columns are divided into groups
+/// that are normalized separately, followed by a learnable scale and shift per channel. The layer behaves the same
+/// during training and evaluation.
+#[derive(Debug, Clone)]
+pub struct GroupNorm {
+    norm: GroupedNorm,
+    scales_and_shifts: Vec<f32>,
+}
+
+impl GroupNorm {
+    pub fn new(groups: usize, [channels, rows, cols]: [usize; 3]) -> Self {
+        assert_eq!(channels % groups, 0, "groups must divide the channels");
+
+        let channel_len = rows * cols;
+        let norm = GroupedNorm::new(
+            channels * channel_len,
+            channels / groups * channel_len,
+            channel_len,
+            channels,
+        );
+        Self {
+            scales_and_shifts: norm.initial_parameters(),
+            norm,
+        }
+    }
+}
+
+grouped_norm_layer!(GroupNorm);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{
+        tests::{assert_gradients_match, uniform},
+        FullyConnectedLayer, Identity, Sequential,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn assert_normalized(values: impl Iterator<Item = f32> + Clone) {
+        let n = values.clone().count() as f32;
+        let mean = values.clone().sum::<f32>() / n;
+        let variance = values.map(|x| (x - mean).powi(2)).sum::<f32>() / n;
+        assert!(mean.abs() < 1e-5, "mean {mean}");
+        assert!((variance - 1.0).abs() < 1e-3, "variance {variance}");
+    }
+
+    #[test]
+    fn batch_norm() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let mut norm = BatchNorm::new_2d([2, 2, 3]).with_momentum(0.5);
+        let inputs: Vec<f32> = uniform(&mut rng, 4 * 12)
+            .iter()
+            .map(|x| 3.0 * x + 1.0)
+            .collect();
+        let mut outputs = vec![0.0; inputs.len()];
+
+        norm.forward(&inputs, &mut outputs);
+        for channel in 0..2 {
+            assert_normalized(norm.channel_indices(4, channel).map(|i| outputs[i]));
+        }
+
+        // The running statistics moved halfway towards the batch statistics.
+        let mean = norm.channel_indices(4, 0).map(|i| inputs[i]).sum::<f32>() / 24.0;
+        assert!((norm.state()[0] - mean / 2.0).abs() < 1e-6);
+
+        // An empty batch leaves them alone.
+        let state = norm.state().to_vec();
+        norm.forward(&[], &mut []);
+        assert_eq!(norm.state(), state);
+
+        // During evaluation the running statistics are used, so a sample is normalized the same regardless of the rest
+        // of the batch.
+        norm.set_training(false);
+        let mut single = vec![0.0; 12];
+        norm.forward(&inputs[..12], &mut single);
+        assert_eq!(single, outputs_in_eval(&mut norm, &inputs)[..12]);
+
+        fn outputs_in_eval(norm: &mut BatchNorm, inputs: &[f32]) -> Vec<f32> {
+            let mut outputs = vec![0.0; inputs.len()];
+            norm.forward(inputs, &mut outputs);
+            outputs
+        }
+    }
+
+    #[test]
+    fn layer_and_group_norm() {
+        let mut rng = StdRng::from_seed([1u8; 32]);
+        let inputs = uniform(&mut rng, 2 * 24);
+        let mut outputs = vec![0.0; inputs.len()];
+
+        LayerNorm::new(6)
+            .with_sequence_len(4)
+            .forward(&inputs, &mut outputs);
+        for row in outputs.chunks_exact(6) {
+            assert_normalized(row.iter().copied());
+        }
+
+        GroupNorm::new(2, [4, 2, 3]).forward(&inputs, &mut outputs);
+        for group in outputs.chunks_exact(12) {
+            assert_normalized(group.iter().copied());
+        }
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = StdRng::from_seed([2u8; 32]);
+
+        let mut batch_norm_eval = BatchNorm::new_2d([2, 3, 2]);
+        batch_norm_eval.set_training(false);
+        let norms: [Box<dyn Layer>; 5] = [
+            Box::new(BatchNorm::new_1d(12)),
+            Box::new(BatchNorm::new_2d([2, 3, 2])),
+            Box::new(batch_norm_eval),
+            Box::new(LayerNorm::new(4).with_sequence_len(3)),
+            Box::new(GroupNorm::new(2, [4, 3, 1])),
+        ];
+
+        for mut norm in norms {
+            // Move the scales and shifts away from their initial values so that their gradients matter.
+            for parameter in norm.parameters_mut() {
+                *parameter += 0.5 * uniform(&mut rng, 1)[0];
+            }
+
+            let mut model = Sequential::new(vec![
+                Box::new(FullyConnectedLayer::with_activation(
+                    3,
+                    12,
+                    uniform(&mut rng, 4 * 12),
+                    Identity,
+                )),
+                norm,
+                Box::new(FullyConnectedLayer::with_activation(
+                    12,
+                    2,
+                    uniform(&mut rng, 13 * 2),
+                    Identity,
+                )),
+            ]);
+            let inputs = uniform(&mut rng, 3 * 16);
+            let targets = uniform(&mut rng, 2 * 16);
+
+            assert_gradients_match(&mut model, &inputs, &targets);
+        }
+    }
+}
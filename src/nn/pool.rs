@@ -0,0 +1,473 @@
+use super::Layer;
+
+/// The windows of a two dimensional pooling layer over samples stored as channels, then rows, then columns.
+#[derive(Debug, Clone, Copy)]
+struct Windows {
+    input_shape: [usize; 3],
+    kernel_size: [usize; 2],
+    stride: [usize; 2],
+}
+
+impl Windows {
+    fn new(input_shape: [usize; 3], kernel_size: [usize; 2], stride: [usize; 2]) -> Self {
+        assert!(stride.iter().all(|&s| s > 0), "stride must be positive");
+        assert!(
+            kernel_size.iter().all(|&k| k > 0),
+            "kernel size must be positive"
+        );
+        assert!(
+            kernel_size[0] <= input_shape[1] && kernel_size[1] <= input_shape[2],
+            "kernel does not fit in the input"
+        );
+
+        Self {
+            input_shape,
+            kernel_size,
+            stride,
+        }
+    }
+
+    fn output_shape(&self) -> [usize; 3] {
+        let [channels, rows, cols] = self.input_shape;
+        [
+            channels,
+            (rows - self.kernel_size[0]) / self.stride[0] + 1,
+            (cols - self.kernel_size[1]) / self.stride[1] + 1,
+        ]
+    }
+
+    fn input_count(&self) -> usize {
+        self.input_shape.iter().product()
+    }
+
+    fn output_count(&self) -> usize {
+        self.output_shape().iter().product()
+    }
+
+    /// Calls `f` with the index of every output element of a sample and the indices of the input elements in its
+    /// window.
+    fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(usize, &mut dyn Iterator<Item = usize>),
+    {
+        let [channels, input_rows, input_cols] = self.input_shape;
+        let [_, output_rows, output_cols] = self.output_shape();
+        let [kernel_rows, kernel_cols] = self.kernel_size;
+
+        for channel in 0..channels {
+            for output_row in 0..output_rows {
+                for output_col in 0..output_cols {
+                    let row = output_row * self.stride[0];
+                    let col = output_col * self.stride[1];
+                    let mut window = (row..row + kernel_rows).flat_map(|input_row| {
+                        (col..col + kernel_cols).map(move |input_col| {
+                            (channel * input_rows + input_row) * input_cols + input_col
+                        })
+                    });
+
+                    f(
+                        (channel * output_rows + output_row) * output_cols + output_col,
+                        &mut window,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Takes the maximum over every window. The position of the maximum is recorded during the forward pass so that the
+/// backward pass can route the gradient to it.
+#[derive(Debug, Clone)]
+pub struct MaxPool2d {
+    windows: Windows,
+    argmax: Vec<usize>,
+}
+
+impl MaxPool2d {
+    /// Creates a pooling layer whose stride equals its kernel size, so the windows do not overlap.
+    pub fn new(input_shape: [usize; 3], kernel_size: [usize; 2]) -> Self {
+        Self {
+            windows: Windows::new(input_shape, kernel_size, kernel_size),
+            argmax: Vec::new(),
+        }
+    }
+
+    pub fn with_stride(self, stride: [usize; 2]) -> Self {
+        Self {
+            windows: Windows::new(self.windows.input_shape, self.windows.kernel_size, stride),
+            argmax: Vec::new(),
+        }
+    }
+
+    pub fn output_shape(&self) -> [usize; 3] {
+        self.windows.output_shape()
+    }
+}
+
+impl Layer for MaxPool2d {
+    fn input_count(&self) -> usize {
+        self.windows.input_count()
+    }
+
+    fn output_count(&self) -> usize {
+        self.windows.output_count()
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        self.windows.input_shape.to_vec()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.windows.output_shape().to_vec()
+    }
+
+    fn flops(&self) -> usize {
+        self.output_count() * self.windows.kernel_size.iter().product::<usize>()
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &[]
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut []
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        let input_count = self.input_count();
+        let output_count = self.output_count();
+        let batch_size = outputs.len() / output_count;
+        assert_eq!(input_count * batch_size, inputs.len());
+
+        self.argmax.resize(outputs.len(), 0);
+
+        for ((input, output), argmax) in inputs
+            .chunks_exact(input_count)
+            .zip(outputs.chunks_exact_mut(output_count))
+            .zip(self.argmax.chunks_exact_mut(output_count))
+        {
+            self.windows.for_each(|output_index, window| {
+                let first = window.next().expect("windows are not empty");
+                let max = window.fold(first, |max, index| {
+                    if input[index] > input[max] {
+                        index
+                    } else {
+                        max
+                    }
+                });
+                output[output_index] = input[max];
+                argmax[output_index] = max;
+            });
+        }
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        _parameter_gradients: &mut [f32],
+    ) {
+        let input_count = self.input_count();
+        let output_count = self.output_count();
+        assert_eq!(inputs.len(), input_gradients.len());
+        assert_eq!(outputs.len(), output_gradients.len());
+        assert_eq!(self.argmax.len(), outputs.len(), "backward without forward");
+
+        input_gradients.fill(0.0);
+        for ((input_gradients, output_gradients), argmax) in input_gradients
+            .chunks_exact_mut(input_count)
+            .zip(output_gradients.chunks_exact(output_count))
+            .zip(self.argmax.chunks_exact(output_count))
+        {
+            for (&gradient, &index) in output_gradients.iter().zip(argmax) {
+                input_gradients[index] += gradient;
+            }
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Takes the mean over every window.
+#[derive(Debug, Clone)]
+pub struct AvgPool2d {
+    windows: Windows,
+}
+
+impl AvgPool2d {
+    /// Creates a pooling layer whose stride equals its kernel size, so the windows do not overlap.
+    pub fn new(input_shape: [usize; 3], kernel_size: [usize; 2]) -> Self {
+        Self {
+            windows: Windows::new(input_shape, kernel_size, kernel_size),
+        }
+    }
+
+    pub fn with_stride(self, stride: [usize; 2]) -> Self {
+        Self {
+            windows: Windows::new(self.windows.input_shape, self.windows.kernel_size, stride),
+        }
+    }
+
+    pub fn output_shape(&self) -> [usize; 3] {
+        self.windows.output_shape()
+    }
+
+    fn scale(&self) -> f32 {
+        let [kernel_rows, kernel_cols] = self.windows.kernel_size;
+        1.0 / (kernel_rows * kernel_cols) as f32
+    }
+}
+
+impl Layer for AvgPool2d {
+    fn input_count(&self) -> usize {
+        self.windows.input_count()
+    }
+
+    fn output_count(&self) -> usize {
+        self.windows.output_count()
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        self.windows.input_shape.to_vec()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.windows.output_shape().to_vec()
+    }
+
+    fn flops(&self) -> usize {
+        self.output_count() * self.windows.kernel_size.iter().product::<usize>()
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &[]
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut []
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        let input_count = self.input_count();
+        let output_count = self.output_count();
+        let batch_size = outputs.len() / output_count;
+        assert_eq!(input_count * batch_size, inputs.len());
+
+        let scale = self.scale();
+        for (input, output) in inputs
+            .chunks_exact(input_count)
+            .zip(outputs.chunks_exact_mut(output_count))
+        {
+            self.windows.for_each(|output_index, window| {
+                output[output_index] = window.map(|index| input[index]).sum::<f32>() * scale;
+            });
+        }
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        _parameter_gradients: &mut [f32],
+    ) {
+        let input_count = self.input_count();
+        let output_count = self.output_count();
+        assert_eq!(inputs.len(), input_gradients.len());
+        assert_eq!(outputs.len(), output_gradients.len());
+
+        let scale = self.scale();
+        input_gradients.fill(0.0);
+        for (input_gradients, output_gradients) in input_gradients
+            .chunks_exact_mut(input_count)
+            .zip(output_gradients.chunks_exact(output_count))
+        {
+            self.windows.for_each(|output_index, window| {
+                for index in window {
+                    input_gradients[index] += output_gradients[output_index] * scale;
+                }
+            });
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Takes the mean over all rows and columns of every channel.
+#[derive(Debug, Clone)]
+pub struct GlobalAvgPool {
+    input_shape: [usize; 3],
+}
+
+impl GlobalAvgPool {
+    pub fn new(input_shape: [usize; 3]) -> Self {
+        Self { input_shape }
+    }
+
+    fn channel_len(&self) -> usize {
+        let [_, rows, cols] = self.input_shape;
+        rows * cols
+    }
+}
+
+impl Layer for GlobalAvgPool {
+    fn input_count(&self) -> usize {
+        self.input_shape.iter().product()
+    }
+
+    fn output_count(&self) -> usize {
+        self.input_shape[0]
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        self.input_shape.to_vec()
+    }
+
+    fn flops(&self) -> usize {
+        self.input_count()
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &[]
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut []
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        let channel_len = self.channel_len();
+        assert_eq!(outputs.len() * channel_len, inputs.len());
+
+        let scale = 1.0 / channel_len as f32;
+        for (channel, output) in inputs.chunks_exact(channel_len).zip(outputs) {
+            *output = channel.iter().sum::<f32>() * scale;
+        }
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        _parameter_gradients: &mut [f32],
+    ) {
+        let channel_len = self.channel_len();
+        assert_eq!(inputs.len(), input_gradients.len());
+        assert_eq!(outputs.len(), output_gradients.len());
+
+        let scale = 1.0 / channel_len as f32;
+        for (channel, &gradient) in input_gradients
+            .chunks_exact_mut(channel_len)
+            .zip(output_gradients.iter())
+        {
+            channel.fill(gradient * scale);
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{
+        tests::{assert_gradients_match, uniform},
+        FullyConnectedLayer, Identity, Sequential,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const INPUTS: [f32; 16] = [
+        1.0, 2.0, 3.0, 4.0, //
+        5.0, 6.0, 8.0, 7.0, //
+        9.0, 0.0, 1.0, 2.0, //
+        3.0, 4.0, 5.0, 6.0, //
+    ];
+
+    #[test]
+    fn max_pool() {
+        let mut pool = MaxPool2d::new([1, 4, 4], [2, 2]);
+        let mut outputs = [0.0; 4];
+        pool.forward(&INPUTS, &mut outputs);
+        assert_eq!(outputs, [6.0, 8.0, 9.0, 6.0]);
+
+        let mut input_gradients = [0.0; 16];
+        pool.backward(
+            &INPUTS,
+            &outputs,
+            &mut [1.0, 2.0, 3.0, 4.0],
+            &mut input_gradients,
+            &mut [],
+        );
+        assert_eq!(
+            input_gradients,
+            [
+                0.0, 0.0, 0.0, 0.0, //
+                0.0, 1.0, 2.0, 0.0, //
+                3.0, 0.0, 0.0, 0.0, //
+                0.0, 0.0, 0.0, 4.0, //
+            ]
+        );
+
+        let mut pool = MaxPool2d::new([1, 4, 4], [3, 3]).with_stride([1, 1]);
+        pool.forward(&INPUTS, &mut outputs);
+        assert_eq!(outputs, [9.0, 8.0, 9.0, 8.0]);
+    }
+
+    #[test]
+    fn avg_pool() {
+        let mut pool = AvgPool2d::new([1, 4, 4], [2, 2]);
+        let mut outputs = [0.0; 4];
+        pool.forward(&INPUTS, &mut outputs);
+        assert_eq!(outputs, [3.5, 5.5, 4.0, 3.5]);
+
+        let mut pool = GlobalAvgPool::new([2, 2, 4]);
+        let mut outputs = [0.0; 2];
+        pool.forward(&INPUTS, &mut outputs);
+        assert_eq!(outputs, [4.5, 3.75]);
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+
+        let pools: [Box<dyn Layer>; 3] = [
+            Box::new(MaxPool2d::new([2, 5, 4], [2, 3]).with_stride([1, 1])),
+            Box::new(AvgPool2d::new([2, 5, 4], [3, 2]).with_stride([2, 1])),
+            Box::new(GlobalAvgPool::new([2, 5, 4])),
+        ];
+
+        for pool in pools {
+            let pool_outputs = pool.output_count();
+            let mut model = Sequential::new(vec![
+                Box::new(FullyConnectedLayer::with_activation(
+                    3,
+                    40,
+                    uniform(&mut rng, 4 * 40),
+                    Identity,
+                )),
+                pool,
+                Box::new(FullyConnectedLayer::with_activation(
+                    pool_outputs,
+                    2,
+                    uniform(&mut rng, (pool_outputs + 1) * 2),
+                    Identity,
+                )),
+            ]);
+            let inputs = uniform(&mut rng, 3 * 2);
+            let targets = uniform(&mut rng, 2 * 2);
+
+            assert_gradients_match(&mut model, &inputs, &targets);
+        }
+    }
+}
@@ -0,0 +1,852 @@
+//! Recurrent layers.
+//!
+//! A [`Cell`] computes the next state from an input and the current state, for a batch of samples at a time.
+//! [`Recurrent`] runs a cell over a sequence and backpropagates through time. The input and state of a cell are
+//! concatenated, input first, and projected by weights in the layout of [`FullyConnectedLayer`]: a row of weights
+//! followed by a bias for every output.
+//!
+//! [`FullyConnectedLayer`]: super::FullyConnectedLayer
+
+use super::{affine_backward, affine_forward, Layer};
+use std::fmt::Debug;
+
+fn sigmoid(value: f32) -> f32 {
+    1.0 / (1.0 + (-value).exp())
+}
+
+/// Concatenates every input with the first `hidden_size` values of the corresponding state.
+fn concat(inputs: &[f32], states: &[f32], state_size: usize, hidden_size: usize) -> Vec<f32> {
+    let batch_size = states.len() / state_size;
+    let input_size = inputs.len() / batch_size;
+    let mut concatenated = Vec::with_capacity(batch_size * (input_size + hidden_size));
+    for (input, state) in inputs
+        .chunks_exact(input_size)
+        .zip(states.chunks_exact(state_size))
+    {
+        concatenated.extend_from_slice(input);
+        concatenated.extend_from_slice(&state[..hidden_size]);
+    }
+    concatenated
+}
+
+/// Collects the initializer values for projections from `input_size + hidden_size` values to `outputs` values.
+fn initialize<I: IntoIterator<Item = f32>>(
+    input_size: usize,
+    hidden_size: usize,
+    outputs: usize,
+    initializer: I,
+) -> Vec<f32> {
+    let parameter_count = (input_size + hidden_size + 1) * outputs;
+    let weights_and_biases: Vec<f32> = initializer.into_iter().take(parameter_count).collect();
+    assert_eq!(
+        weights_and_biases.len(),
+        parameter_count,
+        "initializer ran out of values"
+    );
+    weights_and_biases
+}
+
+/// One step of a recurrent network. All arguments hold a batch of samples, stored one sample after the other. The
+/// first [`Cell::hidden_size`] values of the state are the output of the step.
+pub trait Cell: Debug + Clone + Send + 'static {
+    fn input_size(&self) -> usize;
+
+    fn hidden_size(&self) -> usize;
+
+    fn state_size(&self) -> usize {
+        self.hidden_size()
+    }
+
+    /// The number of values per sample that the forward pass saves for the backward pass.
+    fn cache_size(&self) -> usize;
+
+    fn parameters(&self) -> &[f32];
+
+    fn parameters_mut(&mut self) -> &mut [f32];
+
+    fn forward(&self, inputs: &[f32], states: &[f32], next_states: &mut [f32], caches: &mut [f32]);
+
+    /// Given the arguments and results of `forward` and the gradient with respect to the next states, adds the
+    /// gradient with respect to the parameters to `parameter_gradients` and writes the gradients with respect to the
+    /// inputs and the states to `input_gradients` and `state_gradients`.
+    #[allow(clippy::too_many_arguments)]
+    fn backward(
+        &self,
+        inputs: &[f32],
+        states: &[f32],
+        next_states: &[f32],
+        caches: &[f32],
+        next_state_gradients: &[f32],
+        input_gradients: &mut [f32],
+        state_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    );
+}
+
+/// The Elman cell `h' = tanh(W [x, h] + b)`.
+#[derive(Debug, Clone)]
+pub struct RnnCell {
+    input_size: usize,
+    hidden_size: usize,
+    weights_and_biases: Vec<f32>,
+}
+
+impl RnnCell {
+    pub fn new<I: IntoIterator<Item = f32>>(
+        input_size: usize,
+        hidden_size: usize,
+        initializer: I,
+    ) -> Self {
+        Self {
+            input_size,
+            hidden_size,
+            weights_and_biases: initialize(input_size, hidden_size, hidden_size, initializer),
+        }
+    }
+}
+
+impl Cell for RnnCell {
+    fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    fn hidden_size(&self) -> usize {
+        self.hidden_size
+    }
+
+    fn cache_size(&self) -> usize {
+        0
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.weights_and_biases
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.weights_and_biases
+    }
+
+    fn forward(
+        &self,
+        inputs: &[f32],
+        states: &[f32],
+        next_states: &mut [f32],
+        _caches: &mut [f32],
+    ) {
+        let concatenated = concat(inputs, states, self.hidden_size, self.hidden_size);
+        affine_forward(
+            &self.weights_and_biases,
+            self.input_size + self.hidden_size,
+            &concatenated,
+            next_states,
+        );
+        for state in next_states {
+            *state = state.tanh();
+        }
+    }
+
+    fn backward(
+        &self,
+        inputs: &[f32],
+        states: &[f32],
+        next_states: &[f32],
+        _caches: &[f32],
+        next_state_gradients: &[f32],
+        input_gradients: &mut [f32],
+        state_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        let pre_activation_gradients: Vec<f32> = next_state_gradients
+            .iter()
+            .zip(next_states)
+            .map(|(gradient, state)| gradient * (1.0 - state * state))
+            .collect();
+
+        let concatenated = concat(inputs, states, self.hidden_size, self.hidden_size);
+        let mut concatenated_gradients = vec![0.0; concatenated.len()];
+        affine_backward(
+            &self.weights_and_biases,
+            self.input_size + self.hidden_size,
+            &concatenated,
+            &pre_activation_gradients,
+            &mut concatenated_gradients,
+            parameter_gradients,
+        );
+
+        for ((gradients, input_gradients), state_gradients) in concatenated_gradients
+            .chunks_exact(self.input_size + self.hidden_size)
+            .zip(input_gradients.chunks_exact_mut(self.input_size))
+            .zip(state_gradients.chunks_exact_mut(self.hidden_size))
+        {
+            let (input, state) = gradients.split_at(self.input_size);
+            input_gradients.copy_from_slice(input);
+            state_gradients.copy_from_slice(state);
+        }
+    }
+}
+
+/// The long short-term memory cell. The state is the hidden state followed by the cell state. The projection outputs
+/// the input, forget, candidate and output gates, in that order.
+#[derive(Debug, Clone)]
+pub struct LstmCell {
+    input_size: usize,
+    hidden_size: usize,
+    weights_and_biases: Vec<f32>,
+}
+
+impl LstmCell {
+    pub fn new<I: IntoIterator<Item = f32>>(
+        input_size: usize,
+        hidden_size: usize,
+        initializer: I,
+    ) -> Self {
+        Self {
+            input_size,
+            hidden_size,
+            weights_and_biases: initialize(input_size, hidden_size, 4 * hidden_size, initializer),
+        }
+    }
+}
+
+impl Cell for LstmCell {
+    fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    fn hidden_size(&self) -> usize {
+        self.hidden_size
+    }
+
+    fn state_size(&self) -> usize {
+        2 * self.hidden_size
+    }
+
+    /// The activated gates.
+    fn cache_size(&self) -> usize {
+        4 * self.hidden_size
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.weights_and_biases
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.weights_and_biases
+    }
+
+    fn forward(&self, inputs: &[f32], states: &[f32], next_states: &mut [f32], caches: &mut [f32]) {
+        let h = self.hidden_size;
+        let concatenated = concat(inputs, states, 2 * h, h);
+        affine_forward(
+            &self.weights_and_biases,
+            self.input_size + h,
+            &concatenated,
+            caches,
+        );
+
+        for ((gates, state), next_state) in caches
+            .chunks_exact_mut(4 * h)
+            .zip(states.chunks_exact(2 * h))
+            .zip(next_states.chunks_exact_mut(2 * h))
+        {
+            for j in 0..h {
+                let i = sigmoid(gates[j]);
+                let f = sigmoid(gates[h + j]);
+                let g = gates[2 * h + j].tanh();
+                let o = sigmoid(gates[3 * h + j]);
+                [gates[j], gates[h + j], gates[2 * h + j], gates[3 * h + j]] = [i, f, g, o];
+
+                let c = f * state[h + j] + i * g;
+                next_state[j] = o * c.tanh();
+                next_state[h + j] = c;
+            }
+        }
+    }
+
+    fn backward(
+        &self,
+        inputs: &[f32],
+        states: &[f32],
+        next_states: &[f32],
+        caches: &[f32],
+        next_state_gradients: &[f32],
+        input_gradients: &mut [f32],
+        state_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        let h = self.hidden_size;
+        let mut gate_gradients = vec![0.0; caches.len()];
+
+        for ((((gradients, gates), state), next_state), (next_gradients, state_gradients)) in
+            gate_gradients
+                .chunks_exact_mut(4 * h)
+                .zip(caches.chunks_exact(4 * h))
+                .zip(states.chunks_exact(2 * h))
+                .zip(next_states.chunks_exact(2 * h))
+                .zip(
+                    next_state_gradients
+                        .chunks_exact(2 * h)
+                        .zip(state_gradients.chunks_exact_mut(2 * h)),
+                )
+        {
+            for j in 0..h {
+                let [i, f, g, o] = [gates[j], gates[h + j], gates[2 * h + j], gates[3 * h + j]];
+                let tanh_c = next_state[h + j].tanh();
+                let hidden_gradient = next_gradients[j];
+                let cell_gradient =
+                    next_gradients[h + j] + hidden_gradient * o * (1.0 - tanh_c * tanh_c);
+
+                gradients[j] = cell_gradient * g * i * (1.0 - i);
+                gradients[h + j] = cell_gradient * state[h + j] * f * (1.0 - f);
+                gradients[2 * h + j] = cell_gradient * i * (1.0 - g * g);
+                gradients[3 * h + j] = hidden_gradient * tanh_c * o * (1.0 - o);
+                state_gradients[h + j] = cell_gradient * f;
+            }
+        }
+
+        let concatenated = concat(inputs, states, 2 * h, h);
+        let mut concatenated_gradients = vec![0.0; concatenated.len()];
+        affine_backward(
+            &self.weights_and_biases,
+            self.input_size + h,
+            &concatenated,
+            &gate_gradients,
+            &mut concatenated_gradients,
+            parameter_gradients,
+        );
+
+        for ((gradients, input_gradients), state_gradients) in concatenated_gradients
+            .chunks_exact(self.input_size + h)
+            .zip(input_gradients.chunks_exact_mut(self.input_size))
+            .zip(state_gradients.chunks_exact_mut(2 * h))
+        {
+            let (input, hidden) = gradients.split_at(self.input_size);
+            input_gradients.copy_from_slice(input);
+            state_gradients[..h].copy_from_slice(hidden);
+        }
+    }
+}
+
+/// The gated recurrent unit cell `h' = (1 - z) n + z h` with `n = tanh(W_n [x, r h] + b_n)`. The parameters are the
+/// projection to the reset and update gates `r` and `z`, in that order, followed by the projection to the candidate
+/// `n`.
+#[derive(Debug, Clone)]
+pub struct GruCell {
+    input_size: usize,
+    hidden_size: usize,
+    weights_and_biases: Vec<f32>,
+}
+
+impl GruCell {
+    pub fn new<I: IntoIterator<Item = f32>>(
+        input_size: usize,
+        hidden_size: usize,
+        initializer: I,
+    ) -> Self {
+        Self {
+            input_size,
+            hidden_size,
+            weights_and_biases: initialize(input_size, hidden_size, 3 * hidden_size, initializer),
+        }
+    }
+
+    /// The projections to the gates and to the candidate.
+    fn projections(&self) -> (&[f32], &[f32]) {
+        self.weights_and_biases
+            .split_at((self.input_size + self.hidden_size + 1) * 2 * self.hidden_size)
+    }
+
+    /// Concatenates every input with its state multiplied by the reset gate.
+    fn candidate_inputs(&self, inputs: &[f32], states: &[f32], caches: &[f32]) -> Vec<f32> {
+        let h = self.hidden_size;
+        let mut reset_states = states.to_vec();
+        for (state, gates) in reset_states
+            .chunks_exact_mut(h)
+            .zip(caches.chunks_exact(3 * h))
+        {
+            for (value, reset) in state.iter_mut().zip(&gates[..h]) {
+                *value *= reset;
+            }
+        }
+        concat(inputs, &reset_states, h, h)
+    }
+}
+
+impl Cell for GruCell {
+    fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    fn hidden_size(&self) -> usize {
+        self.hidden_size
+    }
+
+    /// The reset gate, the update gate and the candidate.
+    fn cache_size(&self) -> usize {
+        3 * self.hidden_size
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.weights_and_biases
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.weights_and_biases
+    }
+
+    fn forward(&self, inputs: &[f32], states: &[f32], next_states: &mut [f32], caches: &mut [f32]) {
+        let h = self.hidden_size;
+        let (gate_projection, candidate_projection) = self.projections();
+
+        let mut gates = vec![0.0; next_states.len() * 2];
+        affine_forward(
+            gate_projection,
+            self.input_size + h,
+            &concat(inputs, states, h, h),
+            &mut gates,
+        );
+        for (gates, cache) in gates
+            .chunks_exact(2 * h)
+            .zip(caches.chunks_exact_mut(3 * h))
+        {
+            for (cache, gate) in cache.iter_mut().zip(gates) {
+                *cache = sigmoid(*gate);
+            }
+        }
+
+        let mut candidates = vec![0.0; next_states.len()];
+        affine_forward(
+            candidate_projection,
+            self.input_size + h,
+            &self.candidate_inputs(inputs, states, caches),
+            &mut candidates,
+        );
+
+        for (((next_state, state), candidates), cache) in next_states
+            .chunks_exact_mut(h)
+            .zip(states.chunks_exact(h))
+            .zip(candidates.chunks_exact(h))
+            .zip(caches.chunks_exact_mut(3 * h))
+        {
+            for j in 0..h {
+                let n = candidates[j].tanh();
+                let z = cache[h + j];
+                cache[2 * h + j] = n;
+                next_state[j] = (1.0 - z) * n + z * state[j];
+            }
+        }
+    }
+
+    fn backward(
+        &self,
+        inputs: &[f32],
+        states: &[f32],
+        _next_states: &[f32],
+        caches: &[f32],
+        next_state_gradients: &[f32],
+        input_gradients: &mut [f32],
+        state_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        let h = self.hidden_size;
+        let concatenated_size = self.input_size + h;
+        let (gate_projection, candidate_projection) = self.projections();
+        let (gate_parameter_gradients, candidate_parameter_gradients) =
+            parameter_gradients.split_at_mut(gate_projection.len());
+
+        let mut candidate_gradients = vec![0.0; next_state_gradients.len()];
+        for (((gradients, next_gradients), cache), state_gradients) in candidate_gradients
+            .chunks_exact_mut(h)
+            .zip(next_state_gradients.chunks_exact(h))
+            .zip(caches.chunks_exact(3 * h))
+            .zip(state_gradients.chunks_exact_mut(h))
+        {
+            for j in 0..h {
+                let (z, n) = (cache[h + j], cache[2 * h + j]);
+                gradients[j] = next_gradients[j] * (1.0 - z) * (1.0 - n * n);
+                state_gradients[j] = next_gradients[j] * z;
+            }
+        }
+
+        let candidate_inputs = self.candidate_inputs(inputs, states, caches);
+        let mut candidate_input_gradients = vec![0.0; candidate_inputs.len()];
+        affine_backward(
+            candidate_projection,
+            concatenated_size,
+            &candidate_inputs,
+            &candidate_gradients,
+            &mut candidate_input_gradients,
+            candidate_parameter_gradients,
+        );
+
+        let mut gate_gradients = vec![0.0; 2 * next_state_gradients.len()];
+        for (sample, gradients) in gate_gradients.chunks_exact_mut(2 * h).enumerate() {
+            let cache = &caches[sample * 3 * h..][..3 * h];
+            let state = &states[sample * h..][..h];
+            let next_gradients = &next_state_gradients[sample * h..][..h];
+            let reset_state_gradients =
+                &candidate_input_gradients[sample * concatenated_size + self.input_size..][..h];
+            for j in 0..h {
+                let (r, z, n) = (cache[j], cache[h + j], cache[2 * h + j]);
+                gradients[j] = reset_state_gradients[j] * state[j] * r * (1.0 - r);
+                gradients[h + j] = next_gradients[j] * (state[j] - n) * z * (1.0 - z);
+                state_gradients[sample * h + j] += reset_state_gradients[j] * r;
+            }
+        }
+
+        let mut concatenated_gradients = vec![0.0; candidate_inputs.len()];
+        affine_backward(
+            gate_projection,
+            concatenated_size,
+            &concat(inputs, states, h, h),
+            &gate_gradients,
+            &mut concatenated_gradients,
+            gate_parameter_gradients,
+        );
+
+        for (((gradients, candidate_gradients), input_gradients), state_gradients) in
+            concatenated_gradients
+                .chunks_exact(concatenated_size)
+                .zip(candidate_input_gradients.chunks_exact(concatenated_size))
+                .zip(input_gradients.chunks_exact_mut(self.input_size))
+                .zip(state_gradients.chunks_exact_mut(h))
+        {
+            for (i, input_gradient) in input_gradients.iter_mut().enumerate() {
+                *input_gradient = gradients[i] + candidate_gradients[i];
+            }
+            for (j, state_gradient) in state_gradients.iter_mut().enumerate() {
+                *state_gradient += gradients[self.input_size + j];
+            }
+        }
+    }
+}
+
+/// Runs a [`Cell`] over sequences of `len` steps, starting from a zero state. Every sample holds the inputs of one
+/// step after the other, and the outputs are the hidden states after every step, or only after the last step.
+#[derive(Debug, Clone)]
+pub struct Recurrent<C> {
+    cell: C,
+    len: usize,
+    truncation: usize,
+    last_output_only: bool,
+    /// The inputs of the last forward pass by step, then sample.
+    step_inputs: Vec<f32>,
+    /// The initial states and the states after every step of the last forward pass, by step, then sample.
+    states: Vec<f32>,
+    /// The caches of the cell for every step of the last forward pass, by step, then sample.
+    caches: Vec<f32>,
+}
+
+pub type Rnn = Recurrent<RnnCell>;
+pub type Lstm = Recurrent<LstmCell>;
+pub type Gru = Recurrent<GruCell>;
+
+impl<C: Cell> Recurrent<C> {
+    pub fn new(cell: C, len: usize) -> Self {
+        Self {
+            cell,
+            len,
+            truncation: len.max(1),
+            last_output_only: false,
+            step_inputs: Vec::new(),
+            states: Vec::new(),
+            caches: Vec::new(),
+        }
+    }
+
+    /// Truncates backpropagation through time: the sequence is split into chunks of `steps` steps and gradients do not
+    /// flow from one chunk into the previous one, while the state still does.
+    pub fn with_truncation(mut self, steps: usize) -> Self {
+        assert!(steps > 0, "truncation must be at least one step");
+        self.truncation = steps;
+        self
+    }
+
+    /// Outputs only the hidden state after the last step.
+    pub fn with_last_output_only(mut self) -> Self {
+        self.last_output_only = true;
+        self
+    }
+
+    pub fn cell(&self) -> &C {
+        &self.cell
+    }
+
+    /// The range of the outputs of `sample` that hold the hidden state after `step`, if they are output.
+    fn output_range(&self, sample: usize, step: usize) -> Option<std::ops::Range<usize>> {
+        let h = self.cell.hidden_size();
+        if !self.last_output_only {
+            Some((sample * self.len + step) * h..(sample * self.len + step + 1) * h)
+        } else if step + 1 == self.len {
+            Some(sample * h..(sample + 1) * h)
+        } else {
+            None
+        }
+    }
+}
+
+impl<C: Cell> Layer for Recurrent<C> {
+    fn input_count(&self) -> usize {
+        self.len * self.cell.input_size()
+    }
+
+    fn output_count(&self) -> usize {
+        if self.last_output_only {
+            self.cell.hidden_size()
+        } else {
+            self.len * self.cell.hidden_size()
+        }
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        vec![self.len, self.cell.input_size()]
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        if self.last_output_only {
+            vec![self.cell.hidden_size()]
+        } else {
+            vec![self.len, self.cell.hidden_size()]
+        }
+    }
+
+    fn flops(&self) -> usize {
+        // Every step is dominated by the affine maps of the cell, which use every parameter once.
+        2 * self.len * self.cell.parameters().len()
+    }
+
+    fn parameters(&self) -> &[f32] {
+        self.cell.parameters()
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        self.cell.parameters_mut()
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        let (input_size, state_size, cache_size) = (
+            self.cell.input_size(),
+            self.cell.state_size(),
+            self.cell.cache_size(),
+        );
+        let batch_size = inputs.len() / self.input_count();
+        assert_eq!(batch_size * self.input_count(), inputs.len());
+        assert_eq!(batch_size * self.output_count(), outputs.len());
+
+        self.step_inputs
+            .resize(self.len * batch_size * input_size, 0.0);
+        for (sample, sample_inputs) in inputs.chunks_exact(self.input_count()).enumerate() {
+            for (step, step_inputs) in sample_inputs.chunks_exact(input_size).enumerate() {
+                self.step_inputs[(step * batch_size + sample) * input_size..][..input_size]
+                    .copy_from_slice(step_inputs);
+            }
+        }
+
+        self.states.clear();
+        self.states
+            .resize((self.len + 1) * batch_size * state_size, 0.0);
+        self.caches.resize(self.len * batch_size * cache_size, 0.0);
+
+        for step in 0..self.len {
+            let (states, next_states) = self
+                .states
+                .split_at_mut((step + 1) * batch_size * state_size);
+            self.cell.forward(
+                &self.step_inputs[step * batch_size * input_size..][..batch_size * input_size],
+                &states[step * batch_size * state_size..],
+                &mut next_states[..batch_size * state_size],
+                &mut self.caches[step * batch_size * cache_size..][..batch_size * cache_size],
+            );
+
+            let next_states = &self.states[(step + 1) * batch_size * state_size..];
+            for sample in 0..batch_size {
+                if let Some(range) = self.output_range(sample, step) {
+                    let state = &next_states[sample * state_size..];
+                    outputs[range.clone()].copy_from_slice(&state[..range.len()]);
+                }
+            }
+        }
+    }
+
+    fn backward(
+        &mut self,
+        _inputs: &[f32],
+        _outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        let (input_size, state_size, cache_size) = (
+            self.cell.input_size(),
+            self.cell.state_size(),
+            self.cell.cache_size(),
+        );
+        let batch_size = input_gradients.len() / self.input_count();
+        assert_eq!(
+            (self.len + 1) * batch_size * state_size,
+            self.states.len(),
+            "backward without forward"
+        );
+
+        let mut state_gradients = vec![0.0; batch_size * state_size];
+        let mut next_state_gradients = vec![0.0; batch_size * state_size];
+        let mut step_input_gradients = vec![0.0; batch_size * input_size];
+
+        for step in (0..self.len).rev() {
+            std::mem::swap(&mut state_gradients, &mut next_state_gradients);
+            for sample in 0..batch_size {
+                if let Some(range) = self.output_range(sample, step) {
+                    for (state_gradient, output_gradient) in next_state_gradients
+                        [sample * state_size..]
+                        .iter_mut()
+                        .zip(&output_gradients[range])
+                    {
+                        *state_gradient += output_gradient;
+                    }
+                }
+            }
+
+            let states = |step: usize| {
+                &self.states[step * batch_size * state_size..][..batch_size * state_size]
+            };
+            self.cell.backward(
+                &self.step_inputs[step * batch_size * input_size..][..batch_size * input_size],
+                states(step),
+                states(step + 1),
+                &self.caches[step * batch_size * cache_size..][..batch_size * cache_size],
+                &next_state_gradients,
+                &mut step_input_gradients,
+                &mut state_gradients,
+                parameter_gradients,
+            );
+
+            for (sample, gradients) in step_input_gradients.chunks_exact(input_size).enumerate() {
+                input_gradients[sample * self.input_count() + step * input_size..][..input_size]
+                    .copy_from_slice(gradients);
+            }
+
+            if step % self.truncation == 0 {
+                state_gradients.fill(0.0);
+            }
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{
+        tests::{assert_gradients_match, uniform},
+        FullyConnectedLayer, Identity, Sequential,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn rnn_step() {
+        // With a single input and hidden value, the weights are those of the input, the state and the bias.
+        let mut rnn = Rnn::new(RnnCell::new(1, 1, [0.5, -1.0, 0.25]), 2);
+        let mut outputs = [0.0; 2];
+        rnn.forward(&[1.0, 2.0], &mut outputs);
+
+        let first = (0.5f32 + 0.25).tanh();
+        let second = (1.0 - first + 0.25).tanh();
+        assert!((outputs[0] - first).abs() < 1e-6);
+        assert!((outputs[1] - second).abs() < 1e-6);
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let (input_size, hidden_size, len) = (2, 3, 3);
+
+        let layers: [(Box<dyn Layer>, usize); 4] = [
+            (
+                Box::new(Rnn::new(
+                    RnnCell::new(input_size, hidden_size, uniform(&mut rng, 6 * 3)),
+                    len,
+                )),
+                len * hidden_size,
+            ),
+            (
+                Box::new(Lstm::new(
+                    LstmCell::new(input_size, hidden_size, uniform(&mut rng, 6 * 12)),
+                    len,
+                )),
+                len * hidden_size,
+            ),
+            (
+                Box::new(Gru::new(
+                    GruCell::new(input_size, hidden_size, uniform(&mut rng, 6 * 9)),
+                    len,
+                )),
+                len * hidden_size,
+            ),
+            (
+                Box::new(
+                    Lstm::new(
+                        LstmCell::new(input_size, hidden_size, uniform(&mut rng, 6 * 12)),
+                        len,
+                    )
+                    .with_last_output_only(),
+                ),
+                hidden_size,
+            ),
+        ];
+
+        for (layer, output_count) in layers {
+            let mut model = Sequential::new(vec![
+                Box::new(FullyConnectedLayer::with_activation(
+                    2,
+                    len * input_size,
+                    uniform(&mut rng, 3 * len * input_size),
+                    Identity,
+                )),
+                layer,
+                Box::new(FullyConnectedLayer::with_activation(
+                    output_count,
+                    2,
+                    uniform(&mut rng, (output_count + 1) * 2),
+                    Identity,
+                )),
+            ]);
+            let inputs = uniform(&mut rng, 2 * 4);
+            let targets = uniform(&mut rng, 2 * 4);
+
+            assert_gradients_match(&mut model, &inputs, &targets);
+        }
+    }
+
+    #[test]
+    fn truncation() {
+        let mut rng = StdRng::from_seed([1u8; 32]);
+        let cell = GruCell::new(1, 2, uniform(&mut rng, 4 * 6));
+        let inputs = uniform(&mut rng, 4);
+
+        let input_gradients = |mut layer: Gru| {
+            let mut outputs = vec![0.0; 2];
+            layer.forward(&inputs, &mut outputs);
+            let mut input_gradients = vec![0.0; 4];
+            layer.backward(
+                &inputs,
+                &outputs,
+                &mut [1.0; 2],
+                &mut input_gradients,
+                &mut [0.0; 4 * 6],
+            );
+            input_gradients
+        };
+
+        let full = input_gradients(Gru::new(cell.clone(), 4).with_last_output_only());
+        assert!(full.iter().all(|&gradient| gradient != 0.0));
+
+        // Only the output of the last step has a gradient, so with chunks of two steps the first chunk gets none.
+        let truncated =
+            input_gradients(Gru::new(cell, 4).with_last_output_only().with_truncation(2));
+        assert_eq!(truncated[..2], [0.0, 0.0]);
+        assert_eq!(truncated[2..], full[2..]);
+    }
+}
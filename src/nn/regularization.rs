@@ -0,0 +1,186 @@
+/// Regularization of the parameters of a [`FullyConnectedLayer`](super::FullyConnectedLayer), laid out as a row of
+/// weights followed by a bias for every output.
+///
+/// The L1 and L2 penalties, `l1 * Σ|w|` and `l2 * Σw²`, are added to the loss of every batch together with their
+/// gradients by [`DataParallel::gradients`](crate::parallel::DataParallel::gradients), and so by
+/// [`Trainer`](crate::train::Trainer). [`Sequential::gradients`](super::Sequential::gradients) leaves them out,
+/// since it is called per shard; code that calls it directly adds them with
+/// [`Sequential::regularize`](super::Sequential::regularize) once the gradient is averaged over the batch. Weight decay
+/// and the max-norm constraint act on the parameters directly after every optimizer step: weight decay shrinks them by
+/// `learning_rate * weight_decay` of their value, independently of the gradients, and the max-norm constraint scales
+/// every row of weights whose L2 norm exceeds the limit back down to it. Biases are left alone unless
+/// [`Regularization::with_biases`] is set.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Regularization {
+    pub l1: f32,
+    pub l2: f32,
+    pub weight_decay: f32,
+    pub max_norm: Option<f32>,
+    pub include_biases: bool,
+}
+
+impl Regularization {
+    /// No regularization.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_l1(mut self, l1: f32) -> Self {
+        self.l1 = l1;
+        self
+    }
+
+    pub fn with_l2(mut self, l2: f32) -> Self {
+        self.l2 = l2;
+        self
+    }
+
+    pub fn with_weight_decay(mut self, weight_decay: f32) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+
+    pub fn with_max_norm(mut self, max_norm: f32) -> Self {
+        assert!(max_norm > 0.0, "max norm must be positive");
+        self.max_norm = Some(max_norm);
+        self
+    }
+
+    /// Also regularizes the biases, and counts them in the norm of their row for the max-norm constraint.
+    pub fn with_biases(mut self) -> Self {
+        self.include_biases = true;
+        self
+    }
+
+    /// The parameters of every row that are regularized.
+    fn rows<'a>(
+        &self,
+        row_len: usize,
+        parameters: &'a mut [f32],
+    ) -> impl Iterator<Item = &'a mut [f32]> {
+        let len = if self.include_biases {
+            row_len
+        } else {
+            row_len - 1
+        };
+        parameters
+            .chunks_exact_mut(row_len)
+            .map(move |row| &mut row[..len])
+    }
+
+    /// Adds the gradient of the L1 and L2 penalties to `gradients` and returns the penalties.
+    pub(crate) fn penalty(
+        &self,
+        input_count: usize,
+        parameters: &[f32],
+        gradients: &mut [f32],
+    ) -> f32 {
+        assert_eq!(parameters.len(), gradients.len());
+        if self.l1 == 0.0 && self.l2 == 0.0 {
+            return 0.0;
+        }
+
+        let row_len = input_count + 1;
+        let mut penalty = 0.0;
+        for (row, gradients) in parameters
+            .chunks_exact(row_len)
+            .zip(self.rows(row_len, gradients))
+        {
+            for (&weight, gradient) in row.iter().zip(gradients) {
+                penalty += self.l1 * weight.abs() + self.l2 * weight * weight;
+                // The L1 penalty has no gradient at zero, so leave weights at zero where they are.
+                let sign = if weight == 0.0 { 0.0 } else { weight.signum() };
+                *gradient += self.l1 * sign + 2.0 * self.l2 * weight;
+            }
+        }
+        penalty
+    }
+
+    /// Applies weight decay and the max-norm constraint to `parameters` after an optimizer step.
+    pub(crate) fn constrain(&self, input_count: usize, parameters: &mut [f32], learning_rate: f32) {
+        let decay = 1.0 - learning_rate * self.weight_decay;
+        for row in self.rows(input_count + 1, parameters) {
+            if self.weight_decay != 0.0 {
+                for parameter in row.iter_mut() {
+                    *parameter *= decay;
+                }
+            }
+            if let Some(max_norm) = self.max_norm {
+                let norm = row.iter().map(|w| w * w).sum::<f32>().sqrt();
+                if norm > max_norm {
+                    let scale = max_norm / norm;
+                    for parameter in row {
+                        *parameter *= scale;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        loss::MeanSquaredError,
+        nn::{FullyConnectedLayer, Identity, Sequential},
+        optim::Sgd,
+        parallel::DataParallel,
+    };
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn penalties() {
+        // One output with weights 3 and -4 and bias 2.
+        let parameters = [3.0, -4.0, 2.0];
+        let mut gradients = [0.0; 3];
+        let l1 = Regularization::new().with_l1(0.5);
+        assert_eq!(l1.penalty(2, &parameters, &mut gradients), 3.5);
+        assert_eq!(gradients, [0.5, -0.5, 0.0]);
+
+        let mut gradients = [0.0; 3];
+        let l2 = Regularization::new().with_l2(0.5).with_biases();
+        assert_eq!(l2.penalty(2, &parameters, &mut gradients), 14.5);
+        assert_eq!(gradients, [3.0, -4.0, 2.0]);
+    }
+
+    #[test]
+    fn constraints() {
+        let mut parameters = [3.0, -4.0, 2.0, 0.3, 0.4, 2.0];
+        Regularization::new()
+            .with_weight_decay(0.25)
+            .constrain(2, &mut parameters, 2.0);
+        assert_eq!(parameters, [1.5, -2.0, 2.0, 0.15, 0.2, 2.0]);
+
+        let mut parameters = [3.0, -4.0, 2.0, 0.3, 0.4, 2.0];
+        Regularization::new()
+            .with_max_norm(2.5)
+            .constrain(2, &mut parameters, 0.2);
+        assert_eq!(parameters, [1.5, -2.0, 2.0, 0.3, 0.4, 2.0]);
+    }
+
+    #[test]
+    fn training() {
+        let regularization = Regularization::new().with_l2(0.1).with_max_norm(1.0);
+        let mut model = Sequential::new(vec![Box::new(
+            FullyConnectedLayer::with_activation(1, 1, [2.0, 0.0], Identity)
+                .with_regularization(regularization),
+        )]);
+        let mut parallel = DataParallel::new(&model, NonZeroUsize::MIN, NonZeroUsize::MIN);
+
+        // The penalty is added once per batch, however the batch is split.
+        let mut gradients = [0.0; 2];
+        let loss = parallel.gradients(
+            &mut model,
+            &[1.0, 1.0],
+            &[2.0, 2.0],
+            &MeanSquaredError,
+            &mut gradients,
+        );
+        assert!((loss - 0.4).abs() < 1e-6, "{loss}");
+        assert!((gradients[0] - 0.4).abs() < 1e-6 && gradients[1] == 0.0);
+
+        model.update(&mut Sgd::new(1.0), &gradients);
+        assert_eq!(model.parameters(), [1.0, 0.0]);
+    }
+}
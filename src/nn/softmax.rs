@@ -0,0 +1,247 @@
+use super::Layer;
+
+/// Writes the softmax of `values` to `outputs`. Subtracts the maximum first so that large values do not overflow.
+pub fn softmax(values: &[f32], outputs: &mut [f32]) {
+    assert_eq!(values.len(), outputs.len());
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut sum = 0.0;
+    for (output, value) in outputs.iter_mut().zip(values) {
+        *output = (value - max).exp();
+        sum += *output;
+    }
+    for output in outputs {
+        *output /= sum;
+    }
+}
+
+/// Writes the logarithm of the softmax of `values` to `outputs`, computed as `x - max - ln(sum(exp(x - max)))` which
+/// stays finite where the logarithm of [`softmax`] would not.
+pub fn log_softmax(values: &[f32], outputs: &mut [f32]) {
+    assert_eq!(values.len(), outputs.len());
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = values
+        .iter()
+        .map(|value| (value - max).exp())
+        .sum::<f32>()
+        .ln();
+    for (output, value) in outputs.iter_mut().zip(values) {
+        *output = value - max - log_sum;
+    }
+}
+
+/// The index of the largest value. The first one wins ties.
+pub fn argmax(values: &[f32]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .fold((0, f32::NEG_INFINITY), |best, (index, &value)| {
+            if value > best.1 {
+                (index, value)
+            } else {
+                best
+            }
+        })
+        .0
+}
+
+/// Turns the outputs of every sample into probabilities that sum to one.
+#[derive(Debug, Clone)]
+pub struct Softmax {
+    count: usize,
+}
+
+impl Softmax {
+    pub fn new(count: usize) -> Self {
+        Self { count }
+    }
+}
+
+impl Layer for Softmax {
+    fn input_count(&self) -> usize {
+        self.count
+    }
+
+    fn output_count(&self) -> usize {
+        self.count
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &[]
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut []
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        assert_eq!(inputs.len(), outputs.len());
+        for (inputs, outputs) in inputs
+            .chunks_exact(self.count)
+            .zip(outputs.chunks_exact_mut(self.count))
+        {
+            softmax(inputs, outputs);
+        }
+    }
+
+    fn backward(
+        &mut self,
+        _inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        _parameter_gradients: &mut [f32],
+    ) {
+        for ((outputs, output_gradients), input_gradients) in outputs
+            .chunks_exact(self.count)
+            .zip(output_gradients.chunks_exact(self.count))
+            .zip(input_gradients.chunks_exact_mut(self.count))
+        {
+            let dot: f32 = outputs
+                .iter()
+                .zip(output_gradients)
+                .map(|(y, g)| y * g)
+                .sum();
+            for ((input_gradient, output), output_gradient) in input_gradients
+                .iter_mut()
+                .zip(outputs)
+                .zip(output_gradients)
+            {
+                *input_gradient = output * (output_gradient - dot);
+            }
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+
+    fn outputs_probabilities(&self) -> bool {
+        true
+    }
+}
+
+/// Turns the outputs of every sample into log-probabilities. Pairs with
+/// [`NegativeLogLikelihood`](crate::loss::NegativeLogLikelihood) to train classifiers.
+#[derive(Debug, Clone)]
+pub struct LogSoftmax {
+    count: usize,
+}
+
+impl LogSoftmax {
+    pub fn new(count: usize) -> Self {
+        Self { count }
+    }
+}
+
+impl Layer for LogSoftmax {
+    fn input_count(&self) -> usize {
+        self.count
+    }
+
+    fn output_count(&self) -> usize {
+        self.count
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &[]
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut []
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        assert_eq!(inputs.len(), outputs.len());
+        for (inputs, outputs) in inputs
+            .chunks_exact(self.count)
+            .zip(outputs.chunks_exact_mut(self.count))
+        {
+            log_softmax(inputs, outputs);
+        }
+    }
+
+    fn backward(
+        &mut self,
+        _inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        _parameter_gradients: &mut [f32],
+    ) {
+        for ((outputs, output_gradients), input_gradients) in outputs
+            .chunks_exact(self.count)
+            .zip(output_gradients.chunks_exact(self.count))
+            .zip(input_gradients.chunks_exact_mut(self.count))
+        {
+            let sum: f32 = output_gradients.iter().sum();
+            for ((input_gradient, output), output_gradient) in input_gradients
+                .iter_mut()
+                .zip(outputs)
+                .zip(output_gradients)
+            {
+                *input_gradient = output_gradient - output.exp() * sum;
+            }
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{
+        tests::{assert_gradients_match, uniform},
+        FullyConnectedLayer, Identity, Sequential,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn stable() {
+        let values = [1000.0, 1001.0, -1000.0];
+        let mut outputs = [0.0; 3];
+
+        softmax(&values, &mut outputs);
+        assert!(outputs.iter().all(|p| p.is_finite()));
+        assert!((outputs.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!(outputs[0] < outputs[1]);
+
+        log_softmax(&values, &mut outputs);
+        assert!(outputs.iter().all(|p| p.is_finite()));
+        assert!((outputs[1] - -(1.0 + (-1.0f32).exp()).ln()).abs() < 1e-6);
+        assert!((outputs[2] - (-2001.0 - (1.0 + (-1.0f32).exp()).ln())).abs() < 1e-3);
+
+        assert_eq!(argmax(&values), 1);
+        assert_eq!(argmax(&[2.0, 2.0]), 0);
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let layers: [Box<dyn Layer>; 2] = [Box::new(Softmax::new(4)), Box::new(LogSoftmax::new(4))];
+
+        for layer in layers {
+            let mut model = Sequential::new(vec![
+                Box::new(FullyConnectedLayer::with_activation(
+                    3,
+                    4,
+                    uniform(&mut rng, 4 * 4),
+                    Identity,
+                )),
+                layer,
+                Box::new(FullyConnectedLayer::with_activation(
+                    4,
+                    2,
+                    uniform(&mut rng, 5 * 2),
+                    Identity,
+                )),
+            ]);
+            let inputs = uniform(&mut rng, 3 * 5);
+            let targets = uniform(&mut rng, 2 * 5);
+
+            assert_gradients_match(&mut model, &inputs, &targets);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Tables that describe a model layer by layer, for comparing architectures.
+
+use super::Layer;
+use std::fmt;
+
+/// One row of a [`Summary`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerSummary {
+    pub name: String,
+    pub input_shape: Vec<usize>,
+    pub output_shape: Vec<usize>,
+    pub parameter_count: usize,
+    /// The estimated floating point operations of the forward pass per sample.
+    pub flops: usize,
+    /// The bytes of the outputs per sample.
+    pub activation_bytes: usize,
+}
+
+impl LayerSummary {
+    pub fn new(layer: &dyn Layer) -> Self {
+        Self {
+            name: layer.name(),
+            input_shape: layer.input_shape(),
+            output_shape: layer.output_shape(),
+            parameter_count: layer.parameters().len(),
+            flops: layer.flops(),
+            activation_bytes: layer.output_count() * std::mem::size_of::<f32>(),
+        }
+    }
+}
+
+/// The type, shapes, parameter count, estimated FLOPs and activation memory of every layer of a model, with totals.
+/// Displays as a table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub layers: Vec<LayerSummary>,
+}
+
+impl Summary {
+    pub fn parameter_count(&self) -> usize {
+        self.layers.iter().map(|layer| layer.parameter_count).sum()
+    }
+
+    pub fn flops(&self) -> usize {
+        self.layers.iter().map(|layer| layer.flops).sum()
+    }
+
+    /// The bytes of the outputs of all layers per sample, which is what training keeps around for the backward pass.
+    /// Buffers the layers allocate for themselves are not included.
+    pub fn activation_bytes(&self) -> usize {
+        self.layers.iter().map(|layer| layer.activation_bytes).sum()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = [
+            "Layer",
+            "Input",
+            "Output",
+            "Parameters",
+            "FLOPs",
+            "Activations",
+        ];
+        let mut rows: Vec<[String; 6]> = self
+            .layers
+            .iter()
+            .map(|layer| {
+                [
+                    layer.name.clone(),
+                    format_shape(&layer.input_shape),
+                    format_shape(&layer.output_shape),
+                    layer.parameter_count.to_string(),
+                    layer.flops.to_string(),
+                    format_bytes(layer.activation_bytes),
+                ]
+            })
+            .collect();
+        rows.push([
+            "Total".to_string(),
+            String::new(),
+            String::new(),
+            self.parameter_count().to_string(),
+            self.flops().to_string(),
+            format_bytes(self.activation_bytes()),
+        ]);
+
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        // Names and shapes are left aligned, numbers right aligned.
+        let write_row = |f: &mut fmt::Formatter<'_>, row: [&str; 6]| {
+            for (column, (cell, width)) in row.iter().zip(widths).enumerate() {
+                if column > 0 {
+                    write!(f, "  ")?;
+                }
+                if column < 3 {
+                    write!(f, "{cell:<width$}")?;
+                } else {
+                    write!(f, "{cell:>width$}")?;
+                }
+            }
+            writeln!(f)
+        };
+
+        let rule = "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len() - 1));
+        write_row(f, header)?;
+        writeln!(f, "{rule}")?;
+        let (total, rows) = rows.split_last().expect("the totals are always there");
+        for row in rows {
+            write_row(f, row.each_ref().map(String::as_str))?;
+        }
+        writeln!(f, "{rule}")?;
+        write_row(f, total.each_ref().map(String::as_str))
+    }
+}
+
+fn format_shape(shape: &[usize]) -> String {
+    let dims: Vec<String> = shape.iter().map(usize::to_string).collect();
+    format!("[{}]", dims.join(", "))
+}
+
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// The name of a type without the paths of its modules, so `nn::nn::FullyConnectedLayer<nn::nn::Identity>` becomes
+/// `FullyConnectedLayer<Identity>`.
+pub(crate) fn short_type_name<T: ?Sized>() -> String {
+    let name = std::any::type_name::<T>();
+    let mut short = String::with_capacity(name.len());
+    let mut start = 0;
+    for (index, c) in name.char_indices() {
+        if !(c.is_alphanumeric() || c == '_' || c == ':') {
+            short.push_str(last_segment(&name[start..index]));
+            short.push(c);
+            start = index + c.len_utf8();
+        }
+    }
+    short.push_str(last_segment(&name[start..]));
+    short
+}
+
+fn last_segment(path: &str) -> &str {
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{
+        Conv2d, FullyConnectedLayer, GlobalAvgPool, Identity, MaxPool2d, ReLU, Sequential,
+    };
+
+    #[test]
+    fn sequential() {
+        let model = Sequential::new(vec![
+            Box::new(Conv2d::new([1, 6, 6], 4, [3, 3], std::iter::repeat(0.1))),
+            Box::new(MaxPool2d::new([4, 4, 4], [2, 2])),
+            Box::new(GlobalAvgPool::new([4, 2, 2])),
+            Box::new(FullyConnectedLayer::with_activation(
+                4,
+                3,
+                std::iter::repeat(0.1),
+                Identity,
+            )),
+        ]);
+        let summary = model.summary();
+
+        let names: Vec<&str> = summary.layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Conv2d",
+                "MaxPool2d",
+                "GlobalAvgPool",
+                "FullyConnectedLayer<Identity>"
+            ]
+        );
+        assert_eq!(summary.layers[0].input_shape, [1, 6, 6]);
+        assert_eq!(summary.layers[0].output_shape, [4, 4, 4]);
+        assert_eq!(summary.layers[2].output_shape, [4]);
+        assert_eq!(summary.layers[3].parameter_count, (4 + 1) * 3);
+        assert_eq!(summary.parameter_count(), model.parameter_count());
+        // Every output of the convolution takes 9 multiply-adds, a bias and an activation.
+        assert_eq!(summary.layers[0].flops, 64 * (2 * 9 + 2));
+        assert_eq!(summary.activation_bytes(), 4 * (64 + 16 + 4 + 3));
+
+        let table = summary.to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 1 + 1 + 4 + 1 + 1);
+        assert!(lines.iter().all(|line| line.len() == lines[1].len()));
+        assert!(lines[7].starts_with("Total"));
+        assert!(lines[7].contains(&model.parameter_count().to_string()));
+    }
+
+    #[test]
+    fn names_and_bytes() {
+        assert_eq!(
+            short_type_name::<FullyConnectedLayer<Identity>>(),
+            "FullyConnectedLayer<Identity>"
+        );
+        // Type parameters that have their default value are left out.
+        assert_eq!(
+            short_type_name::<FullyConnectedLayer<ReLU>>(),
+            "FullyConnectedLayer"
+        );
+        assert_eq!(short_type_name::<Vec<(u8, String)>>(), "Vec<(u8, String)>");
+        assert_eq!(format_bytes(1000), "1000 B");
+        assert_eq!(format_bytes(3 * 1024 * 1024 / 2), "1.5 MiB");
+    }
+}
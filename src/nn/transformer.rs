@@ -0,0 +1,1035 @@
+//! Attention and transformer layers.
+//!
+//! Samples are sequences of `len` tokens of `dim` features each, stored token by token. Projections of tokens use
+//! weights in the layout of [`FullyConnectedLayer`], applied to every token separately.
+//!
+//! Attention masks are part of a layer and apply to every sample of every batch alike. Since a layer only receives the
+//! tokens, there is no way to pass a mask per sample, so sequences of different lengths cannot be padded with masked
+//! out tokens; use sequences of a fixed length instead.
+//!
+//! [`FullyConnectedLayer`]: super::FullyConnectedLayer
+
+use super::{
+    affine_backward, affine_forward, fully_connected_backward, fully_connected_forward,
+    norm::GroupedNorm, softmax::softmax, Identity, Layer, ReLU,
+};
+
+/// Splits `values` into consecutive parts of the given lengths.
+fn split<const N: usize>(mut values: &[f32], lens: [usize; N]) -> [&[f32]; N] {
+    lens.map(|len| {
+        let (head, tail) = values.split_at(len);
+        values = tail;
+        head
+    })
+}
+
+fn split_mut<const N: usize>(mut values: &mut [f32], lens: [usize; N]) -> [&mut [f32]; N] {
+    lens.map(|len| {
+        let (head, tail) = std::mem::take(&mut values).split_at_mut(len);
+        values = tail;
+        head
+    })
+}
+
+/// Collects `count` values from `initializer`.
+fn take<I: Iterator<Item = f32>>(initializer: &mut I, count: usize) -> Vec<f32> {
+    let values: Vec<f32> = initializer.take(count).collect();
+    assert_eq!(values.len(), count, "initializer ran out of values");
+    values
+}
+
+/// Multi-head scaled dot-product self-attention without its parameters, which are passed in: the query, key, value and
+/// output projections, in that order.
+#[derive(Debug, Clone)]
+struct Attention {
+    len: usize,
+    dim: usize,
+    heads: usize,
+    /// Whether query `i` may attend to key `j`, at `i * len + j`.
+    mask: Option<Vec<bool>>,
+    queries: Vec<f32>,
+    keys: Vec<f32>,
+    values: Vec<f32>,
+    /// The attention weights by sample, head, query and key.
+    probabilities: Vec<f32>,
+    /// The outputs of all heads before the output projection.
+    mixed: Vec<f32>,
+}
+
+impl Attention {
+    fn new(len: usize, dim: usize, heads: usize) -> Self {
+        assert_eq!(dim % heads, 0, "heads must divide the dimension");
+
+        Self {
+            len,
+            dim,
+            heads,
+            mask: None,
+            queries: Vec::new(),
+            keys: Vec::new(),
+            values: Vec::new(),
+            probabilities: Vec::new(),
+            mixed: Vec::new(),
+        }
+    }
+
+    fn set_mask(&mut self, mask: Vec<bool>) {
+        assert_eq!(mask.len(), self.len * self.len);
+        assert!(
+            mask.chunks_exact(self.len).all(|row| row.contains(&true)),
+            "every query must attend to at least one key"
+        );
+        self.mask = Some(mask);
+    }
+
+    fn parameter_count(&self) -> usize {
+        4 * (self.dim + 1) * self.dim
+    }
+
+    /// The projections of every token, then the scores and the weighted sums of the values.
+    fn flops(&self) -> usize {
+        2 * self.len * self.parameter_count() + 4 * self.len * self.len * self.dim
+    }
+
+    fn projections(&self) -> [usize; 4] {
+        [(self.dim + 1) * self.dim; 4]
+    }
+
+    fn forward(&mut self, parameters: &[f32], inputs: &[f32], outputs: &mut [f32]) {
+        let (len, dim) = (self.len, self.dim);
+        let head_dim = dim / self.heads;
+        let batch_size = inputs.len() / (len * dim);
+        assert_eq!(batch_size * len * dim, inputs.len());
+        assert_eq!(inputs.len(), outputs.len());
+        let [query_projection, key_projection, value_projection, output_projection] =
+            split(parameters, self.projections());
+
+        for (projection, projected) in [
+            (query_projection, &mut self.queries),
+            (key_projection, &mut self.keys),
+            (value_projection, &mut self.values),
+        ] {
+            projected.resize(inputs.len(), 0.0);
+            affine_forward(projection, dim, inputs, projected);
+        }
+
+        let scale = 1.0 / (head_dim as f32).sqrt();
+        self.probabilities
+            .resize(batch_size * self.heads * len * len, 0.0);
+        self.mixed.clear();
+        self.mixed.resize(inputs.len(), 0.0);
+        let mut scores = vec![0.0; len];
+
+        for sample in 0..batch_size {
+            let token = |t: usize, head: usize| (sample * len + t) * dim + head * head_dim;
+            for head in 0..self.heads {
+                for i in 0..len {
+                    let query = &self.queries[token(i, head)..][..head_dim];
+                    for (j, score) in scores.iter_mut().enumerate() {
+                        let key = &self.keys[token(j, head)..][..head_dim];
+                        *score = if self.mask.as_ref().is_some_and(|mask| !mask[i * len + j]) {
+                            f32::NEG_INFINITY
+                        } else {
+                            query.iter().zip(key).map(|(q, k)| q * k).sum::<f32>() * scale
+                        };
+                    }
+
+                    let probabilities = &mut self.probabilities
+                        [((sample * self.heads + head) * len + i) * len..][..len];
+                    softmax(&scores, probabilities);
+
+                    let mixed = &mut self.mixed[token(i, head)..][..head_dim];
+                    for (j, &probability) in probabilities.iter().enumerate() {
+                        let value = &self.values[token(j, head)..][..head_dim];
+                        for (mixed, value) in mixed.iter_mut().zip(value) {
+                            *mixed += probability * value;
+                        }
+                    }
+                }
+            }
+        }
+
+        affine_forward(output_projection, dim, &self.mixed, outputs);
+    }
+
+    fn backward(
+        &self,
+        parameters: &[f32],
+        inputs: &[f32],
+        output_gradients: &[f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        let (len, dim) = (self.len, self.dim);
+        let head_dim = dim / self.heads;
+        let batch_size = inputs.len() / (len * dim);
+        assert_eq!(self.mixed.len(), inputs.len(), "backward without forward");
+        let [query_projection, key_projection, value_projection, output_projection] =
+            split(parameters, self.projections());
+        let [query_gradients, key_gradients, value_gradients, output_projection_gradients] =
+            split_mut(parameter_gradients, self.projections());
+
+        let mut mixed_gradients = vec![0.0; inputs.len()];
+        affine_backward(
+            output_projection,
+            dim,
+            &self.mixed,
+            output_gradients,
+            &mut mixed_gradients,
+            output_projection_gradients,
+        );
+
+        let scale = 1.0 / (head_dim as f32).sqrt();
+        let mut dq = vec![0.0; inputs.len()];
+        let mut dk = vec![0.0; inputs.len()];
+        let mut dv = vec![0.0; inputs.len()];
+        let mut score_gradients = vec![0.0; len];
+
+        for sample in 0..batch_size {
+            let token = |t: usize, head: usize| (sample * len + t) * dim + head * head_dim;
+            for head in 0..self.heads {
+                for i in 0..len {
+                    let probabilities = &self.probabilities
+                        [((sample * self.heads + head) * len + i) * len..][..len];
+                    let mixed_gradients = &mixed_gradients[token(i, head)..][..head_dim];
+
+                    // Through the weighted sum of the values.
+                    for (j, (&probability, score_gradient)) in
+                        probabilities.iter().zip(&mut score_gradients).enumerate()
+                    {
+                        let value = &self.values[token(j, head)..][..head_dim];
+                        *score_gradient =
+                            mixed_gradients.iter().zip(value).map(|(g, v)| g * v).sum();
+                        for (dv, g) in dv[token(j, head)..][..head_dim]
+                            .iter_mut()
+                            .zip(mixed_gradients)
+                        {
+                            *dv += probability * g;
+                        }
+                    }
+
+                    // Through the softmax.
+                    let dot: f32 = probabilities
+                        .iter()
+                        .zip(&score_gradients)
+                        .map(|(p, g)| p * g)
+                        .sum();
+                    for (score_gradient, probability) in
+                        score_gradients.iter_mut().zip(probabilities)
+                    {
+                        *score_gradient = probability * (*score_gradient - dot) * scale;
+                    }
+
+                    // Through the dot products of the query with the keys.
+                    for (j, &score_gradient) in score_gradients.iter().enumerate() {
+                        for f in 0..head_dim {
+                            dq[token(i, head) + f] +=
+                                score_gradient * self.keys[token(j, head) + f];
+                            dk[token(j, head) + f] +=
+                                score_gradient * self.queries[token(i, head) + f];
+                        }
+                    }
+                }
+            }
+        }
+
+        input_gradients.fill(0.0);
+        let mut projection_input_gradients = vec![0.0; inputs.len()];
+        for (projection, gradients, projection_gradients) in [
+            (query_projection, &dq, query_gradients),
+            (key_projection, &dk, key_gradients),
+            (value_projection, &dv, value_gradients),
+        ] {
+            affine_backward(
+                projection,
+                dim,
+                inputs,
+                gradients,
+                &mut projection_input_gradients,
+                projection_gradients,
+            );
+            for (input_gradient, gradient) in
+                input_gradients.iter_mut().zip(&projection_input_gradients)
+            {
+                *input_gradient += gradient;
+            }
+        }
+    }
+}
+
+/// Multi-head scaled dot-product self-attention. The parameters are the query, key, value and output projections, in
+/// that order, each from `dim` to `dim` features.
+#[derive(Debug, Clone)]
+pub struct MultiHeadAttention {
+    attention: Attention,
+    weights_and_biases: Vec<f32>,
+}
+
+impl MultiHeadAttention {
+    pub fn new<I: IntoIterator<Item = f32>>(
+        len: usize,
+        dim: usize,
+        heads: usize,
+        initializer: I,
+    ) -> Self {
+        let attention = Attention::new(len, dim, heads);
+        Self {
+            weights_and_biases: take(&mut initializer.into_iter(), attention.parameter_count()),
+            attention,
+        }
+    }
+
+    /// Only lets query `i` attend to key `j` if `mask[i * len + j]` is true, in every sample. See the
+    /// [module documentation](self) for masks that differ between samples.
+    pub fn with_mask(mut self, mask: Vec<bool>) -> Self {
+        self.attention.set_mask(mask);
+        self
+    }
+
+    /// Only lets every token attend to itself and the tokens before it.
+    pub fn with_causal_mask(self) -> Self {
+        let len = self.attention.len;
+        self.with_mask(causal_mask(len))
+    }
+}
+
+fn causal_mask(len: usize) -> Vec<bool> {
+    (0..len * len)
+        .map(|index| index % len <= index / len)
+        .collect()
+}
+
+impl Layer for MultiHeadAttention {
+    fn input_count(&self) -> usize {
+        self.attention.len * self.attention.dim
+    }
+
+    fn output_count(&self) -> usize {
+        self.attention.len * self.attention.dim
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        vec![self.attention.len, self.attention.dim]
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.attention.len, self.attention.dim]
+    }
+
+    fn flops(&self) -> usize {
+        self.attention.flops()
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.weights_and_biases
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.weights_and_biases
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        self.attention
+            .forward(&self.weights_and_biases, inputs, outputs);
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        _outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        self.attention.backward(
+            &self.weights_and_biases,
+            inputs,
+            output_gradients,
+            input_gradients,
+            parameter_gradients,
+        );
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Adds an encoding of the position to every token, either the fixed sinusoids of the original transformer or learned
+/// parameters.
+#[derive(Debug, Clone)]
+pub struct PositionalEncoding {
+    dim: usize,
+    learned: bool,
+    encodings: Vec<f32>,
+}
+
+impl PositionalEncoding {
+    /// Feature `2i` of token `t` gets `sin(t / 10000^(2i / dim))` and feature `2i + 1` the cosine of the same.
+    pub fn sinusoidal(len: usize, dim: usize) -> Self {
+        let encodings = (0..len * dim)
+            .map(|index| {
+                let (position, feature) = ((index / dim) as f32, index % dim);
+                let angle = position / 10000f32.powf((feature - feature % 2) as f32 / dim as f32);
+                if feature % 2 == 0 {
+                    angle.sin()
+                } else {
+                    angle.cos()
+                }
+            })
+            .collect();
+
+        Self {
+            dim,
+            learned: false,
+            encodings,
+        }
+    }
+
+    pub fn learned<I: IntoIterator<Item = f32>>(len: usize, dim: usize, initializer: I) -> Self {
+        Self {
+            dim,
+            learned: true,
+            encodings: take(&mut initializer.into_iter(), len * dim),
+        }
+    }
+}
+
+impl Layer for PositionalEncoding {
+    fn input_count(&self) -> usize {
+        self.encodings.len()
+    }
+
+    fn output_count(&self) -> usize {
+        self.encodings.len()
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        vec![self.encodings.len() / self.dim, self.dim]
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.input_shape()
+    }
+
+    fn parameters(&self) -> &[f32] {
+        if self.learned {
+            &self.encodings
+        } else {
+            &[]
+        }
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        if self.learned {
+            &mut self.encodings
+        } else {
+            &mut []
+        }
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        assert_eq!(inputs.len(), outputs.len());
+        for (inputs, outputs) in inputs
+            .chunks_exact(self.encodings.len())
+            .zip(outputs.chunks_exact_mut(self.encodings.len()))
+        {
+            for ((output, input), encoding) in outputs.iter_mut().zip(inputs).zip(&self.encodings) {
+                *output = input + encoding;
+            }
+        }
+    }
+
+    fn backward(
+        &mut self,
+        _inputs: &[f32],
+        _outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        input_gradients.copy_from_slice(output_gradients);
+        if self.learned {
+            for sample_gradients in output_gradients.chunks_exact(self.encodings.len()) {
+                for (parameter_gradient, gradient) in
+                    parameter_gradients.iter_mut().zip(sample_gradients)
+                {
+                    *parameter_gradient += gradient;
+                }
+            }
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// A pre-norm transformer encoder block: `y = x + attention(norm(x))` followed by `y + feed_forward(norm(y))`, where
+/// the feed-forward network is a [`FullyConnectedLayer`](super::FullyConnectedLayer) with ReLU to `hidden` features
+/// followed by one without activation back to `dim` features, applied to every token separately.
+///
+/// The parameters are the scales and shifts of the first normalization, the attention as in
+/// [`MultiHeadAttention`], the scales and shifts of the second normalization and the two fully connected layers.
+#[derive(Debug, Clone)]
+pub struct TransformerEncoderBlock {
+    dim: usize,
+    hidden: usize,
+    norm: GroupedNorm,
+    attention: Attention,
+    parameters: Vec<f32>,
+    normalized_inputs: Vec<f32>,
+    residuals: Vec<f32>,
+    normalized_residuals: Vec<f32>,
+    hidden_activations: Vec<f32>,
+}
+
+impl TransformerEncoderBlock {
+    /// Takes the weights of the attention and then of the feed-forward network from `initializer`. The normalizations
+    /// start out as the identity.
+    pub fn new<I: IntoIterator<Item = f32>>(
+        len: usize,
+        dim: usize,
+        heads: usize,
+        hidden: usize,
+        initializer: I,
+    ) -> Self {
+        let norm = GroupedNorm::rows(len, dim);
+        let attention = Attention::new(len, dim, heads);
+        let mut initializer = initializer.into_iter();
+        let parameters = [
+            norm.initial_parameters(),
+            take(&mut initializer, attention.parameter_count()),
+            norm.initial_parameters(),
+            take(&mut initializer, (dim + 1) * hidden + (hidden + 1) * dim),
+        ]
+        .concat();
+
+        Self {
+            dim,
+            hidden,
+            norm,
+            attention,
+            parameters,
+            normalized_inputs: Vec::new(),
+            residuals: Vec::new(),
+            normalized_residuals: Vec::new(),
+            hidden_activations: Vec::new(),
+        }
+    }
+
+    /// Only lets every token attend to itself and the tokens before it.
+    pub fn with_causal_mask(mut self) -> Self {
+        self.attention.set_mask(causal_mask(self.attention.len));
+        self
+    }
+
+    fn sections(&self) -> [usize; 5] {
+        [
+            2 * self.dim,
+            self.attention.parameter_count(),
+            2 * self.dim,
+            (self.dim + 1) * self.hidden,
+            (self.hidden + 1) * self.dim,
+        ]
+    }
+}
+
+impl Layer for TransformerEncoderBlock {
+    fn input_count(&self) -> usize {
+        self.attention.len * self.dim
+    }
+
+    fn output_count(&self) -> usize {
+        self.attention.len * self.dim
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        vec![self.attention.len, self.dim]
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.attention.len, self.dim]
+    }
+
+    fn flops(&self) -> usize {
+        let [_, _, _, expand, contract] = self.sections();
+        self.attention.flops() + 2 * self.attention.len * (expand + contract)
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.parameters
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.parameters
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        assert_eq!(inputs.len(), outputs.len());
+        let [first_norm, attention, second_norm, expand, contract] =
+            split(&self.parameters, self.sections());
+        let tokens = inputs.len() / self.dim;
+
+        self.normalized_inputs.resize(inputs.len(), 0.0);
+        self.norm
+            .forward(first_norm, inputs, &mut self.normalized_inputs);
+
+        self.residuals.resize(inputs.len(), 0.0);
+        self.attention
+            .forward(attention, &self.normalized_inputs, &mut self.residuals);
+        for (residual, input) in self.residuals.iter_mut().zip(inputs) {
+            *residual += input;
+        }
+
+        self.normalized_residuals.resize(inputs.len(), 0.0);
+        self.norm
+            .forward(second_norm, &self.residuals, &mut self.normalized_residuals);
+
+        self.hidden_activations.resize(tokens * self.hidden, 0.0);
+        fully_connected_forward(
+            expand,
+            self.dim,
+            &ReLU,
+            &self.normalized_residuals,
+            &mut self.hidden_activations,
+        );
+        fully_connected_forward(
+            contract,
+            self.hidden,
+            &Identity,
+            &self.hidden_activations,
+            outputs,
+        );
+        for (output, residual) in outputs.iter_mut().zip(&self.residuals) {
+            *output += residual;
+        }
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        let [first_norm, attention, second_norm, expand, contract] =
+            split(&self.parameters, self.sections());
+        let [first_norm_gradients, attention_gradients, second_norm_gradients, expand_gradients, contract_gradients] =
+            split_mut(parameter_gradients, self.sections());
+
+        // The derivative of the identity does not depend on the outputs, so it leaves the output gradients unchanged
+        // for the skip connection below.
+        let mut hidden_gradients = vec![0.0; self.hidden_activations.len()];
+        fully_connected_backward(
+            contract,
+            self.hidden,
+            &Identity,
+            &self.hidden_activations,
+            outputs,
+            output_gradients,
+            &mut hidden_gradients,
+            contract_gradients,
+        );
+
+        let mut normalized_gradients = vec![0.0; inputs.len()];
+        fully_connected_backward(
+            expand,
+            self.dim,
+            &ReLU,
+            &self.normalized_residuals,
+            &self.hidden_activations,
+            &mut hidden_gradients,
+            &mut normalized_gradients,
+            expand_gradients,
+        );
+
+        // The gradient with respect to the residuals flows through the skip connection and the second normalization.
+        let mut residual_gradients = vec![0.0; inputs.len()];
+        self.norm.backward(
+            second_norm,
+            &self.residuals,
+            &normalized_gradients,
+            &mut residual_gradients,
+            second_norm_gradients,
+        );
+        for (residual_gradient, output_gradient) in
+            residual_gradients.iter_mut().zip(&*output_gradients)
+        {
+            *residual_gradient += output_gradient;
+        }
+
+        self.attention.backward(
+            attention,
+            &self.normalized_inputs,
+            &residual_gradients,
+            &mut normalized_gradients,
+            attention_gradients,
+        );
+        self.norm.backward(
+            first_norm,
+            inputs,
+            &normalized_gradients,
+            input_gradients,
+            first_norm_gradients,
+        );
+        for (input_gradient, residual_gradient) in
+            input_gradients.iter_mut().zip(&residual_gradients)
+        {
+            *input_gradient += residual_gradient;
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Turns images into tokens, as in a vision transformer. Samples are stored as channels, then rows, then columns, and
+/// are cut into non-overlapping square patches of `patch_size` pixels, in row-major order. Every patch, stored by
+/// channel, row and column, is projected to `dim` features with weights in the layout of
+/// [`FullyConnectedLayer`](super::FullyConnectedLayer).
+#[derive(Debug, Clone)]
+pub struct PatchEmbedding {
+    input_shape: [usize; 3],
+    patch_size: usize,
+    dim: usize,
+    weights_and_biases: Vec<f32>,
+    patches: Vec<f32>,
+}
+
+impl PatchEmbedding {
+    pub fn new<I: IntoIterator<Item = f32>>(
+        input_shape: [usize; 3],
+        patch_size: usize,
+        dim: usize,
+        initializer: I,
+    ) -> Self {
+        let [channels, rows, cols] = input_shape;
+        assert!(
+            rows % patch_size == 0 && cols % patch_size == 0,
+            "patches must tile the image"
+        );
+
+        Self {
+            input_shape,
+            patch_size,
+            dim,
+            weights_and_biases: take(
+                &mut initializer.into_iter(),
+                (channels * patch_size * patch_size + 1) * dim,
+            ),
+            patches: Vec::new(),
+        }
+    }
+
+    /// The number of tokens per sample.
+    pub fn token_count(&self) -> usize {
+        let [_, rows, cols] = self.input_shape;
+        (rows / self.patch_size) * (cols / self.patch_size)
+    }
+
+    fn patch_len(&self) -> usize {
+        self.input_shape[0] * self.patch_size * self.patch_size
+    }
+
+    /// Calls `f` with the index in the patches and the index in the inputs of every pixel in the batch.
+    fn for_each_pixel(&self, batch_size: usize, mut f: impl FnMut(usize, usize)) {
+        let [channels, rows, cols] = self.input_shape;
+        let p = self.patch_size;
+        let mut index = 0;
+        for sample in 0..batch_size {
+            for patch_row in 0..rows / p {
+                for patch_col in 0..cols / p {
+                    for channel in 0..channels {
+                        for row in patch_row * p..(patch_row + 1) * p {
+                            for col in patch_col * p..(patch_col + 1) * p {
+                                f(
+                                    index,
+                                    ((sample * channels + channel) * rows + row) * cols + col,
+                                );
+                                index += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Layer for PatchEmbedding {
+    fn input_count(&self) -> usize {
+        self.input_shape.iter().product()
+    }
+
+    fn output_count(&self) -> usize {
+        self.token_count() * self.dim
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        self.input_shape.to_vec()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.token_count(), self.dim]
+    }
+
+    fn flops(&self) -> usize {
+        2 * self.token_count() * self.weights_and_biases.len()
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &self.weights_and_biases
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut self.weights_and_biases
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        let batch_size = inputs.len() / self.input_count();
+        let mut patches = std::mem::take(&mut self.patches);
+        patches.resize(inputs.len(), 0.0);
+        self.for_each_pixel(batch_size, |patch_index, input_index| {
+            patches[patch_index] = inputs[input_index];
+        });
+        affine_forward(
+            &self.weights_and_biases,
+            self.patch_len(),
+            &patches,
+            outputs,
+        );
+        self.patches = patches;
+    }
+
+    fn backward(
+        &mut self,
+        inputs: &[f32],
+        _outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        parameter_gradients: &mut [f32],
+    ) {
+        let batch_size = inputs.len() / self.input_count();
+        let mut patch_gradients = vec![0.0; inputs.len()];
+        affine_backward(
+            &self.weights_and_biases,
+            self.patch_len(),
+            &self.patches,
+            output_gradients,
+            &mut patch_gradients,
+            parameter_gradients,
+        );
+        self.for_each_pixel(batch_size, |patch_index, input_index| {
+            input_gradients[input_index] = patch_gradients[patch_index];
+        });
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Averages the tokens of every sample into a single token of `dim` features.
+#[derive(Debug, Clone)]
+pub struct TokenMean {
+    len: usize,
+    dim: usize,
+}
+
+impl TokenMean {
+    pub fn new(len: usize, dim: usize) -> Self {
+        Self { len, dim }
+    }
+}
+
+impl Layer for TokenMean {
+    fn input_count(&self) -> usize {
+        self.len * self.dim
+    }
+
+    fn output_count(&self) -> usize {
+        self.dim
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        vec![self.len, self.dim]
+    }
+
+    fn flops(&self) -> usize {
+        self.input_count()
+    }
+
+    fn parameters(&self) -> &[f32] {
+        &[]
+    }
+
+    fn parameters_mut(&mut self) -> &mut [f32] {
+        &mut []
+    }
+
+    fn forward(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        outputs.fill(0.0);
+        let scale = 1.0 / self.len as f32;
+        for (inputs, outputs) in inputs
+            .chunks_exact(self.len * self.dim)
+            .zip(outputs.chunks_exact_mut(self.dim))
+        {
+            for token in inputs.chunks_exact(self.dim) {
+                for (output, input) in outputs.iter_mut().zip(token) {
+                    *output += input * scale;
+                }
+            }
+        }
+    }
+
+    fn backward(
+        &mut self,
+        _inputs: &[f32],
+        _outputs: &[f32],
+        output_gradients: &mut [f32],
+        input_gradients: &mut [f32],
+        _parameter_gradients: &mut [f32],
+    ) {
+        let scale = 1.0 / self.len as f32;
+        for (input_gradients, output_gradients) in input_gradients
+            .chunks_exact_mut(self.len * self.dim)
+            .zip(output_gradients.chunks_exact(self.dim))
+        {
+            for token in input_gradients.chunks_exact_mut(self.dim) {
+                for (input_gradient, output_gradient) in token.iter_mut().zip(output_gradients) {
+                    *input_gradient = output_gradient * scale;
+                }
+            }
+        }
+    }
+
+    fn clone_layer(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{
+        tests::{assert_gradients_match, uniform},
+        FullyConnectedLayer, Identity, Sequential,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn causal_mask_hides_later_tokens() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let mut attention =
+            MultiHeadAttention::new(3, 4, 2, uniform(&mut rng, 4 * 5 * 4)).with_causal_mask();
+        let mut inputs = uniform(&mut rng, 3 * 4);
+        let mut before = vec![0.0; 3 * 4];
+        attention.forward(&inputs, &mut before);
+
+        // Changing the last token changes only its own output.
+        inputs[8] += 1.0;
+        let mut after = vec![0.0; 3 * 4];
+        attention.forward(&inputs, &mut after);
+        assert_eq!(before[..8], after[..8]);
+        assert_ne!(before[8..], after[8..]);
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = StdRng::from_seed([6u8; 32]);
+        let (len, dim) = (3, 4);
+        // Finite differences are unreliable where they cross the kink of a ReLU in the feed-forward network, so the
+        // weights are kept small and the seed is one where no pre-activation is close to zero.
+        let small = |rng: &mut StdRng, count| -> Vec<f32> {
+            uniform(rng, count).iter().map(|x| 0.5 * x).collect()
+        };
+
+        let layers: [Box<dyn Layer>; 5] = [
+            Box::new(MultiHeadAttention::new(
+                len,
+                dim,
+                2,
+                small(&mut rng, 4 * 5 * 4),
+            )),
+            Box::new(
+                MultiHeadAttention::new(len, dim, 1, small(&mut rng, 4 * 5 * 4)).with_causal_mask(),
+            ),
+            Box::new(PositionalEncoding::learned(
+                len,
+                dim,
+                small(&mut rng, len * dim),
+            )),
+            Box::new(TransformerEncoderBlock::new(
+                len,
+                dim,
+                2,
+                6,
+                small(&mut rng, 4 * 5 * 4 + 5 * 6 + 7 * 4),
+            )),
+            Box::new(
+                TransformerEncoderBlock::new(
+                    len,
+                    dim,
+                    2,
+                    6,
+                    small(&mut rng, 4 * 5 * 4 + 5 * 6 + 7 * 4),
+                )
+                .with_causal_mask(),
+            ),
+        ];
+
+        for layer in layers {
+            let mut model = Sequential::new(vec![
+                Box::new(FullyConnectedLayer::with_activation(
+                    2,
+                    len * dim,
+                    small(&mut rng, 3 * len * dim),
+                    Identity,
+                )),
+                layer,
+                Box::new(FullyConnectedLayer::with_activation(
+                    len * dim,
+                    2,
+                    small(&mut rng, (len * dim + 1) * 2),
+                    Identity,
+                )),
+            ]);
+            let inputs = small(&mut rng, 2 * 4);
+            let targets = small(&mut rng, 2 * 4);
+
+            assert_gradients_match(&mut model, &inputs, &targets);
+        }
+    }
+
+    #[test]
+    fn vision_transformer_gradients() {
+        let mut rng = StdRng::from_seed([2u8; 32]);
+        let embedding = PatchEmbedding::new([2, 4, 4], 2, 3, uniform(&mut rng, 9 * 3));
+        assert_eq!(embedding.token_count(), 4);
+
+        let mut model = Sequential::new(vec![
+            Box::new(FullyConnectedLayer::with_activation(
+                2,
+                32,
+                uniform(&mut rng, 3 * 32),
+                Identity,
+            )),
+            Box::new(embedding),
+            Box::new(PositionalEncoding::sinusoidal(4, 3)),
+            Box::new(TokenMean::new(4, 3)),
+            Box::new(FullyConnectedLayer::with_activation(
+                3,
+                2,
+                uniform(&mut rng, 4 * 2),
+                Identity,
+            )),
+        ]);
+        let inputs = uniform(&mut rng, 2 * 3);
+        let targets = uniform(&mut rng, 2 * 3);
+
+        assert_gradients_match(&mut model, &inputs, &targets);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::nn::TouchedRows;
+
+/// Updates parameters given the gradient of the loss with respect to them.
+pub trait Optimizer {
+    /// Updates one group of parameters. Stateful optimizers use `group` to tell the groups apart.
+    fn step(&mut self, group: usize, parameters: &mut [f32], gradients: &[f32]);
+
+    /// Updates one group of parameters of a layer with sparse gradients, whose gradients outside of `rows` are zero.
+    /// Defaults to [`Optimizer::step`] on the whole group; optimizers for which a zero gradient leaves a parameter
+    /// unchanged can step only the rows.
+    fn step_rows(
+        &mut self,
+        group: usize,
+        parameters: &mut [f32],
+        gradients: &[f32],
+        _rows: &TouchedRows,
+    ) {
+        self.step(group, parameters, gradients);
+    }
+
+    fn learning_rate(&self) -> f32;
+
+    /// Changes the step size, as a [`Scheduler`] does between epochs.
+    fn set_learning_rate(&mut self, learning_rate: f32);
+}
+
+/// Stochastic gradient descent.
+#[derive(Debug, Clone)]
+pub struct Sgd {
+    pub learning_rate: f32,
+}
+
+impl Sgd {
+    pub fn new(learning_rate: f32) -> Self {
+        Self { learning_rate }
+    }
+}
+
+impl Optimizer for Sgd {
+    fn step(&mut self, _group: usize, parameters: &mut [f32], gradients: &[f32]) {
+        assert_eq!(parameters.len(), gradients.len());
+
+        for (parameter, gradient) in parameters.iter_mut().zip(gradients) {
+            *parameter -= self.learning_rate * gradient;
+        }
+    }
+
+    fn step_rows(
+        &mut self,
+        group: usize,
+        parameters: &mut [f32],
+        gradients: &[f32],
+        rows: &TouchedRows,
+    ) {
+        assert_eq!(parameters.len(), gradients.len());
+
+        for range in rows.ranges() {
+            self.step(group, &mut parameters[range.clone()], &gradients[range]);
+        }
+    }
+
+    fn learning_rate(&self) -> f32 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f32) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+/// The L2 norm of all gradients together.
+pub fn global_norm(gradients: &[f32]) -> f32 {
+    gradients.iter().map(|g| g * g).sum::<f32>().sqrt()
+}
+
+/// Limits the gradients before the optimizer step, to keep a few large gradients from throwing training off. The limit
+/// must be positive and finite.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradientClipping {
+    /// Scales all gradients down together so that their [`global_norm`] is at most the given value. This keeps the
+    /// direction of the step.
+    GlobalNorm(f32),
+    /// Clamps every gradient to at most the given magnitude.
+    Value(f32),
+}
+
+impl GradientClipping {
+    /// The norm or magnitude that gradients are clipped to.
+    pub fn limit(&self) -> f32 {
+        match *self {
+            GradientClipping::GlobalNorm(limit) | GradientClipping::Value(limit) => limit,
+        }
+    }
+
+    pub(crate) fn assert_valid(&self) {
+        let limit = self.limit();
+        assert!(
+            limit.is_finite() && limit > 0.0,
+            "gradient clipping limit must be positive, got {limit}"
+        );
+    }
+
+    /// Clips `gradients` in place and returns their global norm before clipping.
+    pub fn apply(&self, gradients: &mut [f32]) -> f32 {
+        self.assert_valid();
+        let norm = global_norm(gradients);
+        match *self {
+            GradientClipping::GlobalNorm(max_norm) => {
+                if norm > max_norm {
+                    let scale = max_norm / norm;
+                    for gradient in gradients {
+                        *gradient *= scale;
+                    }
+                }
+            }
+            GradientClipping::Value(max_value) => {
+                for gradient in gradients {
+                    *gradient = gradient.clamp(-max_value, max_value);
+                }
+            }
+        }
+        norm
+    }
+}
+
+/// Chooses the learning rate of every epoch, counting from zero.
+pub trait Scheduler {
+    fn learning_rate(&self, epoch: usize) -> f32;
+}
+
+/// Multiplies the learning rate by `factor` every `step_size` epochs.
+#[derive(Debug, Clone)]
+pub struct StepDecay {
+    pub initial: f32,
+    pub factor: f32,
+    pub step_size: usize,
+}
+
+impl StepDecay {
+    pub fn new(initial: f32, factor: f32, step_size: usize) -> Self {
+        assert!(step_size > 0, "step size must be at least one epoch");
+        Self {
+            initial,
+            factor,
+            step_size,
+        }
+    }
+}
+
+impl Scheduler for StepDecay {
+    fn learning_rate(&self, epoch: usize) -> f32 {
+        self.initial * self.factor.powi((epoch / self.step_size) as i32)
+    }
+}
+
+/// Lowers the learning rate from `initial` to `minimum` along half a cosine over `epochs` epochs, and keeps it at
+/// `minimum` afterwards.
+#[derive(Debug, Clone)]
+pub struct CosineAnnealing {
+    pub initial: f32,
+    pub minimum: f32,
+    pub epochs: usize,
+}
+
+impl CosineAnnealing {
+    pub fn new(initial: f32, minimum: f32, epochs: usize) -> Self {
+        Self {
+            initial,
+            minimum,
+            epochs,
+        }
+    }
+}
+
+impl Scheduler for CosineAnnealing {
+    fn learning_rate(&self, epoch: usize) -> f32 {
+        let progress = epoch.min(self.epochs) as f32 / self.epochs.max(1) as f32;
+        self.minimum
+            + 0.5 * (self.initial - self.minimum) * (1.0 + (std::f32::consts::PI * progress).cos())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clipping() {
+        let mut gradients = [3.0, -4.0];
+        assert_eq!(
+            GradientClipping::GlobalNorm(10.0).apply(&mut gradients),
+            5.0
+        );
+        assert_eq!(gradients, [3.0, -4.0]);
+        assert_eq!(GradientClipping::GlobalNorm(1.0).apply(&mut gradients), 5.0);
+        assert!((global_norm(&gradients) - 1.0).abs() < 1e-6);
+        assert!((gradients[0] - 0.6).abs() < 1e-6 && (gradients[1] + 0.8).abs() < 1e-6);
+
+        let mut gradients = [3.0, -4.0, 0.5];
+        GradientClipping::Value(1.0).apply(&mut gradients);
+        assert_eq!(gradients, [1.0, -1.0, 0.5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "gradient clipping limit must be positive")]
+    fn negative_clipping() {
+        GradientClipping::Value(-1.0).apply(&mut [1.0]);
+    }
+
+    #[test]
+    fn schedulers() {
+        let step = StepDecay::new(1.0, 0.5, 2);
+        let rates: Vec<f32> = (0..5).map(|epoch| step.learning_rate(epoch)).collect();
+        assert_eq!(rates, [1.0, 1.0, 0.5, 0.5, 0.25]);
+
+        let cosine = CosineAnnealing::new(1.0, 0.1, 4);
+        assert_eq!(cosine.learning_rate(0), 1.0);
+        assert!((cosine.learning_rate(2) - 0.55).abs() < 1e-6);
+        assert!((cosine.learning_rate(4) - 0.1).abs() < 1e-6);
+        assert!((cosine.learning_rate(9) - 0.1).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,475 @@
+//! Data parallel training.
+//!
+//! A batch is split into shards of a fixed number of samples. Every replica of the model that is assigned at least one
+//! shard computes the gradients of its shards on a scoped thread of its own, so a batch with fewer shards than
+//! replicas starts fewer threads. The per-shard gradients are then summed in shard order. Since neither the shards
+//! nor the order of the summation depend on the number of threads, the result is bit-for-bit the same for any number
+//! of threads.
+//!
+//! For the same reason, layers that use randomness, like dropout, are reseeded for every shard from the seed of the
+//! [`DataParallel`], the step and the index of the shard, rather than from the state of the replica that happens to
+//! process the shard.
+//!
+//! State that layers update themselves, like the running statistics of batch normalization, is handled the same way:
+//! every shard starts from the state of the model, and afterwards the model receives the mean of the resulting states
+//! of all shards. Layers that compute statistics over the batch only see their shard: a
+//! [`BatchNorm`](crate::nn::BatchNorm) normalizes every shard with the statistics of that shard, so its behavior
+//! depends on the shard size and not on the batch size.
+//!
+//! Layers with sparse gradients, like [`Embedding`](crate::nn::Embedding), report the rows they touched in every
+//! shard. Only those rows are summed, and the model is told about them so that [`Sequential::update`] only steps
+//! them.
+//!
+//! With anomaly detection on, the replicas check their shards, and the model records the anomaly of the first shard
+//! that has one, with the sample counted from the start of the batch.
+
+use crate::{
+    anomaly::Anomaly,
+    loss::Loss,
+    nn::{derive_seed, Sequential, TouchedRows},
+};
+use std::{num::NonZeroUsize, ops::Range};
+
+pub struct DataParallel {
+    replicas: Vec<Sequential>,
+    shard_size: usize,
+    shard_gradients: Vec<Vec<f32>>,
+    shard_states: Vec<Vec<f32>>,
+    seed: u64,
+    step: u64,
+}
+
+impl DataParallel {
+    pub fn new(model: &Sequential, threads: NonZeroUsize, shard_size: NonZeroUsize) -> Self {
+        Self {
+            replicas: (0..threads.get()).map(|_| model.clone()).collect(),
+            shard_size: shard_size.get(),
+            shard_gradients: Vec::new(),
+            shard_states: Vec::new(),
+            seed: 0,
+            step: 0,
+        }
+    }
+
+    /// Sets the seed from which the seeds of the layers are derived for every shard.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Uses one thread per available core.
+    pub fn with_available_parallelism(model: &Sequential, shard_size: NonZeroUsize) -> Self {
+        let threads = std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+        Self::new(model, threads, shard_size)
+    }
+
+    pub fn threads(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// Computes the loss and its gradient with respect to the parameters of `model`, both averaged over the samples in
+    /// the batch, which must not be empty, plus the regularization penalties of the layers. Returns the loss and writes
+    /// the gradient to `gradients`. Updates the state of `model`, if it has any.
+    pub fn gradients<L>(
+        &mut self,
+        model: &mut Sequential,
+        inputs: &[f32],
+        targets: &[f32],
+        loss: &L,
+        gradients: &mut [f32],
+    ) -> f32
+    where
+        L: Loss + ?Sized,
+    {
+        let input_count = model.input_count();
+        let output_count = model.output_count();
+        assert!(input_count > 0, "the model has no inputs");
+        let batch_size = inputs.len() / input_count;
+        assert!(batch_size > 0, "the batch is empty");
+        assert_eq!(batch_size * input_count, inputs.len());
+        assert_eq!(batch_size * output_count, targets.len());
+        assert_eq!(model.parameter_count(), gradients.len());
+
+        let shards: Vec<_> = inputs
+            .chunks(self.shard_size * input_count)
+            .zip(targets.chunks(self.shard_size * output_count))
+            .collect();
+
+        // Only replicas that get a shard do any work.
+        let threads = self.replicas.len().min(shards.len()).max(1);
+        for replica in &mut self.replicas[..threads] {
+            replica.copy_parameters_from(model);
+        }
+
+        let state = model.state();
+        resize_buffers(&mut self.shard_gradients, shards.len(), gradients.len());
+        resize_buffers(&mut self.shard_states, shards.len(), state.len());
+        let mut shard_losses = vec![0.0; shards.len()];
+        let mut shard_anomalies: Vec<Option<Anomaly>> = vec![None; shards.len()];
+        let mut shard_touched: Vec<Vec<Option<TouchedRows>>> = vec![Vec::new(); shards.len()];
+
+        // Assign shards to replicas round-robin.
+        let step_seed = derive_seed(self.seed, self.step);
+        self.step += 1;
+        std::thread::scope(|scope| {
+            let mut assignments: Vec<Vec<_>> = (0..threads).map(|_| Vec::new()).collect();
+            for (
+                index,
+                (
+                    ((((shard, shard_gradients), shard_state), shard_loss), shard_anomaly),
+                    shard_touched,
+                ),
+            ) in shards
+                .iter()
+                .zip(&mut self.shard_gradients)
+                .zip(&mut self.shard_states)
+                .zip(&mut shard_losses)
+                .zip(&mut shard_anomalies)
+                .zip(&mut shard_touched)
+                .enumerate()
+            {
+                let shard_seed = derive_seed(step_seed, index as u64);
+                assignments[index % threads].push((
+                    shard,
+                    index * self.shard_size,
+                    shard_seed,
+                    shard_gradients,
+                    shard_state,
+                    shard_loss,
+                    shard_anomaly,
+                    shard_touched,
+                ));
+            }
+
+            for (replica, assignment) in self.replicas.iter_mut().zip(assignments) {
+                if assignment.is_empty() {
+                    continue;
+                }
+                let state = &state;
+                scope.spawn(move || {
+                    for (
+                        (inputs, targets),
+                        first_sample,
+                        shard_seed,
+                        shard_gradients,
+                        shard_state,
+                        shard_loss,
+                        shard_anomaly,
+                        shard_touched,
+                    ) in assignment
+                    {
+                        replica.set_seed(shard_seed);
+                        replica.set_state(state);
+                        // The shard gradients are left zero after every batch.
+                        *shard_loss = replica.gradients(inputs, targets, loss, shard_gradients);
+                        *shard_touched = replica.take_touched_rows();
+                        shard_state.copy_from_slice(&replica.state());
+                        *shard_anomaly = replica.take_anomaly().map(|mut anomaly| {
+                            anomaly.sample = anomaly.sample.map(|sample| first_sample + sample);
+                            anomaly
+                        });
+                    }
+                });
+            }
+        });
+
+        // Only sum the parameters that some shard may have a gradient for: all parameters of dense layers, and the
+        // touched rows of sparse ones. Zeroing them in the shards leaves the shard gradients zero for the next batch.
+        let touched = union_touched_rows(shard_touched);
+        let ranges = gradient_ranges(model, &touched);
+        gradients.fill(0.0);
+        let mut total_loss = 0.0;
+        for (shard_gradients, shard_loss) in self.shard_gradients.iter_mut().zip(shard_losses) {
+            for range in &ranges {
+                for (gradient, shard_gradient) in gradients[range.clone()]
+                    .iter_mut()
+                    .zip(&mut shard_gradients[range.clone()])
+                {
+                    *gradient += *shard_gradient;
+                    *shard_gradient = 0.0;
+                }
+            }
+            total_loss += shard_loss;
+        }
+
+        let scale = 1.0 / batch_size as f32;
+        for range in &ranges {
+            for gradient in &mut gradients[range.clone()] {
+                *gradient *= scale;
+            }
+        }
+        let penalty = model.regularize(gradients);
+        model.touch_rows(touched);
+
+        if !state.is_empty() && !shards.is_empty() {
+            let mut mean_state = vec![0.0; state.len()];
+            for shard_state in &self.shard_states {
+                for (value, shard_value) in mean_state.iter_mut().zip(shard_state) {
+                    *value += shard_value;
+                }
+            }
+            let state_scale = 1.0 / shards.len() as f32;
+            for value in &mut mean_state {
+                *value *= state_scale;
+            }
+            model.set_state(&mean_state);
+        }
+
+        // Report the anomaly of the first shard, as a single pass over the whole batch would.
+        model.record_anomaly(shard_anomalies.into_iter().flatten().next());
+
+        total_loss * scale + penalty
+    }
+}
+
+/// The rows that every layer touched in any shard, or `None` for layers that have gradients for all parameters in some
+/// shard.
+fn union_touched_rows(shard_touched: Vec<Vec<Option<TouchedRows>>>) -> Vec<Option<TouchedRows>> {
+    let mut shards = shard_touched.into_iter();
+    let first = shards.next().unwrap_or_default();
+    shards.fold(first, |touched, shard| {
+        touched
+            .into_iter()
+            .zip(shard)
+            .map(|(rows, shard_rows)| Some(rows?.union(&shard_rows?)))
+            .collect()
+    })
+}
+
+/// The ranges of the gradient of `model` that the touched rows cover, and all parameters of layers without touched
+/// rows.
+fn gradient_ranges(model: &Sequential, touched: &[Option<TouchedRows>]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for (layer, touched) in model.layers().iter().zip(touched) {
+        let end = start + layer.parameters().len();
+        match touched {
+            Some(touched) => ranges.extend(
+                touched
+                    .ranges()
+                    .map(|range| start + range.start..start + range.end),
+            ),
+            None => ranges.push(start..end),
+        }
+        start = end;
+    }
+    ranges
+}
+
+/// Keeps exactly `count` buffers of `len` values each, reusing the ones allocated for earlier batches.
+fn resize_buffers(buffers: &mut Vec<Vec<f32>>, count: usize, len: usize) {
+    buffers.truncate(count);
+    for buffer in buffers.iter_mut() {
+        buffer.resize(len, 0.0);
+    }
+    buffers.resize_with(count, || vec![0.0; len]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        loss::MeanSquaredError,
+        nn::{BatchNorm, Dropout, Embedding, FullyConnectedLayer, Identity},
+        optim::Sgd,
+    };
+    use rand::{distributions::Uniform, rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn independent_of_thread_count() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let weights = Uniform::new(-1.0, 1.0);
+        let model = Sequential::new(vec![
+            Box::new(FullyConnectedLayer::new(
+                5,
+                16,
+                (&mut rng).sample_iter(weights),
+            )),
+            Box::new(BatchNorm::new_1d(16)),
+            Box::new(Dropout::new(16, 0.5, 0)),
+            Box::new(FullyConnectedLayer::with_activation(
+                16,
+                3,
+                (&mut rng).sample_iter(weights),
+                Identity,
+            )),
+        ]);
+        let batch_size = 37;
+        let inputs: Vec<f32> = (&mut rng)
+            .sample_iter(weights)
+            .take(5 * batch_size)
+            .collect();
+        let targets: Vec<f32> = (&mut rng)
+            .sample_iter(weights)
+            .take(3 * batch_size)
+            .collect();
+        let shard_size = NonZeroUsize::new(4).unwrap();
+
+        let run = |threads| {
+            let mut model = model.clone();
+            let mut parallel =
+                DataParallel::new(&model, NonZeroUsize::new(threads).unwrap(), shard_size)
+                    .with_seed(7);
+            let mut gradients = vec![0.0; model.parameter_count()];
+            let mut losses = Vec::new();
+            for _ in 0..2 {
+                let loss = parallel.gradients(
+                    &mut model,
+                    &inputs,
+                    &targets,
+                    &MeanSquaredError,
+                    &mut gradients,
+                );
+                losses.push(loss.to_bits());
+            }
+            (
+                losses,
+                gradients.iter().map(|g| g.to_bits()).collect::<Vec<_>>(),
+                model
+                    .state()
+                    .iter()
+                    .map(|s| s.to_bits())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        let expected = run(1);
+        assert_ne!(
+            expected.0[0], expected.0[1],
+            "dropout masks change every step"
+        );
+        assert_ne!(
+            expected.2,
+            model
+                .state()
+                .iter()
+                .map(|s| s.to_bits())
+                .collect::<Vec<_>>(),
+            "running statistics are updated"
+        );
+        for threads in [2, 3, 8] {
+            assert_eq!(run(threads), expected, "{threads} threads");
+        }
+    }
+
+    #[test]
+    fn batches_of_different_sizes() {
+        let mut rng = StdRng::from_seed([1u8; 32]);
+        let weights = Uniform::new(-1.0, 1.0);
+        let model = Sequential::new(vec![
+            Box::new(FullyConnectedLayer::new(
+                2,
+                4,
+                (&mut rng).sample_iter(weights),
+            )),
+            Box::new(BatchNorm::new_1d(4)),
+        ]);
+        let samples: Vec<f32> = (&mut rng).sample_iter(weights).take(4 * 40).collect();
+        let shard_size = NonZeroUsize::new(4).unwrap();
+        let threads = NonZeroUsize::new(8).unwrap();
+
+        let small = |parallel: &mut DataParallel, model: &mut Sequential| {
+            let mut gradients = vec![0.0; model.parameter_count()];
+            let loss = parallel.gradients(
+                model,
+                &samples[..2 * 6],
+                &samples[..4 * 6],
+                &MeanSquaredError,
+                &mut gradients,
+            );
+            (loss, gradients, model.state())
+        };
+
+        // A small batch after a large one gives the same result as on its own.
+        let mut fresh_model = model.clone();
+        let expected = small(
+            &mut DataParallel::new(&fresh_model, threads, shard_size),
+            &mut fresh_model,
+        );
+        let mut model = model;
+        let mut parallel = DataParallel::new(&model, threads, shard_size);
+        let state = model.state();
+        let mut gradients = vec![0.0; model.parameter_count()];
+        parallel.gradients(
+            &mut model,
+            &samples[..2 * 40],
+            &samples[..4 * 40],
+            &MeanSquaredError,
+            &mut gradients,
+        );
+        model.set_state(&state);
+        parallel.step = 0;
+        assert_eq!(small(&mut parallel, &mut model), expected);
+        assert_eq!(parallel.shard_gradients.len(), 2);
+    }
+
+    #[test]
+    fn sparse_gradients() {
+        let mut rng = StdRng::from_seed([2u8; 32]);
+        let weights = Uniform::new(-1.0, 1.0);
+        let mut model = Sequential::new(vec![
+            Box::new(Embedding::new(10, 2, (&mut rng).sample_iter(weights))),
+            Box::new(FullyConnectedLayer::with_activation(
+                2,
+                1,
+                (&mut rng).sample_iter(weights),
+                Identity,
+            )),
+        ]);
+        let inputs = [3.0, 7.0, 3.0, 1.0, 7.0];
+        let targets = [0.5; 5];
+
+        let mut expected = vec![0.0; model.parameter_count()];
+        model
+            .clone()
+            .gradients(&inputs, &targets, &MeanSquaredError, &mut expected);
+        let mut parallel = DataParallel::new(
+            &model,
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+        let mut gradients = vec![1.0; model.parameter_count()];
+        parallel.gradients(
+            &mut model,
+            &inputs,
+            &targets,
+            &MeanSquaredError,
+            &mut gradients,
+        );
+
+        for (gradient, expected) in gradients.iter().zip(&expected) {
+            assert!((gradient - expected / 5.0).abs() < 1e-6);
+        }
+        assert!(parallel
+            .shard_gradients
+            .iter()
+            .flatten()
+            .all(|&gradient| gradient == 0.0));
+
+        // The model learns which rows the replicas touched, so the update leaves all others alone.
+        let before = model.parameters();
+        gradients[0] = 1.0;
+        model.update(&mut Sgd::new(1.0), &gradients);
+        let after = model.parameters();
+        let changed: Vec<_> = (0..10)
+            .filter(|row| after[row * 2] != before[row * 2])
+            .collect();
+        assert_eq!(changed, [1, 3, 7]);
+    }
+
+    #[test]
+    #[should_panic(expected = "the batch is empty")]
+    fn empty_batch() {
+        let model = Sequential::new(vec![Box::new(FullyConnectedLayer::new(1, 1, [1.0, 0.0]))]);
+        let mut parallel =
+            DataParallel::new(&model, NonZeroUsize::MIN, NonZeroUsize::new(4).unwrap());
+        let mut gradients = vec![0.0; 2];
+        parallel.gradients(
+            &mut model.clone(),
+            &[],
+            &[],
+            &MeanSquaredError,
+            &mut gradients,
+        );
+    }
+}
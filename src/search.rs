@@ -0,0 +1,573 @@
+//! Hyperparameter search.
+//!
+//! A [`Search`] draws [`Hyperparameters`] from a [`SearchSpace`] and hands every [`Trial`] to an objective, which
+//! trains a model with them for the given number of epochs and returns a score, lower being better, such as the
+//! validation loss. Besides trying every combination or a random sample of them, the search can spend its budget
+//! adaptively with successive halving, which trains many configurations briefly and only the best of them for longer,
+//! and Hyperband, which runs successive halving with several trade-offs between the number of configurations and the
+//! epochs each starts with.
+//!
+//! Trials can run on several threads. Every configuration gets its own seed derived from the seed of the search, so
+//! the [`Leaderboard`] does not depend on the number of threads.
+
+use crate::{
+    nn::{Activation, Layer},
+    seed::Seeds,
+    Result,
+};
+use rand::{seq::SliceRandom, Rng};
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    num::NonZeroUsize,
+    path::Path,
+};
+
+/// The hyperparameters of one training run of a fully connected network.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hyperparameters {
+    pub learning_rate: f32,
+    /// The number of units of every hidden layer.
+    pub hidden_layers: Vec<usize>,
+    pub activation: Activation,
+    pub batch_size: usize,
+}
+
+impl Hyperparameters {
+    /// Fully connected hidden layers with the activation function, followed by a linear output layer. Append a
+    /// [`LogSoftmax`](crate::nn::LogSoftmax) for a classifier.
+    pub fn layers<I: IntoIterator<Item = f32>>(
+        &self,
+        input_count: usize,
+        output_count: usize,
+        initializer: I,
+    ) -> Vec<Box<dyn Layer>> {
+        let mut initializer = initializer.into_iter();
+        let mut layers = Vec::with_capacity(self.hidden_layers.len() + 1);
+        let mut inputs = input_count;
+        for &units in &self.hidden_layers {
+            layers.push(
+                self.activation
+                    .fully_connected(inputs, units, &mut initializer),
+            );
+            inputs = units;
+        }
+        layers.push(Activation::Identity.fully_connected(inputs, output_count, initializer));
+        layers
+    }
+}
+
+impl fmt::Display for Hyperparameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "learning rate {}, hidden layers {:?}, {}, batch size {}",
+            self.learning_rate, self.hidden_layers, self.activation, self.batch_size
+        )
+    }
+}
+
+/// The values to try for every hyperparameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchSpace {
+    pub learning_rates: Vec<f32>,
+    pub hidden_layers: Vec<Vec<usize>>,
+    pub activations: Vec<Activation>,
+    pub batch_sizes: Vec<usize>,
+}
+
+impl SearchSpace {
+    /// The number of combinations.
+    pub fn len(&self) -> usize {
+        self.learning_rates.len()
+            * self.hidden_layers.len()
+            * self.activations.len()
+            * self.batch_sizes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every combination, varying the batch size fastest and the learning rate slowest.
+    pub fn grid(&self) -> Vec<Hyperparameters> {
+        let mut grid = Vec::with_capacity(self.len());
+        for &learning_rate in &self.learning_rates {
+            for hidden_layers in &self.hidden_layers {
+                for &activation in &self.activations {
+                    for &batch_size in &self.batch_sizes {
+                        grid.push(Hyperparameters {
+                            learning_rate,
+                            hidden_layers: hidden_layers.clone(),
+                            activation,
+                            batch_size,
+                        });
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    /// A combination with every value chosen uniformly at random.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Hyperparameters {
+        assert!(!self.is_empty(), "search space is empty");
+        Hyperparameters {
+            learning_rate: *self.learning_rates.choose(rng).unwrap(),
+            hidden_layers: self.hidden_layers.choose(rng).unwrap().clone(),
+            activation: *self.activations.choose(rng).unwrap(),
+            batch_size: *self.batch_sizes.choose(rng).unwrap(),
+        }
+    }
+}
+
+/// A training run for the objective of a [`Search`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    /// The index of the trial in the search.
+    pub id: usize,
+    /// The index of the configuration. Successive halving trains a configuration again for longer under the same
+    /// index.
+    pub config: usize,
+    pub hyperparameters: Hyperparameters,
+    pub epochs: usize,
+    /// The seed to train with, the same for every trial of a configuration.
+    pub seed: u64,
+}
+
+/// A finished [`Trial`] and its score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub trial: Trial,
+    pub score: f32,
+}
+
+/// The trials of a search, ranked. Trials trained for more epochs rank first, since their scores are the more
+/// reliable, and trials with the same number of epochs rank by score. Displays as a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leaderboard {
+    entries: Vec<Entry>,
+}
+
+impl Leaderboard {
+    pub fn new(mut entries: Vec<Entry>) -> Self {
+        entries.sort_by(|a, b| {
+            b.trial
+                .epochs
+                .cmp(&a.trial.epochs)
+                .then(a.score.total_cmp(&b.score))
+                .then(a.trial.id.cmp(&b.trial.id))
+        });
+        Self { entries }
+    }
+
+    /// The entries, best first.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn best(&self) -> Option<&Entry> {
+        self.entries.first()
+    }
+
+    /// Writes the leaderboard as CSV with a header line. Hidden layer sizes are separated by spaces.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(
+            writer,
+            "rank,trial,config,epochs,score,learning_rate,hidden_layers,activation,batch_size"
+        )?;
+        for (rank, Entry { trial, score }) in self.entries.iter().enumerate() {
+            let hyperparameters = &trial.hyperparameters;
+            let hidden_layers: Vec<String> = hyperparameters
+                .hidden_layers
+                .iter()
+                .map(usize::to_string)
+                .collect();
+            writeln!(
+                writer,
+                "{},{},{},{},{score},{},{},{},{}",
+                rank + 1,
+                trial.id,
+                trial.config,
+                trial.epochs,
+                hyperparameters.learning_rate,
+                hidden_layers.join(" "),
+                hyperparameters.activation,
+                hyperparameters.batch_size
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_csv(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Leaderboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>4}  {:>5}  {:>6}  {:>10}  Hyperparameters",
+            "Rank", "Trial", "Epochs", "Score"
+        )?;
+        for (rank, Entry { trial, score }) in self.entries.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>4}  {:>5}  {:>6}  {score:>10.6}  {}",
+                rank + 1,
+                trial.id,
+                trial.epochs,
+                trial.hyperparameters
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs trials with an objective, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct Search {
+    seed: u64,
+    threads: usize,
+}
+
+impl Search {
+    /// Searches on one thread, deriving the choice of configurations and the seeds of the trials from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self { seed, threads: 1 }
+    }
+
+    /// Runs up to `threads` trials at a time. Objectives that train with a [`Trainer`](crate::train::Trainer) should
+    /// then give it fewer threads, for example with [`Trainer::with_parallelism`](crate::train::Trainer).
+    pub fn with_threads(mut self, threads: NonZeroUsize) -> Self {
+        self.threads = threads.get();
+        self
+    }
+
+    /// Trains every combination of the search space for `epochs` epochs.
+    pub fn grid<F>(&self, space: &SearchSpace, epochs: usize, objective: F) -> Result<Leaderboard>
+    where
+        F: Fn(&Trial) -> Result<f32> + Sync,
+    {
+        let configs = space.grid().into_iter().enumerate().collect();
+        let trials = self.trials(configs, epochs, 0);
+        Ok(Leaderboard::new(self.run(trials, &objective)?))
+    }
+
+    /// Trains `count` random combinations for `epochs` epochs.
+    pub fn random<F>(
+        &self,
+        space: &SearchSpace,
+        count: usize,
+        epochs: usize,
+        objective: F,
+    ) -> Result<Leaderboard>
+    where
+        F: Fn(&Trial) -> Result<f32> + Sync,
+    {
+        let configs = self.sample(space, 0, count);
+        let trials = self.trials(configs, epochs, 0);
+        Ok(Leaderboard::new(self.run(trials, &objective)?))
+    }
+
+    /// Trains `count` random combinations for `min_epochs` epochs, then keeps the best `1 / eta` of them and trains
+    /// those again for `eta` times as many epochs, and so on until one is left or they reach `max_epochs`.
+    pub fn successive_halving<F>(
+        &self,
+        space: &SearchSpace,
+        count: usize,
+        min_epochs: usize,
+        max_epochs: usize,
+        eta: usize,
+        objective: F,
+    ) -> Result<Leaderboard>
+    where
+        F: Fn(&Trial) -> Result<f32> + Sync,
+    {
+        assert!(eta >= 2, "eta must be at least 2");
+        let configs = self.sample(space, 0, count);
+        let mut entries = Vec::new();
+        self.halve(
+            configs,
+            min_epochs,
+            max_epochs,
+            eta,
+            &objective,
+            &mut entries,
+        )?;
+        Ok(Leaderboard::new(entries))
+    }
+
+    /// Runs successive halving in brackets from many configurations starting with few epochs to few configurations
+    /// starting with `max_epochs`, so that at least one bracket suits how early the scores become telling.
+    pub fn hyperband<F>(
+        &self,
+        space: &SearchSpace,
+        max_epochs: usize,
+        eta: usize,
+        objective: F,
+    ) -> Result<Leaderboard>
+    where
+        F: Fn(&Trial) -> Result<f32> + Sync,
+    {
+        assert!(eta >= 2, "eta must be at least 2");
+        assert!(max_epochs > 0, "max epochs must be positive");
+
+        let brackets = max_epochs.ilog(eta) as usize + 1;
+        let mut entries = Vec::new();
+        let mut config_count = 0;
+        for bracket in (0..brackets).rev() {
+            let scale = eta.pow(bracket as u32);
+            let count = (brackets * scale).div_ceil(bracket + 1);
+            let configs = self.sample(space, config_count, count);
+            config_count += count;
+            self.halve(
+                configs,
+                max_epochs / scale,
+                max_epochs,
+                eta,
+                &objective,
+                &mut entries,
+            )?;
+        }
+        Ok(Leaderboard::new(entries))
+    }
+
+    /// Random configurations numbered from `first`. Every configuration is drawn from its own stream, so the
+    /// configurations do not depend on how many are drawn.
+    fn sample(
+        &self,
+        space: &SearchSpace,
+        first: usize,
+        count: usize,
+    ) -> Vec<(usize, Hyperparameters)> {
+        let mut seeds = Seeds::new(self.seed);
+        (first..first + count)
+            .map(|config| {
+                let mut rng = seeds.rng(&format!("search config {config}"));
+                (config, space.sample(&mut rng))
+            })
+            .collect()
+    }
+
+    fn trials(
+        &self,
+        configs: Vec<(usize, Hyperparameters)>,
+        epochs: usize,
+        first_id: usize,
+    ) -> Vec<Trial> {
+        let mut seeds = Seeds::new(self.seed);
+        configs
+            .into_iter()
+            .enumerate()
+            .map(|(index, (config, hyperparameters))| Trial {
+                id: first_id + index,
+                config,
+                hyperparameters,
+                epochs,
+                seed: seeds.seed(&format!("trial config {config}")),
+            })
+            .collect()
+    }
+
+    /// Successive halving of `configs`, adding every trial to `entries`.
+    fn halve<F>(
+        &self,
+        mut configs: Vec<(usize, Hyperparameters)>,
+        min_epochs: usize,
+        max_epochs: usize,
+        eta: usize,
+        objective: &F,
+        entries: &mut Vec<Entry>,
+    ) -> Result<()>
+    where
+        F: Fn(&Trial) -> Result<f32> + Sync,
+    {
+        let mut epochs = min_epochs.clamp(1, max_epochs.max(1));
+        loop {
+            let trials = self.trials(configs, epochs, entries.len());
+            let mut results = self.run(trials, objective)?;
+            entries.extend(results.iter().cloned());
+            if results.len() <= 1 || epochs >= max_epochs {
+                return Ok(());
+            }
+
+            let keep = (results.len() / eta).max(1);
+            results.sort_by(|a, b| a.score.total_cmp(&b.score));
+            configs = results
+                .into_iter()
+                .take(keep)
+                .map(|entry| (entry.trial.config, entry.trial.hyperparameters))
+                .collect();
+            epochs = (epochs * eta).min(max_epochs);
+        }
+    }
+
+    /// Runs the trials, distributing them round-robin over the threads, and returns their entries in order.
+    fn run<F>(&self, trials: Vec<Trial>, objective: &F) -> Result<Vec<Entry>>
+    where
+        F: Fn(&Trial) -> Result<f32> + Sync,
+    {
+        let threads = self.threads.min(trials.len()).max(1);
+        // Errors are not `Send`, so they cross threads as messages.
+        let scores: Vec<std::result::Result<f32, String>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|thread| {
+                    let trials = &trials;
+                    scope.spawn(move || {
+                        trials
+                            .iter()
+                            .skip(thread)
+                            .step_by(threads)
+                            .map(|trial| objective(trial).map_err(|error| error.to_string()))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            let mut results: Vec<_> = handles
+                .into_iter()
+                .map(|handle| handle.join().expect("trial panicked").into_iter())
+                .collect();
+            (0..trials.len())
+                .map(|index| results[index % threads].next().unwrap())
+                .collect()
+        });
+
+        trials
+            .into_iter()
+            .zip(scores)
+            .map(|(trial, score)| {
+                let score = score.map_err(|error| format!("trial {}: {error}", trial.id))?;
+                Ok(Entry { trial, score })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::Sequential;
+
+    fn space() -> SearchSpace {
+        SearchSpace {
+            learning_rates: vec![0.01, 0.1, 1.0],
+            hidden_layers: vec![vec![8], vec![16, 8]],
+            activations: vec![Activation::ReLU, Activation::Tanh],
+            batch_sizes: vec![32],
+        }
+    }
+
+    /// Scores learning rates by their distance from 0.1 on a log scale, prefers ReLU and improves with epochs.
+    fn objective(trial: &Trial) -> Result<f32> {
+        let hyperparameters = &trial.hyperparameters;
+        let activation = (hyperparameters.activation != Activation::ReLU) as u8 as f32;
+        Ok((hyperparameters.learning_rate.log10() + 1.0).abs()
+            + activation
+            + hyperparameters.hidden_layers.len() as f32 / 10.0
+            + 1.0 / trial.epochs as f32)
+    }
+
+    #[test]
+    fn grid() {
+        let space = space();
+        assert_eq!((space.len(), space.grid().len()), (12, 12));
+        let leaderboard = Search::new(0).grid(&space, 2, objective).unwrap();
+        assert_eq!(leaderboard.entries().len(), 12);
+        let best = leaderboard.best().unwrap();
+        assert_eq!(
+            best.trial.hyperparameters,
+            Hyperparameters {
+                learning_rate: 0.1,
+                hidden_layers: vec![8],
+                activation: Activation::ReLU,
+                batch_size: 32,
+            }
+        );
+        let scores: Vec<f32> = leaderboard.entries().iter().map(|e| e.score).collect();
+        assert!(scores.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let mut csv = Vec::new();
+        leaderboard.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 13);
+        assert!(csv.lines().nth(1).unwrap().ends_with(",0.1,8,relu,32"));
+        assert_eq!(leaderboard.to_string().lines().count(), 13);
+
+        let model = Sequential::new(best.trial.hyperparameters.layers(
+            4,
+            3,
+            std::iter::repeat(0.0),
+        ));
+        assert_eq!(model.parameter_count(), 5 * 8 + 9 * 3);
+    }
+
+    #[test]
+    fn random() {
+        let space = space();
+        let leaderboard = Search::new(1).random(&space, 5, 1, objective).unwrap();
+        assert_eq!(leaderboard.entries().len(), 5);
+
+        // The result depends on the seed but not on the number of threads.
+        let threaded = Search::new(1)
+            .with_threads(NonZeroUsize::new(3).unwrap())
+            .random(&space, 5, 1, objective)
+            .unwrap();
+        assert_eq!(threaded, leaderboard);
+        assert_ne!(
+            Search::new(2).random(&space, 5, 1, objective).unwrap(),
+            leaderboard
+        );
+
+        let error = Search::new(1)
+            .random(&space, 5, 1, |trial| {
+                if trial.id == 3 {
+                    Err("diverged".into())
+                } else {
+                    objective(trial)
+                }
+            })
+            .unwrap_err();
+        assert_eq!(error.to_string(), "trial 3: diverged");
+    }
+
+    #[test]
+    fn successive_halving() {
+        let space = space();
+        let leaderboard = Search::new(0)
+            .successive_halving(&space, 9, 1, 9, 3, objective)
+            .unwrap();
+        let epochs: Vec<usize> = leaderboard
+            .entries()
+            .iter()
+            .map(|e| e.trial.epochs)
+            .collect();
+        assert_eq!(epochs, [[9; 1].as_slice(), &[3; 3], &[1; 9]].concat());
+
+        // The configuration that survives is the best of the first rung, trained with the same seed.
+        let best = leaderboard.best().unwrap();
+        let first_rung = leaderboard
+            .entries()
+            .iter()
+            .find(|entry| entry.trial.epochs == 1)
+            .unwrap();
+        assert_eq!(best.trial.config, first_rung.trial.config);
+        assert_eq!(best.trial.seed, first_rung.trial.seed);
+
+        let leaderboard = Search::new(0).hyperband(&space, 9, 3, objective).unwrap();
+        // Brackets of 9 configurations from 1 epoch, 5 from 3 epochs and 3 at 9 epochs.
+        assert_eq!(leaderboard.entries().len(), (9 + 3 + 1) + (5 + 1) + 3);
+        let configs = leaderboard
+            .entries()
+            .iter()
+            .map(|entry| entry.trial.config)
+            .max()
+            .unwrap();
+        assert_eq!(configs + 1, 9 + 5 + 3);
+    }
+}
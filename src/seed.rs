@@ -0,0 +1,128 @@
+//! Reproducible randomness from a single seed.
+//!
+//! Every use of randomness in a run, like initialization, shuffling, dropout or augmentation, draws from its own named
+//! stream, whose seed is derived from the master seed and the name alone. Streams are therefore independent of each
+//! other and of the order in which they are requested: adding a stream, or drawing more from one, leaves the others
+//! unchanged. Together with the per-shard seeding of [`DataParallel`](crate::parallel::DataParallel), which makes
+//! gradients independent of the number of threads, a run is reproduced bit for bit from its master seed.
+//!
+//! [`Seeds`] remembers the streams it handed out, and a [`Checkpoint`](crate::checkpoint::Checkpoint) can record them
+//! so that the seeds of a run can be audited later.
+
+use crate::{nn::derive_seed, Result};
+use rand::{rngs::StdRng, SeedableRng};
+use std::collections::BTreeMap;
+
+/// Splits a master seed into named streams, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seeds {
+    master: u64,
+    /// The seed of every stream handed out so far, by name.
+    streams: BTreeMap<String, u64>,
+}
+
+impl Seeds {
+    /// The stream for initializing parameters.
+    pub const INITIALIZATION: &'static str = "initialization";
+    /// The stream for generating or splitting data.
+    pub const DATA: &'static str = "data";
+    /// The stream for shuffling the training data every epoch, see [`DataLoader::with_shuffle`](crate::data::DataLoader).
+    pub const SHUFFLE: &'static str = "shuffle";
+    /// The stream for layers that use randomness while training, like dropout, see
+    /// [`Trainer::with_seed`](crate::train::Trainer::with_seed).
+    pub const DROPOUT: &'static str = "dropout";
+    /// The stream for random data augmentation.
+    pub const AUGMENTATION: &'static str = "augmentation";
+    /// The stream for assigning samples to cross-validation folds, see [`k_fold`](crate::data::k_fold).
+    pub const FOLDS: &'static str = "folds";
+
+    pub fn new(master: u64) -> Self {
+        Self {
+            master,
+            streams: BTreeMap::new(),
+        }
+    }
+
+    pub fn master(&self) -> u64 {
+        self.master
+    }
+
+    /// The seed of the stream `name`, which is recorded as used.
+    pub fn seed(&mut self, name: &str) -> u64 {
+        let seed = Self::derive(self.master, name);
+        self.streams.insert(name.to_owned(), seed);
+        seed
+    }
+
+    /// A generator for the stream `name`, which is recorded as used.
+    pub fn rng(&mut self, name: &str) -> StdRng {
+        StdRng::seed_from_u64(self.seed(name))
+    }
+
+    /// The streams handed out so far and their seeds, ordered by name.
+    pub fn streams(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.streams
+            .iter()
+            .map(|(name, &seed)| (name.as_str(), seed))
+    }
+
+    /// Recreates seeds that handed out `streams`, checking that they were derived from `master`.
+    pub fn with_streams<I, S>(master: u64, streams: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (S, u64)>,
+        S: Into<String>,
+    {
+        let mut seeds = Self::new(master);
+        for (name, seed) in streams {
+            let name = name.into();
+            if Self::derive(master, &name) != seed {
+                return Err(format!(
+                    "seed of stream {name:?} was not derived from master seed {master}"
+                )
+                .into());
+            }
+            seeds.streams.insert(name, seed);
+        }
+        Ok(seeds)
+    }
+
+    /// Hashes the name with 64-bit FNV-1a, and derives the seed of the stream from the master seed and the hash.
+    fn derive(master: u64, name: &str) -> u64 {
+        let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        derive_seed(master, hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn streams() {
+        let mut seeds = Seeds::new(42);
+        let shuffle = seeds.seed(Seeds::SHUFFLE);
+        let initialization = seeds.seed(Seeds::INITIALIZATION);
+        assert_ne!(shuffle, initialization);
+
+        // Streams do not depend on the order in which they are requested, nor on the other streams.
+        let mut other = Seeds::new(42);
+        assert_eq!(other.seed(Seeds::INITIALIZATION), initialization);
+        let draw = |rng: &mut StdRng| -> Vec<u32> { (0..4).map(|_| rng.gen()).collect() };
+        assert_eq!(
+            draw(&mut seeds.rng(Seeds::DROPOUT)),
+            draw(&mut other.rng(Seeds::DROPOUT))
+        );
+        assert_ne!(Seeds::new(43).seed(Seeds::SHUFFLE), shuffle);
+
+        let streams: Vec<(&str, u64)> = seeds.streams().collect();
+        assert_eq!(
+            streams.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            [Seeds::DROPOUT, Seeds::INITIALIZATION, Seeds::SHUFFLE]
+        );
+        assert_eq!(Seeds::with_streams(42, streams.clone()).unwrap(), seeds);
+        assert!(Seeds::with_streams(7, streams).is_err());
+    }
+}
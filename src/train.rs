@@ -0,0 +1,860 @@
+//! A training loop with hooks.
+//!
+//! A [`Trainer`] owns a model, a loss, an optimizer and optionally a learning rate [`Scheduler`], and trains on the
+//! batches of a [`DataLoader`] for a number of epochs. After every batch and every epoch it calls the
+//! [`Callback`]s passed to [`Trainer::fit`], any of which can stop training. The callbacks stay with the caller, so
+//! they can be inspected afterwards, for example to restore the best model from a [`ModelCheckpoint`].
+//!
+//! With [`Trainer::with_anomaly_detection`], training stops with a [`TrainingAnomaly`] at the first step whose forward
+//! or backward pass produces a value that is not finite, before the optimizer applies it.
+
+use crate::{
+    anomaly::TrainingAnomaly,
+    checkpoint::Checkpoint,
+    data::DataLoader,
+    diagnostics::Diagnostics,
+    loss::Loss,
+    nn::{argmax, Sequential},
+    optim::{global_norm, GradientClipping, Optimizer, Scheduler},
+    parallel::DataParallel,
+    seed::Seeds,
+    Result,
+};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    num::NonZeroUsize,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+};
+
+const SHARD_SIZE: usize = 16;
+
+/// What a [`Callback`] learns at the end of a batch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchStats {
+    /// The number of batches trained on before this one, over all calls to [`Trainer::fit`].
+    pub step: usize,
+    pub epoch: usize,
+    pub batch: usize,
+    pub sample_count: usize,
+    /// The loss averaged over the samples of the batch, plus the regularization penalties of the layers.
+    pub loss: f32,
+    pub learning_rate: f32,
+    /// The L2 norm of the gradient before clipping.
+    pub gradient_norm: f32,
+    /// Per-layer statistics of the step, on the steps the trainer diagnoses.
+    pub diagnostics: Option<Diagnostics>,
+}
+
+/// What a [`Callback`] learns at the end of an epoch. [`Trainer::fit`] returns these for every epoch it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpochStats {
+    pub epoch: usize,
+    /// The loss averaged over the training samples, each measured when its batch was trained on, including the
+    /// regularization penalties of the layers.
+    pub train_loss: f32,
+    /// The loss averaged over the validation samples in evaluation mode at the end of the epoch. Unlike
+    /// [`EpochStats::train_loss`] it leaves out regularization penalties, so with regularization the two are not
+    /// directly comparable.
+    pub validation_loss: Option<f32>,
+    /// The fraction of validation samples classified correctly, if the trainer tracks accuracy.
+    pub validation_accuracy: Option<f32>,
+    pub learning_rate: f32,
+}
+
+impl EpochStats {
+    /// The validation loss, or the training loss if there is no validation set. Callbacks that track the best epoch
+    /// go by this.
+    pub fn monitored_loss(&self) -> f32 {
+        self.validation_loss.unwrap_or(self.train_loss)
+    }
+}
+
+/// Hooks into [`Trainer::fit`]. Returning [`ControlFlow::Break`] stops training once all callbacks have seen the
+/// current batch or epoch, and returning an error stops it immediately.
+pub trait Callback {
+    fn on_batch_end(
+        &mut self,
+        _stats: &BatchStats,
+        _model: &Sequential,
+    ) -> Result<ControlFlow<()>> {
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn on_epoch_end(
+        &mut self,
+        _stats: &EpochStats,
+        _model: &Sequential,
+    ) -> Result<ControlFlow<()>> {
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+pub struct Trainer<L, O> {
+    model: Sequential,
+    loss: L,
+    optimizer: O,
+    scheduler: Option<Box<dyn Scheduler>>,
+    parallel: DataParallel,
+    gradients: Vec<f32>,
+    step: usize,
+    /// The number of epochs trained over all calls to [`Trainer::fit`], from which the scheduler counts.
+    epochs_trained: usize,
+    track_accuracy: bool,
+    clipping: Option<GradientClipping>,
+    diagnostics_interval: Option<usize>,
+    anomaly_dump: Option<PathBuf>,
+}
+
+impl<L, O> Trainer<L, O>
+where
+    L: Loss,
+    O: Optimizer,
+{
+    /// Computes gradients with a [`DataParallel`] on all available cores, in shards of 16 samples. Layers that
+    /// normalize over the batch, like [`BatchNorm`](crate::nn::BatchNorm), normalize every shard on its own; use
+    /// [`Trainer::with_parallelism`] to choose the shard size.
+    pub fn new(model: Sequential, loss: L, optimizer: O) -> Self {
+        let parallel = DataParallel::with_available_parallelism(
+            &model,
+            NonZeroUsize::new(SHARD_SIZE).unwrap(),
+        );
+        Self {
+            gradients: vec![0.0; model.parameter_count()],
+            model,
+            loss,
+            optimizer,
+            scheduler: None,
+            parallel,
+            step: 0,
+            epochs_trained: 0,
+            track_accuracy: false,
+            clipping: None,
+            diagnostics_interval: None,
+            anomaly_dump: None,
+        }
+    }
+
+    /// Sets the learning rate of the optimizer at the start of every epoch, counting the epochs over all calls to
+    /// [`Trainer::fit`], so that a schedule continues where the last call left off.
+    pub fn with_scheduler<S: Scheduler + 'static>(mut self, scheduler: S) -> Self {
+        self.scheduler = Some(Box::new(scheduler));
+        self
+    }
+
+    /// Seeds the layers that use randomness while training, like dropout. Together with a seeded [`DataLoader`], this
+    /// makes training reproducible; see [`Seeds`] for deriving both from one seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.parallel = self.parallel.with_seed(seed);
+        self
+    }
+
+    /// Computes gradients with `parallel`, for example to choose the number of threads or the seed.
+    pub fn with_parallelism(mut self, parallel: DataParallel) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// Also measures the validation accuracy of a classifier, taking the class with the largest output as the
+    /// prediction and the class with the largest target as the label.
+    pub fn with_accuracy(mut self) -> Self {
+        self.track_accuracy = true;
+        self
+    }
+
+    /// Clips the gradients before every optimizer step. Panics unless the limit is positive and finite.
+    pub fn with_gradient_clipping(mut self, clipping: GradientClipping) -> Self {
+        clipping.assert_valid();
+        self.clipping = Some(clipping);
+        self
+    }
+
+    /// Computes [`Diagnostics`] every `steps` steps, starting with the first, and passes them to the callbacks in
+    /// [`BatchStats::diagnostics`].
+    pub fn with_diagnostics(mut self, steps: usize) -> Self {
+        assert!(steps > 0, "diagnostics interval must be positive");
+        self.diagnostics_interval = Some(steps);
+        self
+    }
+
+    /// Checks every forward and backward pass for values that are not finite, and stops training with a
+    /// [`TrainingAnomaly`] at the first, see [`anomaly`](crate::anomaly).
+    pub fn with_anomaly_detection(mut self) -> Self {
+        self.model.set_anomaly_detection(true);
+        self
+    }
+
+    /// Detects anomalies like [`Trainer::with_anomaly_detection`], and writes the offending batch to `batch.csv` and
+    /// the parameters and state before the step to `parameters.ckpt` in the directory `step-<step>` below
+    /// `directory`. The checkpoint can be loaded with [`Checkpoint::load`] to reproduce the step.
+    pub fn with_anomaly_dump<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.anomaly_dump = Some(directory.into());
+        self.with_anomaly_detection()
+    }
+
+    pub fn model(&self) -> &Sequential {
+        &self.model
+    }
+
+    pub fn model_mut(&mut self) -> &mut Sequential {
+        &mut self.model
+    }
+
+    pub fn into_model(self) -> Sequential {
+        self.model
+    }
+
+    pub fn optimizer(&self) -> &O {
+        &self.optimizer
+    }
+
+    /// Trains for up to `epochs` epochs, reshuffling `train` at the start of every epoch and evaluating on
+    /// `validation`, if given, at the end. Returns the statistics of every epoch that ran.
+    pub fn fit(
+        &mut self,
+        train: &mut DataLoader,
+        validation: Option<&DataLoader>,
+        epochs: usize,
+        callbacks: &mut [&mut dyn Callback],
+    ) -> Result<Vec<EpochStats>> {
+        assert_eq!(train.input_count(), self.model.input_count());
+
+        let mut history = Vec::with_capacity(epochs);
+        for epoch in 0..epochs {
+            if let Some(scheduler) = &self.scheduler {
+                self.optimizer
+                    .set_learning_rate(scheduler.learning_rate(self.epochs_trained));
+            }
+            self.epochs_trained += 1;
+            self.model.train();
+            train.shuffle();
+
+            let mut stop = false;
+            let mut total_loss = 0.0;
+            let mut sample_count = 0;
+            for (batch, (inputs, targets)) in train.batches().enumerate() {
+                // Forget anomalies found outside of training steps, such as in validation.
+                self.model.take_anomaly();
+                // The step updates the state of the model even if it finds an anomaly, so keep the state it started
+                // from for the dump.
+                let before = self
+                    .anomaly_dump
+                    .is_some()
+                    .then(|| Checkpoint::of(&self.model));
+                let loss = self.parallel.gradients(
+                    &mut self.model,
+                    &inputs,
+                    &targets,
+                    &self.loss,
+                    &mut self.gradients,
+                );
+                if let Some(anomaly) = self.model.take_anomaly() {
+                    let dump = match (&self.anomaly_dump, before) {
+                        (Some(directory), Some(before)) => {
+                            let directory = directory.join(format!("step-{}", self.step));
+                            dump(&directory, &before, &inputs, train.input_count(), &targets)?;
+                            Some(directory)
+                        }
+                        _ => None,
+                    };
+                    return Err(TrainingAnomaly {
+                        anomaly,
+                        step: self.step,
+                        epoch,
+                        batch,
+                        dump,
+                    }
+                    .into());
+                }
+                let gradient_norm = match &self.clipping {
+                    Some(clipping) => clipping.apply(&mut self.gradients),
+                    None => global_norm(&self.gradients),
+                };
+                let previous_parameters = self
+                    .diagnostics_interval
+                    .is_some_and(|interval| self.step.is_multiple_of(interval))
+                    .then(|| self.model.parameters());
+                self.model.update(&mut self.optimizer, &self.gradients);
+                let diagnostics = previous_parameters.map(|previous_parameters| {
+                    Diagnostics::new(
+                        &mut self.model,
+                        &inputs,
+                        &previous_parameters,
+                        &self.gradients,
+                    )
+                });
+
+                let stats = BatchStats {
+                    step: self.step,
+                    epoch,
+                    batch,
+                    sample_count: inputs.len() / train.input_count(),
+                    loss,
+                    learning_rate: self.optimizer.learning_rate(),
+                    gradient_norm,
+                    diagnostics,
+                };
+                self.step += 1;
+                total_loss += loss * stats.sample_count as f32;
+                sample_count += stats.sample_count;
+                for callback in callbacks.iter_mut() {
+                    stop |= callback.on_batch_end(&stats, &self.model)?.is_break();
+                }
+                if stop {
+                    break;
+                }
+            }
+
+            let validation = validation.map(|validation| self.validate(validation));
+            let stats = EpochStats {
+                epoch,
+                train_loss: total_loss / sample_count.max(1) as f32,
+                validation_loss: validation.map(|(loss, _)| loss),
+                validation_accuracy: validation.and_then(|(_, accuracy)| accuracy),
+                learning_rate: self.optimizer.learning_rate(),
+            };
+            for callback in callbacks.iter_mut() {
+                stop |= callback.on_epoch_end(&stats, &self.model)?.is_break();
+            }
+            history.push(stats);
+            if stop {
+                break;
+            }
+        }
+
+        Ok(history)
+    }
+
+    /// The loss averaged over the samples of `data`, in evaluation mode.
+    pub fn evaluate(&mut self, data: &DataLoader) -> f32 {
+        self.validate(data).0
+    }
+
+    /// The mean loss and, if tracked, the accuracy on `data`.
+    fn validate(&mut self, data: &DataLoader) -> (f32, Option<f32>) {
+        let classes = self.model.output_count();
+        let mut total_loss = 0.0;
+        let mut correct = 0;
+        for (inputs, targets) in data.batches() {
+            let outputs = self.model.forward_eval(&inputs);
+            let mut gradients = vec![0.0; outputs.len()];
+            total_loss += self.loss.loss(&outputs, &targets, &mut gradients);
+            correct += outputs
+                .chunks_exact(classes)
+                .zip(targets.chunks_exact(classes))
+                .filter(|(outputs, targets)| argmax(outputs) == argmax(targets))
+                .count();
+        }
+
+        let len = data.len().max(1) as f32;
+        let accuracy = self.track_accuracy.then(|| correct as f32 / len);
+        (total_loss / len, accuracy)
+    }
+}
+
+/// Writes the batch, one sample per line with the inputs followed by the targets, and the checkpoint of the model
+/// taken before the step to `directory`.
+fn dump(
+    directory: &Path,
+    checkpoint: &Checkpoint,
+    inputs: &[f32],
+    input_count: usize,
+    targets: &[f32],
+) -> Result<()> {
+    fs::create_dir_all(directory)?;
+    checkpoint.save(directory.join("parameters.ckpt"))?;
+
+    let target_count = targets.len() / (inputs.len() / input_count).max(1);
+    let mut writer = BufWriter::new(File::create(directory.join("batch.csv"))?);
+    let header: Vec<String> = (0..input_count)
+        .map(|index| format!("input_{index}"))
+        .chain((0..target_count).map(|index| format!("target_{index}")))
+        .collect();
+    writeln!(writer, "{}", header.join(","))?;
+    for (inputs, targets) in inputs
+        .chunks_exact(input_count)
+        .zip(targets.chunks_exact(target_count))
+    {
+        let values: Vec<String> = inputs.iter().chain(targets).map(f32::to_string).collect();
+        writeln!(writer, "{}", values.join(","))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Stops training once the monitored loss has not improved by more than a margin for a number of epochs.
+#[derive(Debug, Clone)]
+pub struct EarlyStopping {
+    patience: usize,
+    min_delta: f32,
+    best_loss: f32,
+    best_epoch: Option<usize>,
+    epochs_without_improvement: usize,
+}
+
+impl EarlyStopping {
+    /// Stops after `patience` epochs in a row without any improvement.
+    pub fn new(patience: usize) -> Self {
+        Self {
+            patience,
+            min_delta: 0.0,
+            best_loss: f32::INFINITY,
+            best_epoch: None,
+            epochs_without_improvement: 0,
+        }
+    }
+
+    /// Only counts a loss as an improvement if it is lower than the best so far by more than `min_delta`.
+    pub fn with_min_delta(mut self, min_delta: f32) -> Self {
+        self.min_delta = min_delta;
+        self
+    }
+
+    pub fn best_epoch(&self) -> Option<usize> {
+        self.best_epoch
+    }
+}
+
+impl Callback for EarlyStopping {
+    fn on_epoch_end(&mut self, stats: &EpochStats, _model: &Sequential) -> Result<ControlFlow<()>> {
+        let loss = stats.monitored_loss();
+        if loss < self.best_loss - self.min_delta {
+            self.best_loss = loss;
+            self.best_epoch = Some(stats.epoch);
+            self.epochs_without_improvement = 0;
+        } else {
+            self.epochs_without_improvement += 1;
+        }
+
+        Ok(if self.epochs_without_improvement >= self.patience {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        })
+    }
+}
+
+/// Keeps a [`Checkpoint`] of the model after the epoch with the lowest monitored loss, and optionally saves it to a
+/// file every time it improves.
+#[derive(Debug, Clone, Default)]
+pub struct ModelCheckpoint {
+    path: Option<PathBuf>,
+    seeds: Option<Seeds>,
+    best_loss: Option<f32>,
+    best: Option<(usize, Checkpoint)>,
+}
+
+impl ModelCheckpoint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Records `seeds` in every checkpoint. This takes a copy of the streams handed out so far, so call it once the run
+    /// has requested all of its streams; streams requested later, like [`Seeds::AUGMENTATION`] during training, are
+    /// missing from the checkpoints.
+    pub fn with_seeds(mut self, seeds: &Seeds) -> Self {
+        self.seeds = Some(seeds.clone());
+        self
+    }
+
+    pub fn best_epoch(&self) -> Option<usize> {
+        self.best.as_ref().map(|(epoch, _)| *epoch)
+    }
+
+    pub fn best(&self) -> Option<&Checkpoint> {
+        self.best.as_ref().map(|(_, checkpoint)| checkpoint)
+    }
+}
+
+impl Callback for ModelCheckpoint {
+    fn on_epoch_end(&mut self, stats: &EpochStats, model: &Sequential) -> Result<ControlFlow<()>> {
+        let loss = stats.monitored_loss();
+        if self.best_loss.is_none_or(|best| loss < best) {
+            let mut checkpoint = Checkpoint::of(model);
+            checkpoint.seeds = self.seeds.clone();
+            if let Some(path) = &self.path {
+                checkpoint.save(path)?;
+            }
+            self.best_loss = Some(loss);
+            self.best = Some((stats.epoch, checkpoint));
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+/// Prints the statistics of every epoch, and of every `batch_interval`th batch if set.
+#[derive(Debug, Clone, Default)]
+pub struct ProgressLogger {
+    batch_interval: Option<usize>,
+}
+
+impl ProgressLogger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_batch_interval(mut self, batches: usize) -> Self {
+        assert!(batches > 0, "batch interval must be positive");
+        self.batch_interval = Some(batches);
+        self
+    }
+}
+
+impl Callback for ProgressLogger {
+    fn on_batch_end(&mut self, stats: &BatchStats, _model: &Sequential) -> Result<ControlFlow<()>> {
+        if self
+            .batch_interval
+            .is_some_and(|interval| stats.batch.is_multiple_of(interval))
+        {
+            println!(
+                "epoch: {}, batch: {:5}, loss: {:8.5}, gradient norm: {:8.5}",
+                stats.epoch, stats.batch, stats.loss, stats.gradient_norm
+            );
+        }
+        if let Some(diagnostics) = &stats.diagnostics {
+            print!("{diagnostics}");
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn on_epoch_end(&mut self, stats: &EpochStats, _model: &Sequential) -> Result<ControlFlow<()>> {
+        print!("epoch: {}, loss: {:8.5}", stats.epoch, stats.train_loss);
+        if let Some(loss) = stats.validation_loss {
+            print!(", validation loss: {loss:8.5}");
+        }
+        if let Some(accuracy) = stats.validation_accuracy {
+            print!(", validation accuracy: {accuracy:.4}");
+        }
+        println!(", learning rate: {}", stats.learning_rate);
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        loss::MeanSquaredError,
+        nn::{BatchNorm, FullyConnectedLayer, Identity},
+        optim::{Sgd, StepDecay},
+    };
+    use rand::{distributions::Uniform, rngs::StdRng, Rng, SeedableRng};
+
+    fn sine(rng: &mut StdRng, count: usize, batch_size: usize) -> DataLoader {
+        let (inputs, targets) = (0..count).map(|_| crate::data::sample_sine(rng)).unzip();
+        DataLoader::new(inputs, 1, targets, 1, batch_size)
+    }
+
+    fn trainer(rng: &mut StdRng, learning_rate: f32) -> Trainer<MeanSquaredError, Sgd> {
+        let weights = Uniform::new(-0.5, 0.5);
+        let model = Sequential::new(vec![
+            Box::new(FullyConnectedLayer::new(1, 8, rng.sample_iter(weights))),
+            Box::new(FullyConnectedLayer::with_activation(
+                8,
+                1,
+                rng.sample_iter(weights),
+                Identity,
+            )),
+        ]);
+        Trainer::new(model, MeanSquaredError, Sgd::new(learning_rate))
+    }
+
+    /// Counts the calls it receives and stops after a given batch.
+    #[derive(Default)]
+    struct Counter {
+        batches: usize,
+        epochs: usize,
+        stop_after_batch: Option<usize>,
+    }
+
+    impl Callback for Counter {
+        fn on_batch_end(
+            &mut self,
+            stats: &BatchStats,
+            _model: &Sequential,
+        ) -> Result<ControlFlow<()>> {
+            self.batches += 1;
+            Ok(if Some(stats.batch) == self.stop_after_batch {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            })
+        }
+
+        fn on_epoch_end(
+            &mut self,
+            _stats: &EpochStats,
+            _model: &Sequential,
+        ) -> Result<ControlFlow<()>> {
+            self.epochs += 1;
+            Ok(ControlFlow::Continue(()))
+        }
+    }
+
+    #[test]
+    fn fit() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let mut train = sine(&mut rng, 256, 32).with_shuffle(1);
+        let validation = sine(&mut rng, 64, 64);
+        let mut trainer = trainer(&mut rng, 0.0).with_scheduler(StepDecay::new(0.2, 0.5, 5));
+
+        let mut counter = Counter::default();
+        let mut checkpoint = ModelCheckpoint::new();
+        let history = trainer
+            .fit(
+                &mut train,
+                Some(&validation),
+                10,
+                &mut [&mut counter, &mut checkpoint],
+            )
+            .unwrap();
+
+        assert_eq!((counter.batches, counter.epochs), (80, 10));
+        assert_eq!(history.len(), 10);
+        assert_eq!(history[4].learning_rate, 0.2);
+        assert_eq!(history[5].learning_rate, 0.1);
+        // Training more continues the schedule.
+        let more = trainer.fit(&mut train, None, 1, &mut []).unwrap();
+        assert_eq!(more[0].learning_rate, 0.05);
+        let first = history[0].validation_loss.unwrap();
+        let last = history[9].validation_loss.unwrap();
+        assert!(last < first / 2.0, "{first} -> {last}");
+
+        // The checkpoint holds the model of the best epoch.
+        let best_epoch = checkpoint.best_epoch().unwrap();
+        let best_loss = history
+            .iter()
+            .map(EpochStats::monitored_loss)
+            .fold(f32::INFINITY, f32::min);
+        assert_eq!(history[best_epoch].monitored_loss(), best_loss);
+        checkpoint
+            .best()
+            .unwrap()
+            .restore(trainer.model_mut())
+            .unwrap();
+        assert_eq!(trainer.evaluate(&validation), best_loss);
+    }
+
+    #[test]
+    fn stopping() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let mut train = sine(&mut rng, 64, 16);
+
+        // Without learning nothing improves after the first epoch.
+        let mut trainer = trainer(&mut rng, 0.0);
+        let mut early_stopping = EarlyStopping::new(3);
+        let history = trainer
+            .fit(&mut train, None, 100, &mut [&mut early_stopping])
+            .unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(early_stopping.best_epoch(), Some(0));
+
+        // A callback can stop in the middle of an epoch; the epoch still ends.
+        let mut counter = Counter {
+            stop_after_batch: Some(1),
+            ..Counter::default()
+        };
+        let history = trainer
+            .fit(&mut train, None, 100, &mut [&mut counter])
+            .unwrap();
+        assert_eq!((history.len(), counter.batches, counter.epochs), (1, 2, 1));
+    }
+
+    /// Records the stats of every batch.
+    #[derive(Default)]
+    struct Recorder(Vec<BatchStats>);
+
+    impl Callback for Recorder {
+        fn on_batch_end(
+            &mut self,
+            stats: &BatchStats,
+            _model: &Sequential,
+        ) -> Result<ControlFlow<()>> {
+            self.0.push(stats.clone());
+            Ok(ControlFlow::Continue(()))
+        }
+    }
+
+    #[test]
+    fn clipping_and_diagnostics() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let mut train = sine(&mut rng, 64, 16);
+        let mut trainer = trainer(&mut rng, 1.0)
+            .with_gradient_clipping(GradientClipping::GlobalNorm(0.01))
+            .with_diagnostics(2);
+        let before = trainer.model().parameters();
+
+        let mut recorder = Recorder::default();
+        trainer
+            .fit(&mut train, None, 1, &mut [&mut recorder])
+            .unwrap();
+
+        // Every step moves the parameters by at most the clipped norm times the learning rate.
+        let after = trainer.model().parameters();
+        assert!(global_norm(&before) > 0.0);
+        let change: Vec<f32> = after.iter().zip(&before).map(|(a, b)| a - b).collect();
+        assert!(global_norm(&change) <= 4.0 * 0.01 + 1e-6);
+        assert!(recorder.0.iter().all(|stats| stats.gradient_norm > 0.01));
+
+        let diagnosed: Vec<bool> = recorder
+            .0
+            .iter()
+            .map(|stats| stats.diagnostics.is_some())
+            .collect();
+        assert_eq!(diagnosed, [true, false, true, false]);
+        let diagnostics = recorder.0[0].diagnostics.as_ref().unwrap();
+        assert_eq!(diagnostics.layers.len(), 2);
+        let gradient_norm = diagnostics
+            .layers
+            .iter()
+            .map(|layer| layer.gradient_norm * layer.gradient_norm)
+            .sum::<f32>()
+            .sqrt();
+        assert!((gradient_norm - 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reproducible() {
+        let run = |master: u64| {
+            let mut seeds = Seeds::new(master);
+            let mut rng = seeds.rng(Seeds::INITIALIZATION);
+            let weights = Uniform::new(-0.5, 0.5);
+            let model = Sequential::new(vec![
+                Box::new(FullyConnectedLayer::new(
+                    1,
+                    8,
+                    (&mut rng).sample_iter(weights),
+                )),
+                Box::new(crate::nn::Dropout::new(8, 0.5, 0)),
+                Box::new(FullyConnectedLayer::with_activation(
+                    8,
+                    1,
+                    (&mut rng).sample_iter(weights),
+                    Identity,
+                )),
+            ]);
+            let mut train =
+                sine(&mut seeds.rng(Seeds::DATA), 64, 16).with_shuffle(seeds.seed(Seeds::SHUFFLE));
+            let mut trainer = Trainer::new(model, MeanSquaredError, Sgd::new(0.1))
+                .with_seed(seeds.seed(Seeds::DROPOUT));
+            let mut checkpoint = ModelCheckpoint::new().with_seeds(&seeds);
+            trainer
+                .fit(&mut train, None, 3, &mut [&mut checkpoint])
+                .unwrap();
+            (trainer.into_model().parameters(), checkpoint)
+        };
+
+        let (parameters, checkpoint) = run(5);
+        assert_eq!(run(5).0, parameters);
+        assert_ne!(run(6).0, parameters);
+
+        let seeds = checkpoint.best().unwrap().seeds.as_ref().unwrap();
+        assert_eq!(seeds.master(), 5);
+        assert_eq!(seeds.streams().count(), 4);
+    }
+
+    #[test]
+    fn anomaly() {
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        // The second batch has a target that is not a number in its 21st sample, in the second shard.
+        let train = sine(&mut rng, 64, 32);
+        let (mut inputs, mut targets) = (Vec::new(), Vec::new());
+        for (batch_inputs, batch_targets) in train.batches() {
+            inputs.extend(batch_inputs);
+            targets.extend(batch_targets);
+        }
+        targets[32 + 20] = f32::NAN;
+        let mut train = DataLoader::new(inputs, 1, targets, 1, 32);
+
+        let directory = std::env::temp_dir().join(format!("nn-anomaly-{}", std::process::id()));
+        let mut trainer = trainer(&mut rng, 0.1).with_anomaly_dump(&directory);
+        let mut counter = Counter::default();
+        let error = trainer
+            .fit(&mut train, None, 1, &mut [&mut counter])
+            .unwrap_err();
+        let error = error.downcast_ref::<TrainingAnomaly>().unwrap();
+        assert_eq!((error.step, error.epoch, error.batch), (1, 0, 1));
+        assert_eq!(error.anomaly.values, crate::anomaly::Values::LossGradients);
+        assert_eq!((error.anomaly.layer, error.anomaly.sample), (1, Some(20)));
+        assert_eq!(counter.batches, 1, "the step stops before the callbacks");
+
+        // The dump holds the parameters the step started from and the batch.
+        let dump = error.dump.as_ref().unwrap();
+        assert_eq!(dump, &directory.join("step-1"));
+        let checkpoint = Checkpoint::load(dump.join("parameters.ckpt")).unwrap();
+        assert_eq!(checkpoint.parameters, trainer.model().parameters());
+        let batch = fs::read_to_string(dump.join("batch.csv")).unwrap();
+        let lines: Vec<&str> = batch.lines().collect();
+        assert_eq!((lines.len(), lines[0]), (33, "input_0,target_0"));
+        assert!(lines[21].ends_with(",NaN"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn anomaly_dump_keeps_state() {
+        #[derive(Default)]
+        struct States(Vec<Vec<f32>>);
+
+        impl Callback for States {
+            fn on_batch_end(
+                &mut self,
+                _stats: &BatchStats,
+                model: &Sequential,
+            ) -> Result<ControlFlow<()>> {
+                self.0.push(model.state());
+                Ok(ControlFlow::Continue(()))
+            }
+        }
+
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let weights = Uniform::new(-0.5, 0.5);
+        let model = Sequential::new(vec![
+            Box::new(FullyConnectedLayer::with_activation(
+                1,
+                4,
+                (&mut rng).sample_iter(weights),
+                Identity,
+            )),
+            Box::new(BatchNorm::new_1d(4)),
+            Box::new(FullyConnectedLayer::with_activation(
+                4,
+                1,
+                (&mut rng).sample_iter(weights),
+                Identity,
+            )),
+        ]);
+        // The second batch has an input that is not a number, which also spoils the running statistics.
+        let train = sine(&mut rng, 64, 32);
+        let (mut inputs, mut targets) = (Vec::new(), Vec::new());
+        for (batch_inputs, batch_targets) in train.batches() {
+            inputs.extend(batch_inputs);
+            targets.extend(batch_targets);
+        }
+        inputs[32 + 5] = f32::NAN;
+        let mut train = DataLoader::new(inputs, 1, targets, 1, 32);
+
+        let directory =
+            std::env::temp_dir().join(format!("nn-anomaly-state-{}", std::process::id()));
+        let mut trainer =
+            Trainer::new(model, MeanSquaredError, Sgd::new(0.1)).with_anomaly_dump(&directory);
+        let mut states = States::default();
+        let error = trainer
+            .fit(&mut train, None, 1, &mut [&mut states])
+            .unwrap_err();
+        let error = error.downcast_ref::<TrainingAnomaly>().unwrap();
+        assert_eq!((error.anomaly.layer, error.step), (0, 1));
+        assert!(trainer.model().state().iter().any(|value| value.is_nan()));
+
+        // The dump holds the state after the first step, not the state the failed step left behind.
+        let checkpoint =
+            Checkpoint::load(error.dump.as_ref().unwrap().join("parameters.ckpt")).unwrap();
+        assert_eq!(checkpoint.state, states.0[0]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}